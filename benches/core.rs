@@ -0,0 +1,118 @@
+#![allow(incomplete_features)]
+#![feature(generic_const_exprs)]
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use r_bustub::buffer::buffer_pool_manager::ParallelBufferPoolManager;
+use r_bustub::buffer::replacer::{LRUReplacer, PageId};
+use r_bustub::container::extendible_hash_table::ExtendibleHashTable;
+use r_bustub::storage::disk::disk_manager::{DiskManager, DiskManagerInstance, PAGE_SIZE};
+use r_bustub::storage::disk::in_memory_disk_manager::InMemoryDiskManager;
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::SeedableRng;
+use std::collections::hash_map::RandomState;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+// The pages are kept in memory, so the buffer pool and hash table benches measure CPU cost only.
+type Bpm = ParallelBufferPoolManager<LRUReplacer, InMemoryDiskManager>;
+
+// about a dozen i32 -> i32 buckets worth, so inserts go through the splits too
+const NUM_KEYS: i32 = 5_000;
+
+fn hash_table_benches(c: &mut Criterion) {
+    c.bench_function("hash_table_insert", |b| {
+        b.iter_custom(|iters| {
+            let mut elapsed = Duration::ZERO;
+            for _ in 0..iters {
+                // buckets never merge, so every round starts from an empty table
+                let bpm = Bpm::new(4, 64, Arc::new(InMemoryDiskManager::new()));
+                let mut table =
+                    ExtendibleHashTable::<_, _, i32, i32, _>::new(&bpm, RandomState::new());
+                let start = Instant::now();
+                for i in 0..NUM_KEYS {
+                    table.insert(&i, &i).unwrap();
+                }
+                elapsed += start.elapsed();
+            }
+            elapsed
+        })
+    });
+
+    let bpm = Bpm::new(4, 64, Arc::new(InMemoryDiskManager::new()));
+    let mut table = ExtendibleHashTable::<_, _, i32, i32, _>::new(&bpm, RandomState::new());
+    for i in 0..NUM_KEYS {
+        table.insert(&i, &i).unwrap();
+    }
+    c.bench_function("hash_table_get_value", |b| {
+        b.iter(|| {
+            for i in 0..NUM_KEYS {
+                black_box(table.get_value(&i).unwrap());
+            }
+        })
+    });
+}
+
+fn buffer_pool_benches(c: &mut Criterion) {
+    let bpm = Bpm::new(1, 16, Arc::new(InMemoryDiskManager::new()));
+    let mut page_ids = Vec::new();
+    for _ in 0..64 {
+        let mut page_id = PageId::default();
+        bpm.new_page(&mut page_id).unwrap();
+        bpm.unpin_page(page_id, true);
+        page_ids.push(page_id);
+    }
+
+    c.bench_function("fetch_page_hit", |b| {
+        let page_id = page_ids[page_ids.len() - 1];
+        b.iter(|| {
+            black_box(bpm.fetch_page(page_id).unwrap());
+            bpm.unpin_page(page_id, false);
+        })
+    });
+
+    // cycling through 4x the pool size evicts every page before it is fetched again
+    c.bench_function("fetch_page_miss", |b| {
+        let mut next = 0;
+        b.iter(|| {
+            let page_id = page_ids[next];
+            next = (next + 1) % page_ids.len();
+            black_box(bpm.fetch_page(page_id).unwrap());
+            bpm.unpin_page(page_id, false);
+        })
+    });
+}
+
+fn disk_manager_benches(c: &mut Criterion) {
+    const NUM_PAGES: u32 = 256;
+    let disk_manager = DiskManagerInstance::new("bench_disk_manager");
+    let page = [7u8; PAGE_SIZE];
+    let sequential: Vec<_> = (0..NUM_PAGES).map(PageId::new).collect();
+    let mut random = sequential.clone();
+    random.shuffle(&mut StdRng::seed_from_u64(0));
+
+    c.bench_function("write_page_sequential", |b| {
+        b.iter(|| {
+            for page_id in sequential.iter() {
+                disk_manager.write_page(*page_id, &page).unwrap();
+            }
+        })
+    });
+    c.bench_function("write_page_random", |b| {
+        b.iter(|| {
+            for page_id in random.iter() {
+                disk_manager.write_page(*page_id, &page).unwrap();
+            }
+        })
+    });
+    drop(disk_manager);
+    std::fs::remove_file("bench_disk_manager.db").unwrap();
+}
+
+criterion_group!(
+    benches,
+    hash_table_benches,
+    buffer_pool_benches,
+    disk_manager_benches
+);
+criterion_main!(benches);
@@ -0,0 +1,73 @@
+use std::hint;
+use std::thread;
+use std::time::Duration;
+
+// Calls that busy-wait with spin_loop, 2^step spins each.
+pub const SPIN_STEPS: u32 = 6;
+// Calls after the spinning that give up the time slice.
+pub const YIELD_STEPS: u32 = 4;
+// After that the sleeps double from MIN_SLEEP up to MAX_SLEEP.
+pub const MIN_SLEEP: Duration = Duration::from_micros(50);
+pub const MAX_SLEEP: Duration = Duration::from_millis(1);
+
+// Waiting for something another thread is about to release, e.g. a frame. Short waits end
+// after a few spins without giving up the core, long ones end up sleeping instead of burning
+// it. Use a fresh one per wait.
+#[derive(Debug, Default)]
+pub struct Backoff {
+    step: u32,
+}
+
+impl Backoff {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Waits a bit longer than the previous call.
+    pub fn snooze(&mut self) {
+        if self.step < SPIN_STEPS {
+            for _ in 0..1 << self.step {
+                hint::spin_loop();
+            }
+        } else if self.step < SPIN_STEPS + YIELD_STEPS {
+            thread::yield_now();
+        } else {
+            thread::sleep(self.next_sleep());
+        }
+        // stop counting once the sleeps are capped
+        if self.next_sleep() < MAX_SLEEP {
+            self.step += 1;
+        }
+    }
+
+    // How long the next snooze sleeps, zero while it would still spin or yield.
+    pub fn next_sleep(&self) -> Duration {
+        match self.step.checked_sub(SPIN_STEPS + YIELD_STEPS) {
+            None => Duration::ZERO,
+            Some(doublings) => MIN_SLEEP
+                .saturating_mul(1 << doublings.min(16))
+                .min(MAX_SLEEP),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn backoff_test() {
+        let mut backoff = Backoff::new();
+        for _ in 0..SPIN_STEPS + YIELD_STEPS {
+            assert_eq!(backoff.next_sleep(), Duration::ZERO);
+            backoff.snooze();
+        }
+        assert_eq!(backoff.next_sleep(), MIN_SLEEP);
+        backoff.snooze();
+        assert_eq!(backoff.next_sleep(), MIN_SLEEP * 2);
+        for _ in 0..10 {
+            backoff.snooze();
+        }
+        assert_eq!(backoff.next_sleep(), MAX_SLEEP);
+    }
+}
@@ -1,284 +1,2958 @@
+use crate::buffer::backoff::Backoff;
+use crate::buffer::frame_arena::{FrameArena, FrameReadGuard, FrameWriteGuard};
+use crate::buffer::page_event_listener::PageEventListener;
+use crate::buffer::page_id_allocator::{PageIdAllocator, Striding};
+use crate::buffer::page_router::{Modulo, PageRouter};
 use crate::buffer::replacer::{FrameId, LRUReplacer, PageId, Replacer};
-use crate::storage::disk::disk_manager::{DiskManager, DiskManagerInstance};
-use crate::storage::pages::page::{Data, Page};
+use crate::metrics::Metrics;
+use crate::recovery::log_record::Lsn;
+use crate::storage::disk::disk_manager::{DiskManager, DiskManagerInstance, PAGE_SIZE};
+use crate::storage::pages::page::{Data, InitPage, Page};
+use bytemuck::{cast_mut, cast_ref, Pod};
 use libc::free;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::fmt::{Debug, Formatter};
+use std::io;
+use std::mem::size_of;
 use std::ops::{Deref, DerefMut};
+use std::path::Path;
 use std::slice::IterMut;
 use std::sync::atomic::{AtomicUsize, Ordering};
-use std::sync::{Arc, Mutex, RwLock};
+use std::sync::{Arc, Mutex, MutexGuard, RwLock, TryLockError};
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InstanceLoad {
+    pub resident_pages: usize,
+    pub free_frames: usize,
+    pub pinned_frames: usize,
+    // number of times a caller found the instance lock already held
+    pub lock_contention: usize,
+}
+
+// Bytes of memory held by a buffer pool, estimated from the capacity of its structures.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct MemoryReport {
+    // the frames' page data
+    pub page_buffers: usize,
+    // the Page structs around the buffers
+    pub frames: usize,
+    pub page_table: usize,
+    pub free_list: usize,
+    pub replacer: usize,
+    // ids of deleted pages waiting to be reused
+    pub free_page_ids: usize,
+}
+
+impl MemoryReport {
+    // everything but the page buffers
+    pub fn overhead(&self) -> usize {
+        self.frames + self.page_table + self.free_list + self.replacer + self.free_page_ids
+    }
+
+    pub fn total(&self) -> usize {
+        self.page_buffers + self.overhead()
+    }
+}
+
+// Counters of a buffer pool since it was created.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct BpmStats {
+    // fetches that found the page resident
+    pub hits: usize,
+    // fetches that had to read the page from disk
+    pub misses: usize,
+    // pages written back, on eviction or flush
+    pub flushes: usize,
+}
+
+// Why a new page could not be allocated.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PoolPressure {
+    // every frame is pinned, retrying is pointless until a page is unpinned
+    AllPinned,
+    // some instance was locked by another thread and skipped, a retry may succeed
+    TransientContention,
+    // the pool was opened read-only
+    ReadOnly,
+}
+
+// new_page_blocking_timeout gave up, every frame stayed pinned until the deadline.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PoolExhausted;
+
+#[derive(Debug)]
+pub enum BufferError {
+    // every frame is pinned, or one that had to be freed is
+    NoFreeFrame,
+    // the disk manager failed, the pages involved are left as they were
+    Io(io::Error),
+    // the operation would write, but the pool was opened read-only
+    ReadOnly,
+    // the page is still in use, so it can't be deleted
+    Pinned,
+}
+
+impl From<io::Error> for BufferError {
+    fn from(err: io::Error) -> Self {
+        BufferError::Io(err)
+    }
+}
 
 pub struct BufferPoolManager<R: Replacer, D: DiskManager> {
     pool_size: usize,
     num_instances: usize,
     instance_index: usize,
-    deleted_page_ids: Vec<u32>,
-    next_page_id: u32,
+    allocator: Arc<dyn PageIdAllocator>,
+    router: Arc<dyn PageRouter>,
     replacer: R,
     frames: Vec<Page>,
     page_table: HashMap<PageId, FrameId>,
+    // resident pages whose frame is dirty, so flushing skips the clean ones
+    dirty: HashSet<PageId>,
     free_list: Vec<FrameId>,
     disk_manager: Arc<D>,
+    // fetches that found the page resident / had to read it
+    hits: usize,
+    misses: usize,
+    evictions: usize,
+    // pages written back, on eviction or flush
+    flushes: usize,
+    listener: Option<Arc<dyn PageEventListener>>,
+    // new and deleted pages and dirty evictions are refused
+    read_only: bool,
+    // run check_invariants after every operation that changes the pool, in debug builds
+    invariant_checks: bool,
+    // where the buffers of frames live
+    arena: Arc<FrameArena>,
 }
 
 
 impl<R: Replacer, D: DiskManager> BufferPoolManager<R, D> {
+    // A standalone pool, for callers that don't need ParallelBufferPoolManager's sharding.
+    pub fn single(pool_size: usize, disk_manager: Arc<D>) -> Self {
+        Self::new(
+            pool_size,
+            1,
+            0,
+            disk_manager,
+            Arc::new(Striding::default()),
+            Arc::new(Modulo),
+        )
+    }
+
     fn new(
         pool_size: usize,
         num_instances: usize,
         instance_index: usize,
         disk_manager: Arc<D>,
+        allocator: Arc<dyn PageIdAllocator>,
+        router: Arc<dyn PageRouter>,
     ) -> Self {
-        let next_page_id = instance_index as u32;
         let replacer = R::new(pool_size);
-        let frames = vec![Page::new(); pool_size];
+        let arena = FrameArena::new(pool_size);
+        let frames = (0..pool_size).map(|i| Page::in_arena(&arena, i)).collect();
         let page_table = HashMap::new();
-        let deleted_page_ids = Vec::new();
         let free_list = (0..pool_size).map(FrameId).collect();
         BufferPoolManager {
             pool_size,
             num_instances,
             instance_index,
-            next_page_id,
+            allocator,
+            router,
             replacer,
             frames,
             page_table,
+            dirty: HashSet::new(),
             free_list,
             disk_manager,
-            deleted_page_ids,
+            hits: 0,
+            misses: 0,
+            evictions: 0,
+            flushes: 0,
+            listener: None,
+            read_only: false,
+            invariant_checks: false,
+            arena,
+        }
+    }
+
+    pub fn set_listener(&mut self, listener: Option<Arc<dyn PageEventListener>>) {
+        self.listener = listener;
+    }
+
+    pub fn set_invariant_checks(&mut self, invariant_checks: bool) {
+        self.invariant_checks = invariant_checks;
+    }
+
+    // Panics unless no two pages share a frame, every page sits in the frame the page table
+    // says, free frames hold no page, the dirty set lists exactly the dirty pages and no pinned
+    // frame is evictable. The last part only covers replacers that can peek.
+    pub fn check_invariants(&self) {
+        let mut used_frames = HashMap::new();
+        for (page_id, frame_id) in &self.page_table {
+            if let Some(other) = used_frames.insert(frame_id.0, *page_id) {
+                panic!("{:?} and {:?} both map to {:?}", other, page_id, frame_id);
+            }
+        }
+        for (page_id, frame_id) in &self.page_table {
+            assert_eq!(
+                self.frames[frame_id.0].get_page_id(),
+                Some(*page_id),
+                "{:?} maps to {:?}, which holds another page",
+                page_id,
+                frame_id
+            );
         }
+        for frame_id in &self.free_list {
+            assert!(
+                !used_frames.contains_key(&frame_id.0),
+                "free {:?} holds {:?}",
+                frame_id,
+                used_frames[&frame_id.0]
+            );
+            let page = &self.frames[frame_id.0];
+            assert!(
+                page.get_page_id().is_none() && !page.is_dirty(),
+                "free {:?} still has the metadata of {:?}",
+                frame_id,
+                page.get_page_id()
+            );
+        }
+        for (page_id, frame_id) in &self.page_table {
+            assert_eq!(
+                self.frames[frame_id.0].is_dirty(),
+                self.dirty.contains(page_id),
+                "the dirty set is wrong about {:?}",
+                page_id
+            );
+        }
+        assert!(self.dirty.len() <= self.page_table.len(), "the dirty set holds evicted pages");
+        for frame_id in self.replacer.snapshot() {
+            assert_eq!(
+                self.frames[frame_id.0].get_pin_count(),
+                0,
+                "pinned frame {:?} is in the replacer",
+                frame_id
+            );
+        }
+    }
+
+    fn maybe_check_invariants(&self) {
+        if cfg!(debug_assertions) && self.invariant_checks {
+            self.check_invariants();
+        }
+    }
+
+    fn notify(&self, event: impl FnOnce(&dyn PageEventListener)) {
+        if let Some(listener) = &self.listener {
+            event(&**listener);
+        }
+    }
+
+    // Swaps in another replacer, handing it the evictable frames in the order the old one
+    // would have evicted them. Pinned frames reach the new replacer when they are unpinned.
+    pub fn set_replacer(&mut self, mut replacer: R) {
+        while let Some(frame_id) = self.replacer.victim() {
+            replacer.unpin(frame_id);
+        }
+        self.replacer = replacer;
     }
 
-    fn alloc_frame(&mut self) -> Option<FrameId> {
+    // Frees up a frame, writing back the page evicted from it if dirty. If the write fails the
+    // victim stays resident and dirty.
+    fn alloc_frame(&mut self) -> Result<FrameId, BufferError> {
         if let Some(frame_id) = self.free_list.pop() {
-            Some(frame_id)
-        } else {
-            self.replacer.victim()
+            return Ok(frame_id);
+        }
+        let frame_id = self.replacer.victim().ok_or(BufferError::NoFreeFrame)?;
+        let victim_page = &mut self.frames[frame_id.0];
+        let victim_page_id = victim_page.get_page_id().unwrap();
+        if victim_page.is_dirty() {
+            if self.read_only {
+                self.replacer.unpin(frame_id);
+                return Err(BufferError::ReadOnly);
+            }
+            if let Err(err) = Self::write_back(&*self.disk_manager, victim_page) {
+                self.replacer.unpin(frame_id);
+                return Err(err.into());
+            }
+            victim_page.set_is_dirty(false);
+            self.dirty.remove(&victim_page_id);
+            self.flushes += 1;
         }
+        self.page_table.remove(&victim_page_id);
+        self.evictions += 1;
+        self.notify(|listener| listener.on_evict(victim_page_id));
+        Ok(frame_id)
+    }
+
+    // Freed ids that new pages of this instance may reuse.
+    pub fn free_page_ids(&self) -> Vec<PageId> {
+        self.allocator
+            .free_page_ids()
+            .into_iter()
+            .filter(|page_id| {
+                self.router.route(*page_id, self.num_instances) == self.instance_index
+            })
+            .collect()
     }
 
     fn alloc_page_id(&mut self) -> PageId {
-        if let Some(page_id) = self.deleted_page_ids.pop() {
-            PageId(page_id)
-        } else {
-            let page_id = self.next_page_id;
-            self.next_page_id += self.num_instances as u32;
-            PageId(page_id)
-        }
+        self.allocator
+            .allocate(self.instance_index, self.num_instances)
     }
 
-    fn fetch_page(&mut self, page_id: PageId) -> Option<Data> {
-        if let Some(frame_id) = self.page_table.get(&page_id) {
-            let mut page = &mut self.frames[frame_id.0];
-            self.replacer.pin(*frame_id);
-            page.increase_pin_count();
-            Some(page.get_data())
+    pub fn fetch_page(&mut self, page_id: PageId) -> Option<Data> {
+        self.try_fetch_page(page_id).ok()
+    }
+
+    pub fn try_fetch_page(&mut self, page_id: PageId) -> Result<Data, BufferError> {
+        if let Some(frame_id) = self.page_table.get(&page_id).copied() {
+            self.hits += 1;
+            // leave the replacer before the data is handed out
+            if self.frames[frame_id.0].get_pin_count() == 0 {
+                self.replacer.pin(frame_id);
+            }
+            self.frames[frame_id.0].increase_pin_count();
+            self.check_pinned_not_evictable(frame_id);
+            self.maybe_check_invariants();
+            self.notify(|listener| listener.on_fetch(page_id));
+            Ok(self.frames[frame_id.0].get_data())
         } else {
-            let victim_frame_id = self.replacer.victim()?;
-            self.replacer.pin(victim_frame_id);
+            self.misses += 1;
+            let victim_frame_id = self.alloc_frame()?;
             let victim_page = &mut self.frames[victim_frame_id.0];
-            if victim_page.is_dirty() {
-                self.disk_manager
-                    .write_page(victim_page.get_page_id().unwrap(), &(*victim_page.get_data().read().unwrap()).0);
+            let data = victim_page.get_data();
+            if let Err(err) = self
+                .disk_manager
+                .read_page(page_id, &mut data.write().unwrap().0)
+            {
+                victim_page.reset();
+                self.free_list.push(victim_frame_id);
+                return Err(err.into());
             }
-            self.page_table.remove(&victim_page.get_page_id().unwrap());
+            self.replacer.pin(victim_frame_id);
+            self.replacer.loaded(victim_frame_id, page_id);
             self.page_table.insert(page_id, victim_frame_id);
             victim_page.set_pin_count(1);
             victim_page.set_is_dirty(false);
             victim_page.set_page_id(page_id);
-            self.disk_manager
-                .read_page(page_id, &mut (*victim_page.get_data().write().unwrap()).0);
-            Some(victim_page.get_data())
+            self.check_pinned_not_evictable(victim_frame_id);
+            self.maybe_check_invariants();
+            self.notify(|listener| listener.on_fetch(page_id));
+            Ok(data)
         }
     }
 
-    fn unpin_page(&mut self, page_id: PageId, is_dirty: bool) {
-        let frame_id = self.page_table.get(&page_id).unwrap();
+    // Returns false, changing nothing, if the page isn't resident or isn't pinned, e.g. after
+    // unpinning it once too often.
+    pub fn unpin_page(&mut self, page_id: PageId, is_dirty: bool) -> bool {
+        let Some(frame_id) = self.page_table.get(&page_id).copied() else {
+            return false;
+        };
         let page = &mut self.frames[frame_id.0];
+        if page.get_pin_count() == 0 {
+            return false;
+        }
         page.decrease_pin_count();
         if page.get_pin_count() == 0 {
-            self.replacer.unpin(*frame_id);
+            self.replacer.unpin(frame_id);
+        }
+        if is_dirty {
+            page.set_is_dirty(true);
+            self.dirty.insert(page_id);
         }
-        if !page.is_dirty() {
-            page.set_is_dirty(is_dirty);
+        self.check_pinned_not_evictable(frame_id);
+        self.maybe_check_invariants();
+        true
+    }
+
+    // A frame somebody has pinned must never be handed out as a victim. Only checked in debug
+    // builds, as it walks the replacer.
+    fn check_pinned_not_evictable(&self, frame_id: FrameId) {
+        if cfg!(debug_assertions) && self.frames[frame_id.0].get_pin_count() > 0 {
+            assert!(
+                !self.replacer.peek_victims(self.replacer.size()).contains(&frame_id),
+                "pinned frame {:?} is in the replacer",
+                frame_id
+            );
         }
     }
 
-    fn flush_page(&mut self, page_id: PageId) {
-        let frame_id = self.page_table.get(&page_id).unwrap();
-        let page = &self.frames[frame_id.0];
+    // Pages that aren't resident have nothing to flush.
+    pub fn flush_page(&mut self, page_id: PageId) -> io::Result<()> {
+        let Some(frame_id) = self.page_table.get(&page_id) else {
+            return Ok(());
+        };
+        let page = &mut self.frames[frame_id.0];
         if page.is_dirty() {
-            self.disk_manager.write_page(page_id, &(*page.get_data().read().unwrap()).0);
+            Self::write_back(&*self.disk_manager, page)?;
+            page.set_is_dirty(false);
+            self.dirty.remove(&page_id);
+            self.flushes += 1;
+            self.notify(|listener| listener.on_flush(page_id));
         }
+        Ok(())
     }
 
-    fn new_page(&mut self, page_id: &mut PageId) -> Option<Data> {
-        let victim_frame_id = self.alloc_frame()?;
-        let new_page_id = self.alloc_page_id();
-        let mut victim_page = &mut self.frames[victim_frame_id.0];
-        if victim_page.is_dirty() {
-            self.disk_manager
-                .write_page(victim_page.get_page_id().unwrap(), &(*victim_page.get_data().read().unwrap()).0);
+    // Writes data as the content of the page and marks the page clean, if it is resident. For
+    // a caller holding the page's write latch, which flush_page would wait for, so data has to
+    // be what the page holds once the latch is released.
+    pub fn write_through(&mut self, page_id: PageId, data: &[u8; PAGE_SIZE]) -> io::Result<()> {
+        let Some(frame_id) = self.page_table.get(&page_id).copied() else {
+            return Ok(());
+        };
+        self.disk_manager.write_page(page_id, data)?;
+        self.frames[frame_id.0].set_is_dirty(false);
+        self.dirty.remove(&page_id);
+        self.flushes += 1;
+        self.notify(|listener| listener.on_flush(page_id));
+        Ok(())
+    }
+
+    // Writes only the dirty range if it covers less than half the page.
+    fn write_back(disk_manager: &D, page: &Page) -> io::Result<()> {
+        let page_id = page.get_page_id().unwrap();
+        let data = page.get_data();
+        let data = data.read().unwrap();
+        match page.get_dirty_range() {
+            Some(range) if range.len() < PAGE_SIZE / 2 => {
+                disk_manager.write_range(page_id, &data.0, range)
+            }
+            _ => disk_manager.write_page(page_id, &data.0),
+        }
+    }
+
+    // Writes the page, reads it back, and only marks it clean if the disk returned the same
+    // bytes. Returns whether the page is known to be on disk. Costs a read per call.
+    pub fn flush_and_verify(&mut self, page_id: PageId) -> bool {
+        let Some(frame_id) = self.page_table.get(&page_id) else {
+            return false;
+        };
+        let page = &mut self.frames[frame_id.0];
+        let data = page.get_data();
+        let data = data.read().unwrap();
+        let mut buf = [0u8; PAGE_SIZE];
+        let verified = self.disk_manager.write_page(page_id, &data.0).is_ok()
+            && self.disk_manager.read_page(page_id, &mut buf).is_ok()
+            && buf == data.0;
+        if verified {
+            page.set_is_dirty(false);
+            self.dirty.remove(&page_id);
+            self.flushes += 1;
+            self.notify(|listener| listener.on_flush(page_id));
+        }
+        verified
+    }
+
+    pub fn new_page(&mut self, page_id: &mut PageId) -> Option<Data> {
+        if self.read_only {
+            return None;
         }
-        if let Some(victim_page_id) = victim_page
-            .get_page_id() {
-            self.page_table.remove(&victim_page_id);
+        let new_page_id = self.alloc_page_id();
+        let page = self.new_page_with_id(new_page_id)?;
+        *page_id = new_page_id;
+        Some(page)
+    }
+
+    // Gives new_page_id back to the allocator if no frame is available.
+    fn new_page_with_id(&mut self, new_page_id: PageId) -> Option<Data> {
+        if self.read_only {
+            self.allocator.deallocate(new_page_id);
+            return None;
         }
+        let Ok(victim_frame_id) = self.alloc_frame() else {
+            self.allocator.deallocate(new_page_id);
+            return None;
+        };
+        let mut victim_page = &mut self.frames[victim_frame_id.0];
         self.page_table.insert(new_page_id, victim_frame_id);
         victim_page.set_page_id(new_page_id);
         victim_page.set_is_dirty(true);
+        self.dirty.insert(new_page_id);
         victim_page.set_pin_count(1);
         victim_page.reset_data();
         self.replacer.pin(victim_frame_id);
+        self.replacer.loaded(victim_frame_id, new_page_id);
+        self.check_pinned_not_evictable(victim_frame_id);
+        self.maybe_check_invariants();
+        self.notify(|listener| listener.on_new(new_page_id));
+        Some(self.frames[victim_frame_id.0].get_data())
+    }
 
-        *page_id = new_page_id;
-        Some(victim_page.get_data())
+    // Returns whether the page was resident. Its frame is freed without writing it back. Fails
+    // with Pinned while someone holds a pin on it.
+    pub fn delete_page(&mut self, page_id: PageId) -> Result<bool, BufferError> {
+        if self.read_only {
+            return Err(BufferError::ReadOnly);
+        }
+        let Some(frame_id) = self.page_table.get(&page_id).copied() else {
+            return Ok(false);
+        };
+        if self.frames[frame_id.0].get_pin_count() > 0 {
+            return Err(BufferError::Pinned);
+        }
+        // an unpinned page is still a victim candidate
+        let evictable = self.replacer.remove(frame_id);
+        debug_assert!(evictable, "unpinned frame {:?} was not evictable", frame_id);
+        // a dirty frame left as it was would still be flushed under the deleted id
+        self.frames[frame_id.0].reset();
+        self.dirty.remove(&page_id);
+        self.free_list.push(frame_id);
+        self.page_table.remove(&page_id);
+        self.allocator.deallocate(page_id);
+        self.maybe_check_invariants();
+        self.notify(|listener| listener.on_delete(page_id));
+        Ok(true)
     }
 
-    fn delete_page(&mut self, page_id: PageId) {
-        if let Some(frame_id) = self.page_table.get(&page_id) {
-            if self.frames[frame_id.0].get_pin_count() > 0 {
-                panic!(
-                    "Attempt to delete a page with pin count > 0"
-                );
-            }
-            self.free_list.push(*frame_id);
-            self.page_table.remove(&page_id);
-            self.deleted_page_ids.push(page_id.0);
+    // Writes the page back if needed and drops it from the pool. Pinned pages, and dirty ones
+    // in a read-only pool, are left alone.
+    fn evict_page(&mut self, page_id: PageId) -> io::Result<bool> {
+        let Some(frame_id) = self.page_table.get(&page_id).copied() else {
+            return Ok(false);
+        };
+        let page = &mut self.frames[frame_id.0];
+        if page.get_pin_count() > 0 || (page.is_dirty() && self.read_only) {
+            return Ok(false);
         }
+        if page.is_dirty() {
+            Self::write_back(&*self.disk_manager, page)?;
+            self.flushes += 1;
+        }
+        page.reset();
+        self.dirty.remove(&page_id);
+        self.replacer.remove(frame_id);
+        self.page_table.remove(&page_id);
+        self.free_list.push(frame_id);
+        self.maybe_check_invariants();
+        self.notify(|listener| listener.on_evict(page_id));
+        Ok(true)
     }
 
-    fn flush_all_pages(&mut self) {
-        for page in self.frames.iter() {
-            if page.is_dirty() {
-                self.disk_manager
-                    .write_page(page.get_page_id().unwrap(), &(*page.get_data().read().unwrap()).0);
+    // Grows or shrinks the pool to new_pool_size frames. Shrinking drops the last frames,
+    // evicting their pages and writing back the dirty ones, and fails without resizing if one
+    // of them is pinned. Pages evicted before a write failure stay evicted. The buffers of
+    // dropped frames stay allocated, growing again reuses them.
+    pub fn resize(&mut self, new_pool_size: usize) -> Result<(), BufferError> {
+        let old_pool_size = self.pool_size;
+        if new_pool_size < old_pool_size {
+            let dropped = &self.frames[new_pool_size..];
+            if dropped.iter().any(|page| page.get_pin_count() > 0) {
+                return Err(BufferError::NoFreeFrame);
+            }
+            if self.read_only && dropped.iter().any(Page::is_dirty) {
+                return Err(BufferError::ReadOnly);
+            }
+            let page_ids: Vec<_> = dropped.iter().filter_map(Page::get_page_id).collect();
+            for page_id in page_ids {
+                self.evict_page(page_id)?;
             }
+            self.free_list.retain(|frame_id| frame_id.0 < new_pool_size);
+            self.frames.truncate(new_pool_size);
+        } else {
+            if self.arena.len() < new_pool_size {
+                self.arena.grow(new_pool_size - self.arena.len());
+            }
+            let arena = &self.arena;
+            self.frames
+                .extend((old_pool_size..new_pool_size).map(|i| Page::in_arena(arena, i)));
+            self.free_list.extend((old_pool_size..new_pool_size).map(FrameId));
+        }
+        self.replacer.resize(new_pool_size);
+        self.pool_size = new_pool_size;
+        self.maybe_check_invariants();
+        Ok(())
+    }
+
+    pub fn pin_count(&self, page_id: PageId) -> Option<usize> {
+        let frame_id = self.page_table.get(&page_id)?;
+        Some(self.frames[frame_id.0].get_pin_count())
+    }
+
+    pub fn is_resident(&self, page_id: PageId) -> bool {
+        self.page_table.contains_key(&page_id)
+    }
+
+    pub fn stats(&self) -> BpmStats {
+        BpmStats {
+            hits: self.hits,
+            misses: self.misses,
+            flushes: self.flushes,
+        }
+    }
+
+    // Writes every dirty page, in lsn order like ParallelBufferPoolManager::flush_all_pages.
+    pub fn flush_all_pages(&mut self) -> io::Result<()> {
+        let mut dirty_pages = self.dirty_pages();
+        dirty_pages.sort_by_key(|(lsn, _)| *lsn);
+        for (_, page_id) in dirty_pages {
+            self.flush_page(page_id)?;
+        }
+        Ok(())
+    }
+
+    fn load(&self, lock_contention: usize) -> InstanceLoad {
+        InstanceLoad {
+            resident_pages: self.page_table.len(),
+            free_frames: self.free_list.len(),
+            pinned_frames: self
+                .frames
+                .iter()
+                .filter(|page| page.get_pin_count() > 0)
+                .count(),
+            lock_contention,
+        }
+    }
+
+    // Doesn't count the shared allocator.
+    fn memory_usage(&self) -> MemoryReport {
+        MemoryReport {
+            // dropped frames keep their buffers
+            page_buffers: self.arena.len() * PAGE_SIZE,
+            frames: self.frames.capacity() * size_of::<Page>(),
+            // plus one control byte per bucket
+            page_table: self.page_table.capacity() * (size_of::<(PageId, FrameId)>() + 1),
+            free_list: self.free_list.capacity() * size_of::<FrameId>(),
+            replacer: self.replacer.memory_usage(),
+            free_page_ids: 0,
+        }
+    }
+
+    fn set_page_lsn(&mut self, page_id: PageId, lsn: Lsn) {
+        if let Some(frame_id) = self.page_table.get(&page_id) {
+            self.frames[frame_id.0].set_lsn(lsn);
+        }
+    }
+
+    fn dirty_pages(&self) -> Vec<(Option<Lsn>, PageId)> {
+        self.dirty
+            .iter()
+            .map(|page_id| (self.frames[self.page_table[page_id].0].get_lsn(), *page_id))
+            .collect()
+    }
+
+    // Only bytes lo..hi of the pinned page changed. Returns false, changing nothing, if the
+    // page isn't resident or isn't pinned, like unpin_page.
+    fn mark_dirty_range(&mut self, page_id: PageId, lo: usize, hi: usize) -> bool {
+        let Some(frame_id) = self.page_table.get(&page_id).copied() else {
+            return false;
+        };
+        let page = &mut self.frames[frame_id.0];
+        if page.get_pin_count() == 0 {
+            return false;
         }
+        page.mark_dirty_range(lo, hi);
+        self.dirty.insert(page_id);
+        true
+    }
+}
+
+// A pinned page that unpins itself when dropped, dirty if mark_dirty was called.
+pub struct PageGuard<'a, R: Replacer, D: DiskManager> {
+    bpm: &'a ParallelBufferPoolManager<R, D>,
+    page_id: PageId,
+    data: Data,
+    is_dirty: bool,
+}
+
+impl<'a, R: Replacer, D: DiskManager> PageGuard<'a, R, D> {
+    pub fn page_id(&self) -> PageId {
+        self.page_id
+    }
+
+    pub fn mark_dirty(&mut self) {
+        self.is_dirty = true;
+    }
+
+    fn arena(&self) -> &'a FrameArena {
+        let bpm = self.bpm;
+        &bpm.arenas[bpm.route(self.page_id)]
+    }
+
+    // Takes the read lock, held until the returned guard is dropped.
+    pub fn into_read(self) -> ReadPageGuard<'a, R, D> {
+        let lock = self.arena().read(self.data.index()).unwrap();
+        ReadPageGuard { lock, page: self }
+    }
+
+    // Takes the write lock. The page is unpinned dirty.
+    pub fn into_write(mut self) -> WritePageGuard<'a, R, D> {
+        self.mark_dirty();
+        let lock = self.arena().write(self.data.index()).unwrap();
+        WritePageGuard { lock, page: self }
+    }
+}
+
+// A pinned page with its read lock held. The lock goes before the pin.
+pub struct ReadPageGuard<'a, R: Replacer, D: DiskManager> {
+    lock: FrameReadGuard<'a>,
+    page: PageGuard<'a, R, D>,
+}
+
+impl<'a, R: Replacer, D: DiskManager> ReadPageGuard<'a, R, D> {
+    pub fn page_id(&self) -> PageId {
+        self.page.page_id
+    }
+
+    // The page seen as a T, e.g. a HashTableDirectoryPage. Takes T as a parameter, unlike
+    // AsRef::as_ref.
+    #[allow(clippy::should_implement_trait)]
+    pub fn as_ref<T: Pod>(&self) -> &T {
+        cast_ref(&**self.lock)
+    }
+}
+
+// A pinned page with its write lock held, unpinned dirty. The lock goes before the pin.
+pub struct WritePageGuard<'a, R: Replacer, D: DiskManager> {
+    lock: FrameWriteGuard<'a>,
+    page: PageGuard<'a, R, D>,
+}
+
+impl<'a, R: Replacer, D: DiskManager> WritePageGuard<'a, R, D> {
+    pub fn page_id(&self) -> PageId {
+        self.page.page_id
+    }
+
+    #[allow(clippy::should_implement_trait)]
+    pub fn as_ref<T: Pod>(&self) -> &T {
+        cast_ref(&**self.lock)
+    }
+
+    #[allow(clippy::should_implement_trait)]
+    pub fn as_mut<T: Pod>(&mut self) -> &mut T {
+        cast_mut(&mut **self.lock)
+    }
+}
+
+impl<'a, R: Replacer, D: DiskManager> Deref for PageGuard<'a, R, D> {
+    type Target = Data;
+
+    fn deref(&self) -> &Self::Target {
+        &self.data
+    }
+}
+
+impl<'a, R: Replacer, D: DiskManager> Drop for PageGuard<'a, R, D> {
+    fn drop(&mut self) {
+        self.bpm.unpin_page(self.page_id, self.is_dirty);
+    }
+}
+
+// Every instance lock of a pool, for maintenance that needs the whole pool to itself. The
+// locks are released when the guard is dropped.
+pub struct MaintenanceGuard<'a, R: Replacer, D: DiskManager> {
+    instances: Vec<MutexGuard<'a, BufferPoolManager<R, D>>>,
+}
+
+impl<'a, R: Replacer, D: DiskManager> MaintenanceGuard<'a, R, D> {
+    pub fn num_instances(&self) -> usize {
+        self.instances.len()
+    }
+
+    pub fn instance(&mut self, index: usize) -> &mut BufferPoolManager<R, D> {
+        &mut self.instances[index]
     }
 }
 
 pub struct ParallelBufferPoolManager<R: Replacer, D: DiskManager> {
     num_instances: usize,
-    pool_size: usize,
     instances: Vec<Arc<Mutex<BufferPoolManager<R, D>>>>,
+    // the arena of each instance, reachable without its lock so page guards can borrow it
+    arenas: Vec<Arc<FrameArena>>,
     start_index: AtomicUsize,
+    lock_contention: Vec<AtomicUsize>,
+    allocator: Arc<dyn PageIdAllocator>,
+    router: Arc<dyn PageRouter>,
+    read_only: bool,
 }
 
-impl<'a, R: Replacer, D: DiskManager> ParallelBufferPoolManager<R, D> {
+impl<R: Replacer, D: DiskManager> ParallelBufferPoolManager<R, D> {
+    // pool_size is the number of frames of each instance, so the pool holds
+    // num_instances * pool_size pages in total. See new_total for sizing the whole pool.
     pub fn new(num_instances: usize, pool_size: usize, disk_manager: Arc<D>) -> Self {
+        Self::with_allocator(
+            num_instances,
+            pool_size,
+            disk_manager,
+            Arc::new(Striding::default()),
+        )
+    }
+
+    pub fn with_allocator(
+        num_instances: usize,
+        pool_size: usize,
+        disk_manager: Arc<D>,
+        allocator: Arc<dyn PageIdAllocator>,
+    ) -> Self {
+        assert!(num_instances > 0, "a buffer pool needs at least one instance");
+        Self::with_instance_sizes(
+            vec![pool_size; num_instances],
+            disk_manager,
+            allocator,
+            Arc::new(Modulo),
+        )
+    }
+
+    // Places pages by router instead of by page_id % num_instances. Striding still allocates
+    // the ids, wherever they end up.
+    pub fn with_router(
+        num_instances: usize,
+        pool_size: usize,
+        disk_manager: Arc<D>,
+        router: Arc<dyn PageRouter>,
+    ) -> Self {
+        assert!(num_instances > 0, "a buffer pool needs at least one instance");
+        Self::with_instance_sizes(
+            vec![pool_size; num_instances],
+            disk_manager,
+            Arc::new(Striding::default()),
+            router,
+        )
+    }
+
+    // total_pool_size frames split as evenly as possible, the first instances get one frame more
+    // if it doesn't divide.
+    pub fn new_total(num_instances: usize, total_pool_size: usize, disk_manager: Arc<D>) -> Self {
+        assert!(num_instances > 0, "a buffer pool needs at least one instance");
+        let pool_sizes = (0..num_instances)
+            .map(|i| {
+                total_pool_size / num_instances + usize::from(i < total_pool_size % num_instances)
+            })
+            .collect();
+        Self::with_instance_sizes(
+            pool_sizes,
+            disk_manager,
+            Arc::new(Striding::default()),
+            Arc::new(Modulo),
+        )
+    }
+
+    fn with_instance_sizes(
+        pool_sizes: Vec<usize>,
+        disk_manager: Arc<D>,
+        allocator: Arc<dyn PageIdAllocator>,
+        router: Arc<dyn PageRouter>,
+    ) -> Self {
+        assert!(
+            pool_sizes.iter().all(|pool_size| *pool_size > 0),
+            "a buffer pool instance needs at least one frame"
+        );
+        let num_instances = pool_sizes.len();
         let mut instances = Vec::new();
-        for i in 0..pool_size {
+        for (i, pool_size) in pool_sizes.iter().enumerate() {
             instances.push(Arc::new(Mutex::new(BufferPoolManager::<R, D>::new(
-                pool_size,
+                *pool_size,
                 num_instances,
                 i,
                 disk_manager.clone(),
+                allocator.clone(),
+                router.clone(),
             ))));
         }
         let start_index = AtomicUsize::new(0);
+        let lock_contention = instances.iter().map(|_| AtomicUsize::new(0)).collect();
+        let arenas = instances
+            .iter()
+            .map(|instance| instance.lock().unwrap().arena.clone())
+            .collect();
         Self {
             num_instances,
-            pool_size,
             instances,
+            arenas,
             start_index,
+            lock_contention,
+            allocator,
+            router,
+            read_only: false,
         }
     }
 
-    fn get_instance(&self, page_id: PageId) -> Arc<Mutex<BufferPoolManager<R, D>>> {
-        self.instances[(page_id.0 as usize % self.num_instances)].clone()
+    // For inspecting a db without writing to it, e.g. over DiskManagerInstance::open_readonly.
+    // Creating or deleting pages fails, and so does evicting a page a caller unpinned as dirty.
+    pub fn read_only(num_instances: usize, pool_size: usize, disk_manager: Arc<D>) -> Self {
+        let mut pool = Self::new(num_instances, pool_size, disk_manager);
+        for index in 0..pool.instances.len() {
+            pool.lock_instance(index).read_only = true;
+        }
+        pool.read_only = true;
+        pool
     }
 
-    pub fn fetch_page_run<T>(&self, page_id: PageId, f: impl FnOnce(Data) -> T) -> Option<T> {
-        self.get_instance(page_id)
-            .lock()
-            .unwrap()
-            .fetch_page(page_id)
-            .map(f)
+    // Frames over all instances, which may have been resized through maintenance_lock.
+    pub fn total_pool_size(&self) -> usize {
+        (0..self.instances.len())
+            .map(|index| self.lock_instance(index).pool_size)
+            .sum()
     }
 
-    pub fn unpin_page(&self, page_id: PageId, is_dirty: bool) {
-        self.get_instance(page_id).lock().unwrap().unpin_page(page_id, is_dirty)
+    // The index of the instance the page lives in.
+    fn route(&self, page_id: PageId) -> usize {
+        let index = self.router.route(page_id, self.num_instances);
+        debug_assert!(index < self.num_instances, "{:?} routed to {}", page_id, index);
+        index
     }
 
-    pub fn flush_page(&self, page_id: PageId) {
-        self.get_instance(page_id).lock().unwrap().flush_page(page_id)
+    fn get_instance(&self, page_id: PageId) -> MutexGuard<'_, BufferPoolManager<R, D>> {
+        self.lock_instance(self.route(page_id))
     }
 
-    pub fn new_page_run<T>(&self, page_id: &mut PageId, f: impl FnOnce(Data) -> T) -> Option<T> {
-        let (mut left, mut right) = self.instances.split_at(self.start_index.load(Ordering::Relaxed));
-        let mut iter = right.iter().chain(left).enumerate();
-        self.start_index.fetch_add(1, Ordering::Relaxed);
-        for (i, instance) in iter {
-            if let Some(page) = instance
-                .try_lock()
-                .ok()
-                .as_mut()
-                .and_then(|mut i| i.new_page(page_id))
-            {
-                self.start_index.store(i, Ordering::Relaxed);
-                return Some(f(page));
+    // Registers the pool's listener with every instance, replacing any previous one. None
+    // removes it.
+    pub fn set_listener(&self, listener: Option<Arc<dyn PageEventListener>>) {
+        for index in 0..self.instances.len() {
+            self.lock_instance(index).set_listener(listener.clone());
+        }
+    }
+
+    // Swaps the replacer of every instance, each built from the instance's pool size. The
+    // instances switch one at a time under their own lock.
+    pub fn set_replacer(&self, mut new_replacer: impl FnMut(usize) -> R) {
+        for index in 0..self.instances.len() {
+            let mut instance = self.lock_instance(index);
+            let replacer = new_replacer(instance.pool_size);
+            instance.set_replacer(replacer);
+        }
+    }
+
+    // Locks every instance, always in index order so two maintenance routines can't deadlock.
+    // Blocks until no other thread holds an instance lock. The calling thread must not hold one
+    // itself (e.g. inside a page event listener), or it deadlocks on its own lock.
+    pub fn maintenance_lock(&self) -> MaintenanceGuard<'_, R, D> {
+        MaintenanceGuard {
+            instances: (0..self.instances.len())
+                .map(|index| self.lock_instance(index))
+                .collect(),
+        }
+    }
+
+    // See BufferPoolManager::check_invariants. Only has an effect in debug builds.
+    pub fn set_invariant_checks(&self, invariant_checks: bool) {
+        for index in 0..self.instances.len() {
+            self.lock_instance(index).set_invariant_checks(invariant_checks);
+        }
+    }
+
+    pub fn check_invariants(&self) {
+        for index in 0..self.instances.len() {
+            self.lock_instance(index).check_invariants();
+        }
+    }
+
+    fn lock_instance(&self, index: usize) -> MutexGuard<'_, BufferPoolManager<R, D>> {
+        match self.instances[index].try_lock() {
+            Ok(instance) => instance,
+            Err(TryLockError::WouldBlock) => {
+                self.lock_contention[index].fetch_add(1, Ordering::Relaxed);
+                self.instances[index].lock().unwrap()
             }
+            Err(TryLockError::Poisoned(err)) => panic!("{}", err),
         }
-        None
     }
 
-    pub fn new_page(&self, page_id: &mut PageId) -> Option<Data> {
-        self.get_instance(*page_id).lock().unwrap().new_page(page_id)
+    // Resident pages held by an instance other than the one their id routes to, as
+    // (instance index, page id). Pools created before instances were sized by num_instances
+    // could end up like this.
+    pub fn misrouted_pages(&self) -> Vec<(usize, PageId)> {
+        let mut misrouted = Vec::new();
+        for index in 0..self.instances.len() {
+            let instance = self.lock_instance(index);
+            misrouted.extend(
+                instance
+                    .page_table
+                    .keys()
+                    .filter(|page_id| self.route(**page_id) != index)
+                    .map(|page_id| (index, *page_id)),
+            );
+        }
+        misrouted
     }
 
-    pub fn new_page_blocking(&self, page_id: &mut PageId) -> Data {
-        loop {
-            if let Some(page) = self.new_page(page_id) {
-                return page;
+    // Moves unpinned misrouted pages to the instance their id routes to, by evicting them and
+    // fetching them again. Returns how many pages were moved, or the first error. A page whose
+    // fetch failed was written back when it was evicted, so it is only on disk now and the
+    // pages after it are left where they are.
+    pub fn repair_routing(&self) -> Result<usize, BufferError> {
+        let mut moved = 0;
+        for (index, page_id) in self.misrouted_pages() {
+            if !self.lock_instance(index).evict_page(page_id)? {
+                continue;
             }
-            thread::sleep(Duration::from_millis(1));
+            self.try_fetch_page(page_id)?;
+            self.unpin_page(page_id, false);
+            moved += 1;
         }
+        Ok(moved)
     }
 
-    pub fn fetch_page(&self, page_id: PageId) -> Option<Data> {
-        self.get_instance(page_id).lock().unwrap().fetch_page(page_id)
+    // free_page_ids of each instance, by instance index.
+    pub fn free_page_ids(&self) -> Vec<Vec<PageId>> {
+        (0..self.instances.len())
+            .map(|index| self.lock_instance(index).free_page_ids())
+            .collect()
     }
 
-    pub fn delete_page(&self, page_id: PageId) {
-        self.get_instance(page_id).lock().unwrap().delete_page(page_id)
+    // Takes each instance lock briefly, in order.
+    pub fn instance_load(&self) -> Vec<InstanceLoad> {
+        (0..self.instances.len())
+            .map(|index| {
+                let instance = self.lock_instance(index);
+                instance.load(self.lock_contention[index].load(Ordering::Relaxed))
+            })
+            .collect()
     }
 
-    pub fn flush_all_pages(&self) {
-        for instance in self.instances.iter() {
-            instance.lock().unwrap().flush_all_pages();
+    // Sums the instances, taking each lock briefly in order.
+    pub fn stats(&self) -> BpmStats {
+        let mut stats = BpmStats::default();
+        for index in 0..self.instances.len() {
+            let instance = self.lock_instance(index).stats();
+            stats.hits += instance.hits;
+            stats.misses += instance.misses;
+            stats.flushes += instance.flushes;
         }
+        stats
     }
-}
-
-#[cfg(test)]
-mod test {
-    use super::*;
 
-    #[test]
-    fn parallel_buffer_pool_test() {
-        const NUM_THREADS: usize = 10;
-        let disk_manager = Arc::new(DiskManagerInstance::new("test"));
-        let pbpm = ParallelBufferPoolManager::<LRUReplacer, DiskManagerInstance>::new(
-            5,
-            10,
-            disk_manager,
-        );
-        let pbpm = Arc::new(pbpm);
-        for tid in 0..NUM_THREADS {
-            let pbpm = pbpm.clone();
-            std::thread::spawn(move || {
-                let mut page_id = PageId(tid as u32);
-                let page = pbpm.new_page(&mut page_id).unwrap();
-                let mut page = page.write().unwrap();
-                page.0[0] = page_id.0 as u8;
-                pbpm.unpin_page(page_id, true);
-            });
+    // Sums the instances, taking each lock briefly in order.
+    pub fn memory_usage(&self) -> MemoryReport {
+        let mut report = MemoryReport {
+            free_page_ids: self.allocator.free_page_ids().len() * size_of::<PageId>(),
+            ..MemoryReport::default()
+        };
+        for index in 0..self.instances.len() {
+            let instance = self.lock_instance(index).memory_usage();
+            report.page_buffers += instance.page_buffers;
+            report.frames += instance.frames;
+            report.page_table += instance.page_table;
+            report.free_list += instance.free_list;
+            report.replacer += instance.replacer;
         }
-        // std::fs::remove_file("test.db").unwrap();
+        report
     }
-}
 
+    pub fn fetch_page_run<T>(&self, page_id: PageId, f: impl FnOnce(Data) -> T) -> Option<T> {
+        self.get_instance(page_id)
+            .fetch_page(page_id)
+            .map(f)
+    }
+
+    pub fn unpin_page(&self, page_id: PageId, is_dirty: bool) -> bool {
+        self.get_instance(page_id).unpin_page(page_id, is_dirty)
+    }
+
+    // Unpins many pages, taking each instance lock once. Every entry undoes one pin like
+    // unpin_page, so a page listed twice must have been pinned twice.
+    pub fn unpin_pages(&self, pages: &[(PageId, bool)]) {
+        let mut by_instance = vec![Vec::new(); self.num_instances];
+        for (page_id, is_dirty) in pages {
+            by_instance[self.route(*page_id)].push((*page_id, *is_dirty));
+        }
+        for (index, pages) in by_instance.into_iter().enumerate() {
+            if pages.is_empty() {
+                continue;
+            }
+            let mut instance = self.lock_instance(index);
+            for (page_id, is_dirty) in pages {
+                instance.unpin_page(page_id, is_dirty);
+            }
+        }
+    }
+
+    // Records that only bytes lo..hi of a pinned page changed, so writing it back can skip the
+    // rest. Unpin it with is_dirty = false afterwards, true would mark the whole page. False
+    // if the page isn't resident or isn't pinned.
+    pub fn mark_dirty_range(&self, page_id: PageId, lo: usize, hi: usize) -> bool {
+        self.get_instance(page_id).mark_dirty_range(page_id, lo, hi)
+    }
+
+    pub fn flush_page(&self, page_id: PageId) -> io::Result<()> {
+        self.get_instance(page_id).flush_page(page_id)
+    }
+
+    pub fn write_through(&self, page_id: PageId, data: &[u8; PAGE_SIZE]) -> io::Result<()> {
+        self.get_instance(page_id).write_through(page_id, data)
+    }
+
+    pub fn flush_and_verify(&self, page_id: PageId) -> bool {
+        self.get_instance(page_id).flush_and_verify(page_id)
+    }
+
+    pub fn new_page_run<T>(&self, page_id: &mut PageId, f: impl FnOnce(Data) -> T) -> Option<T> {
+        self.try_new_page_run(page_id, f).ok()
+    }
+
+    pub fn try_new_page(&self, page_id: &mut PageId) -> Result<Data, PoolPressure> {
+        self.try_new_page_run(page_id, |page| page)
+    }
+
+    pub fn try_new_page_run<T>(
+        &self,
+        page_id: &mut PageId,
+        f: impl FnOnce(Data) -> T,
+    ) -> Result<T, PoolPressure> {
+        if self.read_only {
+            return Err(PoolPressure::ReadOnly);
+        }
+        let mut pressure = PoolPressure::AllPinned;
+        let start = self.start_index.load(Ordering::Relaxed) % self.instances.len();
+        let (mut left, mut right) = self.instances.split_at(start);
+        let mut iter = right.iter().chain(left).enumerate();
+        self.start_index.fetch_add(1, Ordering::Relaxed);
+        for (i, _) in iter {
+            let new_page_id = self
+                .allocator
+                .allocate((start + i) % self.instances.len(), self.num_instances);
+            let index = self.route(new_page_id);
+            let mut instance = match self.instances[index].try_lock() {
+                Ok(instance) => instance,
+                Err(_) => {
+                    self.allocator.deallocate(new_page_id);
+                    self.lock_contention[index].fetch_add(1, Ordering::Relaxed);
+                    pressure = PoolPressure::TransientContention;
+                    continue;
+                }
+            };
+            if let Some(page) = instance.new_page_with_id(new_page_id) {
+                *page_id = new_page_id;
+                self.start_index.store(i, Ordering::Relaxed);
+                return Ok(f(page));
+            }
+        }
+        Err(pressure)
+    }
+
+    // page_id is a hint for the instance to place the page in.
+    pub fn new_page(&self, page_id: &mut PageId) -> Option<Data> {
+        let new_page_id = self
+            .allocator
+            .allocate(self.route(*page_id), self.num_instances);
+        let page = self.get_instance(new_page_id).new_page_with_id(new_page_id)?;
+        *page_id = new_page_id;
+        Some(page)
+    }
+
+    // Allocates a page and runs T's initialization on it. The page is pinned.
+    pub fn new_typed_page<T: Pod + InitPage>(&self) -> Option<(PageId, Data)> {
+        let mut page_id = PageId(0);
+        let data = self.new_page(&mut page_id)?;
+        Self::init_typed_page::<T>(page_id, &data);
+        Some((page_id, data))
+    }
+
+    pub fn new_typed_page_blocking<T: Pod + InitPage>(&self) -> (PageId, Data) {
+        let mut page_id = PageId(0);
+        let data = self.new_page_blocking(&mut page_id);
+        Self::init_typed_page::<T>(page_id, &data);
+        (page_id, data)
+    }
+
+    fn init_typed_page<T: Pod + InitPage>(page_id: PageId, data: &Data) {
+        let mut data = data.write().unwrap();
+        let page: &mut T = cast_mut(&mut **data);
+        page.init(page_id);
+    }
+
+    // Panics on a read-only pool, where waiting would never end.
+    pub fn new_page_blocking(&self, page_id: &mut PageId) -> Data {
+        assert!(!self.read_only, "can't create pages in a read-only buffer pool");
+        let mut backoff = Backoff::new();
+        loop {
+            if let Some(page) = self.new_page(page_id) {
+                return page;
+            }
+            backoff.snooze();
+        }
+    }
+
+    // Like new_page_blocking, but backs off exponentially from 1ms up to 64ms between attempts
+    // and gives up once timeout has passed, so a pin leak shows up as an error instead of a hang.
+    pub fn new_page_blocking_timeout(
+        &self,
+        page_id: &mut PageId,
+        timeout: Duration,
+    ) -> Result<Data, PoolExhausted> {
+        Self::retry_until(timeout, || self.new_page(page_id)).ok_or(PoolExhausted)
+    }
+
+    // Calls attempt until it returns Some, backing off exponentially from 1ms up to 64ms in
+    // between. None once timeout has passed.
+    fn retry_until<T>(timeout: Duration, mut attempt: impl FnMut() -> Option<T>) -> Option<T> {
+        let deadline = Instant::now() + timeout;
+        let mut backoff = Duration::from_millis(1);
+        loop {
+            if let Some(result) = attempt() {
+                return Some(result);
+            }
+            let now = Instant::now();
+            if now >= deadline {
+                return None;
+            }
+            thread::sleep(backoff.min(deadline - now));
+            backoff = (backoff * 2).min(Duration::from_millis(64));
+        }
+    }
+
+    pub fn fetch_page(&self, page_id: PageId) -> Option<Data> {
+        self.get_instance(page_id).fetch_page(page_id)
+    }
+
+    // Like try_fetch_page, but retries until a frame is available while every frame of the
+    // page's instance is pinned, however long that takes (fetch_page_timeout gives up). Disk
+    // errors come back at once.
+    pub fn fetch_page_blocking(&self, page_id: PageId) -> Result<Data, BufferError> {
+        let mut backoff = Backoff::new();
+        loop {
+            match self.try_fetch_page(page_id) {
+                Err(BufferError::NoFreeFrame) => backoff.snooze(),
+                result => return result,
+            }
+        }
+    }
+
+    // Like fetch_page_blocking, but backs off like new_page_blocking_timeout and gives up with
+    // Ok(None) once timeout has passed. Only a full pool is waited out, retrying a disk error
+    // wouldn't help, so it comes back at once.
+    pub fn fetch_page_timeout(
+        &self,
+        page_id: PageId,
+        timeout: Duration,
+    ) -> Result<Option<Data>, BufferError> {
+        Self::retry_until(timeout, || match self.try_fetch_page(page_id) {
+            Err(BufferError::NoFreeFrame) => None,
+            result => Some(result),
+        })
+        .transpose()
+    }
+
+    // Fetches many pages, taking each instance lock once. The results are in the order of
+    // page_ids, each pinned like by fetch_page, so a page listed twice is pinned twice.
+    pub fn fetch_pages(&self, page_ids: &[PageId]) -> Vec<Option<Data>> {
+        let mut by_instance = vec![Vec::new(); self.num_instances];
+        for (position, page_id) in page_ids.iter().enumerate() {
+            by_instance[self.route(*page_id)].push((position, *page_id));
+        }
+        let mut pages = vec![None; page_ids.len()];
+        for (index, requests) in by_instance.into_iter().enumerate() {
+            if requests.is_empty() {
+                continue;
+            }
+            let mut instance = self.lock_instance(index);
+            for (position, page_id) in requests {
+                pages[position] = instance.fetch_page(page_id);
+            }
+        }
+        pages
+    }
+
+    // Like fetch_page, unpinned when the guard is dropped.
+    pub fn fetch_page_guarded(&self, page_id: PageId) -> Option<PageGuard<'_, R, D>> {
+        let data = self.fetch_page(page_id)?;
+        Some(PageGuard {
+            bpm: self,
+            page_id,
+            data,
+            is_dirty: false,
+        })
+    }
+
+    pub fn fetch_page_read(&self, page_id: PageId) -> Option<ReadPageGuard<'_, R, D>> {
+        self.fetch_page_guarded(page_id).map(PageGuard::into_read)
+    }
+
+    pub fn fetch_page_write(&self, page_id: PageId) -> Option<WritePageGuard<'_, R, D>> {
+        self.fetch_page_guarded(page_id).map(PageGuard::into_write)
+    }
+
+    // Like new_page, unpinned when the guard is dropped. A new page is dirty from the start.
+    pub fn new_page_guarded(&self) -> Option<(PageId, PageGuard<'_, R, D>)> {
+        let mut page_id = PageId(0);
+        let data = self.new_page(&mut page_id)?;
+        let guard = PageGuard {
+            bpm: self,
+            page_id,
+            data,
+            is_dirty: true,
+        };
+        Some((page_id, guard))
+    }
+
+    // Runs f over a resident page without pinning it or touching the replacer, e.g. for a
+    // cache inspector. Returns None rather than reading the page from disk, and if the page
+    // latch is poisoned. The instance stays locked until f returns, so the frame can't be
+    // reused under f; keep f short. The page latch is only tried, as callers may hold a page
+    // latch and then call into the pool: while a writer has the page, the instance is unlocked
+    // and the lookup retried after backing off.
+    pub fn peek_page<T>(
+        &self,
+        page_id: PageId,
+        f: impl FnOnce(&[u8; PAGE_SIZE]) -> T,
+    ) -> Option<T> {
+        let mut backoff = Backoff::new();
+        loop {
+            let instance = self.get_instance(page_id);
+            let frame_id = instance.page_table.get(&page_id)?;
+            let data = instance.frames[frame_id.0].get_data();
+            let latch = data.try_read();
+            match latch {
+                Ok(latch) => return Some(f(&latch.0)),
+                Err(TryLockError::Poisoned(_)) => return None,
+                Err(TryLockError::WouldBlock) => {
+                    drop(instance);
+                    backoff.snooze();
+                }
+            }
+        }
+    }
+
+    // None if the page isn't resident, e.g. for tests checking that every pin was undone.
+    pub fn pin_count(&self, page_id: PageId) -> Option<usize> {
+        self.get_instance(page_id).pin_count(page_id)
+    }
+
+    pub fn is_resident(&self, page_id: PageId) -> bool {
+        self.get_instance(page_id).is_resident(page_id)
+    }
+
+    pub fn try_fetch_page(&self, page_id: PageId) -> Result<Data, BufferError> {
+        self.get_instance(page_id).try_fetch_page(page_id)
+    }
+
+    pub fn delete_page(&self, page_id: PageId) -> Result<bool, BufferError> {
+        self.get_instance(page_id).delete_page(page_id)
+    }
+
+    // Reads the given pages into the pool and leaves them unpinned, e.g. a table's hot set
+    // after a restart. An instance stops taking pages once its free frames run out, so a warmup
+    // never evicts anything, including earlier warmed pages. Returns how many pages are resident.
+    pub fn warmup(&self, page_ids: &[PageId]) -> usize {
+        let mut warmed = 0;
+        for page_id in page_ids {
+            let mut instance = self.get_instance(*page_id);
+            if !instance.page_table.contains_key(page_id) && instance.free_list.is_empty() {
+                continue;
+            }
+            if instance.try_fetch_page(*page_id).is_ok() {
+                instance.unpin_page(*page_id, false);
+                warmed += 1;
+            }
+        }
+        warmed
+    }
+
+    // Reads the pages that aren't resident into the pool and leaves them unpinned, e.g. the next
+    // pages of a scan. Unlike warmup it evicts to make room, possibly pages prefetched by the
+    // same call. Pages that can't get a frame or fail to read are skipped. The reads count as
+    // misses.
+    pub fn prefetch(&self, page_ids: &[PageId]) {
+        for page_id in page_ids {
+            let mut instance = self.get_instance(*page_id);
+            if instance.page_table.contains_key(page_id) {
+                continue;
+            }
+            if instance.try_fetch_page(*page_id).is_ok() {
+                instance.unpin_page(*page_id, false);
+            }
+        }
+    }
+
+    // Records that the log record lsn changed the page, so flush_all_pages knows where it goes.
+    // Does nothing if the page isn't resident.
+    pub fn set_page_lsn(&self, page_id: PageId, lsn: Lsn) {
+        self.get_instance(page_id).set_page_lsn(page_id, lsn);
+    }
+
+    // Writes the dirty pages of all instances in lsn order, pages without an lsn first, so a
+    // crash never leaves a page on disk while an earlier logged change is missing. Pages
+    // evicted meanwhile have already been written. The pages without an lsn can go in any
+    // order, so the instances write those concurrently, one thread each, before the logged
+    // pages follow one at a time.
+    pub fn flush_all_pages(&self) -> io::Result<()>
+    where
+        R: Send,
+        D: Send + Sync,
+    {
+        let flush_unlogged = |index| -> io::Result<Vec<(Option<Lsn>, PageId)>> {
+            let mut instance = self.lock_instance(index);
+            let (unlogged, logged): (Vec<_>, Vec<_>) = instance
+                .dirty_pages()
+                .into_iter()
+                .partition(|(lsn, _)| lsn.is_none());
+            for (_, page_id) in unlogged {
+                instance.flush_page(page_id)?;
+            }
+            Ok(logged)
+        };
+        let mut dirty_pages = Vec::new();
+        thread::scope(|s| {
+            let handles: Vec<_> = (0..self.instances.len())
+                .map(|index| s.spawn(move || flush_unlogged(index)))
+                .collect();
+            for handle in handles {
+                dirty_pages.extend(handle.join().unwrap()?);
+            }
+            Ok::<_, io::Error>(())
+        })?;
+        dirty_pages.sort_by_key(|(lsn, _)| *lsn);
+        for (_, page_id) in dirty_pages {
+            let mut instance = self.get_instance(page_id);
+            if instance.page_table.contains_key(&page_id) {
+                instance.flush_page(page_id)?;
+            }
+        }
+        Ok(())
+    }
+
+    // Flushes every dirty page and then syncs the disk, so every change unpinned as dirty
+    // before the call survives a crash.
+    pub fn checkpoint(&self) -> io::Result<()>
+    where
+        R: Send,
+        D: Send + Sync,
+    {
+        self.flush_all_pages()?;
+        let disk_manager = self.lock_instance(0).disk_manager.clone();
+        disk_manager.sync()
+    }
+
+    // The pages the disk manager finds torn on disk, see DiskManager::scan_for_torn_pages. A
+    // resident copy of such a page may still be fine.
+    pub fn scan_for_torn_pages(&self) -> io::Result<Vec<PageId>> {
+        let disk_manager = self.lock_instance(0).disk_manager.clone();
+        disk_manager.scan_for_torn_pages()
+    }
+
+    // Flushes every dirty page and copies the db file to path. All instances stay locked from
+    // the flush to the end of the copy, so nothing can be written back in between; fetches
+    // and new pages wait for the copy rather than the copy racing them. Threads already
+    // holding a page can keep using it in memory, their changes just miss the snapshot, so
+    // take it between operations to get a consistent table.
+    pub fn snapshot_to(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        let mut guard = self.maintenance_lock();
+        let mut dirty_pages = Vec::new();
+        for index in 0..guard.num_instances() {
+            dirty_pages.extend(
+                guard
+                    .instance(index)
+                    .dirty_pages()
+                    .into_iter()
+                    .map(|(lsn, page_id)| (lsn, index, page_id)),
+            );
+        }
+        dirty_pages.sort_by_key(|(lsn, _, _)| *lsn);
+        for (_, index, page_id) in dirty_pages {
+            guard.instance(index).flush_page(page_id)?;
+        }
+        guard.instance(0).disk_manager.copy_to(path.as_ref())
+    }
+}
+
+impl<R: Replacer, D: DiskManager> Metrics for ParallelBufferPoolManager<R, D> {
+    // Summed over the instances, each read under its own lock. The hit ratio is 0 before the
+    // first fetch.
+    fn snapshot(&self) -> Vec<(String, f64)> {
+        let (mut hits, mut misses, mut evictions) = (0, 0, 0);
+        let (mut resident_pages, mut free_frames) = (0, 0);
+        for index in 0..self.instances.len() {
+            let instance = self.lock_instance(index);
+            hits += instance.hits;
+            misses += instance.misses;
+            evictions += instance.evictions;
+            resident_pages += instance.page_table.len();
+            free_frames += instance.free_list.len();
+        }
+        let hit_ratio = if hits + misses == 0 {
+            0.0
+        } else {
+            hits as f64 / (hits + misses) as f64
+        };
+        vec![
+            ("buffer_pool_hit_ratio".to_string(), hit_ratio),
+            ("buffer_pool_hits".to_string(), hits as f64),
+            ("buffer_pool_misses".to_string(), misses as f64),
+            ("buffer_pool_evictions".to_string(), evictions as f64),
+            ("buffer_pool_resident_pages".to_string(), resident_pages as f64),
+            ("buffer_pool_free_frames".to_string(), free_frames as f64),
+        ]
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::buffer::page_id_allocator::Sequential;
+    use crate::buffer::replacer::FIFOReplacer;
+    use crate::storage::disk::faulty_disk_manager::FaultyDiskManager;
+    use crate::storage::disk::in_memory_disk_manager::InMemoryDiskManager;
+    use crate::storage::disk::recording_disk_manager::{DiskOpKind, RecordingDiskManager};
+    use std::collections::HashSet;
+    use std::sync::atomic::AtomicBool;
+
+    #[test]
+    fn parallel_buffer_pool_test() {
+        const NUM_THREADS: usize = 10;
+        let disk_manager = Arc::new(DiskManagerInstance::new("test"));
+        let pbpm = ParallelBufferPoolManager::<LRUReplacer, DiskManagerInstance>::new(
+            5,
+            10,
+            disk_manager,
+        );
+        let pbpm = Arc::new(pbpm);
+        for tid in 0..NUM_THREADS {
+            let pbpm = pbpm.clone();
+            std::thread::spawn(move || {
+                let mut page_id = PageId(tid as u32);
+                let page = pbpm.new_page(&mut page_id).unwrap();
+                let mut page = page.write().unwrap();
+                page.0[0] = page_id.0 as u8;
+                pbpm.unpin_page(page_id, true);
+            });
+        }
+        // std::fs::remove_file("test.db").unwrap();
+    }
+
+    #[test]
+    fn instance_load_test() {
+        let disk_manager = Arc::new(DiskManagerInstance::new("test_instance_load"));
+        let pbpm = ParallelBufferPoolManager::<LRUReplacer, DiskManagerInstance>::new(
+            5,
+            10,
+            disk_manager,
+        );
+        // every page id below routes to instance 0
+        let mut page_ids = Vec::new();
+        for _ in 0..4 {
+            let mut page_id = PageId(0);
+            pbpm.new_page(&mut page_id).unwrap();
+            assert_eq!(page_id.0 % 5, 0);
+            page_ids.push(page_id);
+        }
+        pbpm.unpin_page(page_ids[0], false);
+
+        let load = pbpm.instance_load();
+        assert_eq!(load[0].resident_pages, 4);
+        assert_eq!(load[0].pinned_frames, 3);
+        assert_eq!(load[0].free_frames, 10 - 4);
+        for instance_load in &load[1..] {
+            assert_eq!(instance_load.resident_pages, 0);
+            assert_eq!(instance_load.pinned_frames, 0);
+            assert_eq!(instance_load.free_frames, 10);
+        }
+        assert!(load.iter().all(|instance_load| instance_load.lock_contention == 0));
+
+        thread::scope(|s| {
+            let held = pbpm.instances[0].lock().unwrap();
+            let fetcher = s.spawn(|| pbpm.fetch_page(page_ids[1]));
+            thread::sleep(Duration::from_millis(50));
+            drop(held);
+            assert!(fetcher.join().unwrap().is_some());
+        });
+        assert_eq!(pbpm.instance_load()[0].lock_contention, 1);
+        std::fs::remove_file("test_instance_load.db").unwrap();
+    }
+
+    #[test]
+    fn single_instance_test() {
+        let disk_manager = Arc::new(DiskManagerInstance::new("test_single_instance"));
+        let mut bpm = BufferPoolManager::<LRUReplacer, _>::single(3, disk_manager);
+        let mut page_ids = Vec::new();
+        for i in 0..3 {
+            let mut page_id = PageId(0);
+            let page = bpm.new_page(&mut page_id).unwrap();
+            page.write().unwrap().0[0] = i;
+            page_ids.push(page_id);
+        }
+        assert_eq!(page_ids, vec![PageId(0), PageId(1), PageId(2)]);
+        // every frame is pinned
+        assert!(bpm.new_page(&mut PageId(0)).is_none());
+        assert!(bpm.fetch_page(PageId(3)).is_none());
+
+        for page_id in page_ids.iter() {
+            bpm.unpin_page(*page_id, true);
+        }
+        // evicts page 0, writing it back
+        let mut page_id = PageId(0);
+        bpm.new_page(&mut page_id).unwrap();
+        assert_eq!(page_id, PageId(3));
+        bpm.unpin_page(page_id, false);
+        let page = bpm.fetch_page(page_ids[0]).unwrap();
+        assert_eq!(page.read().unwrap().0[0], 0);
+        bpm.unpin_page(page_ids[0], false);
+
+        // page 1 was evicted to make room for page 0
+        bpm.flush_page(page_ids[2]).unwrap();
+        let flushes = bpm.stats().flushes;
+        bpm.delete_page(page_ids[2]).unwrap();
+        // neither the deleted nor the evicted page is resident, there is nothing to flush
+        bpm.flush_page(page_ids[2]).unwrap();
+        bpm.flush_page(page_ids[1]).unwrap();
+        assert_eq!(bpm.stats().flushes, flushes);
+        assert_eq!(bpm.load(0).resident_pages, 2);
+        assert_eq!(bpm.load(0).free_frames, 1);
+        std::fs::remove_file("test_single_instance.db").unwrap();
+    }
+
+    #[test]
+    fn pool_pressure_test() {
+        let disk_manager = Arc::new(DiskManagerInstance::new("test_pool_pressure"));
+        let pbpm = ParallelBufferPoolManager::<LRUReplacer, DiskManagerInstance>::new(
+            2,
+            2,
+            disk_manager,
+        );
+        let mut page_ids = Vec::new();
+        for _ in 0..4 {
+            let mut page_id = PageId(0);
+            pbpm.try_new_page(&mut page_id).unwrap();
+            page_ids.push(page_id);
+        }
+        assert_eq!(
+            pbpm.try_new_page(&mut PageId(0)).err(),
+            Some(PoolPressure::AllPinned)
+        );
+
+        // instance 0 has a free frame but is busy, instance 1 is full
+        let page_id = *page_ids.iter().find(|page_id| page_id.0 % 2 == 0).unwrap();
+        pbpm.unpin_page(page_id, false);
+        let held = pbpm.instances[0].lock().unwrap();
+        assert_eq!(
+            pbpm.try_new_page(&mut PageId(0)).err(),
+            Some(PoolPressure::TransientContention)
+        );
+        drop(held);
+        assert!(pbpm.try_new_page(&mut PageId(0)).is_ok());
+        std::fs::remove_file("test_pool_pressure.db").unwrap();
+    }
+
+    #[test]
+    fn sequential_allocator_reopen_test() {
+        let disk_manager = Arc::new(DiskManagerInstance::new("test_sequential_allocator"));
+        {
+            let pbpm = ParallelBufferPoolManager::<LRUReplacer, _>::with_allocator(
+                2,
+                2,
+                disk_manager.clone(),
+                Arc::new(Sequential::new(0)),
+            );
+            for i in 0..6 {
+                let mut page_id = PageId(0);
+                let page = pbpm.new_page_blocking(&mut page_id);
+                assert_eq!(page_id, PageId(i));
+                page.write().unwrap().0[0] = i as u8;
+                pbpm.unpin_page(page_id, true);
+            }
+            pbpm.flush_all_pages().unwrap();
+        }
+
+        // the same file with a different number of instances
+        let pbpm = ParallelBufferPoolManager::<LRUReplacer, _>::with_allocator(
+            3,
+            3,
+            disk_manager,
+            Arc::new(Sequential::new(6)),
+        );
+        for i in 0..6 {
+            let page = pbpm.fetch_page(PageId(i)).unwrap();
+            assert_eq!(page.read().unwrap().0[0], i as u8);
+            pbpm.unpin_page(PageId(i), false);
+        }
+        let mut page_id = PageId(0);
+        pbpm.new_page(&mut page_id).unwrap();
+        assert_eq!(page_id, PageId(6));
+        std::fs::remove_file("test_sequential_allocator.db").unwrap();
+    }
+
+    #[test]
+    fn new_typed_page_test() {
+        use crate::storage::pages::hash_table_bucket_page::HashTableBucketPage;
+        use crate::storage::pages::hash_table_directory_page::HashTableDirectoryPage;
+        use bytemuck::cast_ref;
+
+        let disk_manager = Arc::new(DiskManagerInstance::new("test_new_typed_page"));
+        let pbpm = ParallelBufferPoolManager::<LRUReplacer, _>::new(1, 1, disk_manager);
+        // leave garbage in the only frame
+        let mut page_id = PageId(0);
+        pbpm.new_page(&mut page_id).unwrap().write().unwrap().0 = [0xff; PAGE_SIZE];
+        pbpm.unpin_page(page_id, false);
+        pbpm.delete_page(page_id).unwrap();
+
+        let (page_id, data) = pbpm.new_typed_page::<HashTableDirectoryPage>().unwrap();
+        {
+            let data = data.read().unwrap();
+            let dir: &HashTableDirectoryPage = cast_ref(&**data);
+            assert_eq!(dir.get_page_id(), page_id);
+            assert_eq!(dir.get_global_depth(), 0);
+            assert_eq!(dir.get_local_depth(0), 0);
+        }
+        assert!(pbpm.new_typed_page::<HashTableDirectoryPage>().is_none());
+        pbpm.unpin_page(page_id, true);
+
+        let (_, data) = pbpm.new_typed_page_blocking::<HashTableBucketPage<i32, i32>>();
+        let data = data.read().unwrap();
+        let bucket: &HashTableBucketPage<i32, i32> = cast_ref(&**data);
+        assert_eq!(bucket.num_readable(), 0);
+        std::fs::remove_file("test_new_typed_page.db").unwrap();
+    }
+
+    #[test]
+    fn fetch_unwritten_page_test() {
+        use crate::storage::pages::hash_table_bucket_page::HashTableBucketPage;
+        use bytemuck::cast_ref;
+
+        let disk_manager = Arc::new(DiskManagerInstance::new("test_fetch_unwritten_page"));
+        let pbpm = ParallelBufferPoolManager::<LRUReplacer, _>::new(1, 1, disk_manager);
+        let (page_id, data) = pbpm.new_typed_page::<HashTableBucketPage<i32, i32>>().unwrap();
+        drop(data);
+        pbpm.unpin_page(page_id, true);
+
+        // a page past the end of the file goes into the frame the bucket just left
+        let data = pbpm.fetch_page(PageId(1000)).unwrap();
+        let data = data.read().unwrap();
+        let bucket: &HashTableBucketPage<i32, i32> = cast_ref(&**data);
+        assert!(!bucket.is_initialized());
+        assert_eq!(data.0, [0; PAGE_SIZE]);
+        std::fs::remove_file("test_fetch_unwritten_page.db").unwrap();
+    }
+
+    fn disk_metric(disk_manager: &impl Metrics, name: &str) -> f64 {
+        let metrics: HashMap<_, _> = disk_manager.snapshot().into_iter().collect();
+        metrics[name]
+    }
+
+    // the pages written since the trace was last taken, in order
+    fn take_writes<D: DiskManager>(disk_manager: &RecordingDiskManager<D>) -> Vec<PageId> {
+        disk_manager
+            .take_trace()
+            .into_iter()
+            .filter(|op| op.kind == DiskOpKind::Write)
+            .map(|op| op.page_id)
+            .collect()
+    }
+
+    #[test]
+    fn flush_and_verify_test() {
+        let disk_manager = Arc::new(FaultyDiskManager::new(InMemoryDiskManager::new()));
+        let mut bpm = BufferPoolManager::<LRUReplacer, _>::single(2, disk_manager.clone());
+        let mut page_id = PageId(0);
+        bpm.new_page(&mut page_id).unwrap().write().unwrap().0[0] = 42;
+        let frame_id = bpm.page_table[&page_id];
+        assert!(bpm.frames[frame_id.0].is_dirty());
+        assert!(bpm.flush_and_verify(page_id));
+        assert!(!bpm.frames[frame_id.0].is_dirty());
+
+        bpm.fetch_page(page_id).unwrap().write().unwrap().0[0] = 43;
+        bpm.unpin_page(page_id, true);
+        // the page read back no longer matches
+        disk_manager.set_corrupt_reads(true);
+        assert!(!bpm.flush_and_verify(page_id));
+        assert!(bpm.frames[frame_id.0].is_dirty());
+        assert!(!bpm.flush_and_verify(PageId(7)));
+    }
+
+    #[test]
+    fn eviction_write_failure_test() {
+        let disk_manager = Arc::new(FaultyDiskManager::new(InMemoryDiskManager::new()));
+        let mut bpm = BufferPoolManager::<LRUReplacer, _>::single(1, disk_manager.clone());
+        let mut page_id = PageId(0);
+        bpm.new_page(&mut page_id).unwrap().write().unwrap().0[0] = 42;
+        bpm.unpin_page(page_id, true);
+
+        // evicting the dirty page fails, so it has to stay in the pool
+        disk_manager.fail_nth_write(1);
+        assert!(matches!(
+            bpm.try_fetch_page(PageId(1)),
+            Err(BufferError::Io(_))
+        ));
+        let frame_id = bpm.page_table[&page_id];
+        assert!(bpm.frames[frame_id.0].is_dirty());
+        assert_eq!(bpm.fetch_page(page_id).unwrap().read().unwrap().0[0], 42);
+        bpm.unpin_page(page_id, false);
+
+        // the retry writes it back
+        bpm.try_fetch_page(PageId(1)).unwrap();
+        bpm.unpin_page(PageId(1), false);
+        assert_eq!(bpm.fetch_page(page_id).unwrap().read().unwrap().0[0], 42);
+        bpm.unpin_page(page_id, false);
+
+        disk_manager.fail_nth_read(1);
+        assert!(matches!(
+            bpm.try_fetch_page(PageId(1)),
+            Err(BufferError::Io(_))
+        ));
+        assert_eq!(bpm.load(0).free_frames, 1);
+        assert!(bpm.fetch_page(PageId(1)).is_some());
+    }
+
+    #[test]
+    #[should_panic(expected = "at least one instance")]
+    fn zero_instances_test() {
+        let disk_manager = Arc::new(InMemoryDiskManager::new());
+        ParallelBufferPoolManager::<LRUReplacer, _>::new(0, 10, disk_manager);
+    }
+
+    #[test]
+    fn total_frames_test() {
+        let disk_manager = Arc::new(InMemoryDiskManager::new());
+        let pbpm = ParallelBufferPoolManager::<LRUReplacer, _>::new(3, 7, disk_manager);
+        let load = pbpm.instance_load();
+        assert_eq!(load.len(), 3);
+        assert_eq!(load.iter().map(|load| load.free_frames).sum::<usize>(), 3 * 7);
+        assert_eq!(pbpm.total_pool_size(), 3 * 7);
+        // every frame can hold a page at once
+        for _ in 0..3 * 7 {
+            pbpm.try_new_page(&mut PageId(0)).unwrap();
+        }
+        assert_eq!(
+            pbpm.try_new_page(&mut PageId(0)).err(),
+            Some(PoolPressure::AllPinned)
+        );
+    }
+
+    #[test]
+    fn new_total_test() {
+        let disk_manager = Arc::new(InMemoryDiskManager::new());
+        let pbpm = ParallelBufferPoolManager::<LRUReplacer, _>::new_total(3, 11, disk_manager);
+        assert_eq!(pbpm.total_pool_size(), 11);
+        let free_frames: Vec<_> = pbpm
+            .instance_load()
+            .iter()
+            .map(|load| load.free_frames)
+            .collect();
+        assert_eq!(free_frames, vec![4, 4, 3]);
+    }
+
+    #[test]
+    fn repair_routing_test() {
+        let disk_manager = Arc::new(InMemoryDiskManager::new());
+        let pbpm = ParallelBufferPoolManager::<LRUReplacer, _>::new(2, 4, disk_manager);
+        // page 4 routes to instance 0 but gets created in instance 1
+        {
+            let mut instance = pbpm.instances[1].lock().unwrap();
+            let page = instance.new_page_with_id(PageId(4)).unwrap();
+            page.write().unwrap().0[0] = 4;
+            instance.unpin_page(PageId(4), true);
+        }
+        assert_eq!(pbpm.misrouted_pages(), vec![(1, PageId(4))]);
+
+        // instance 0 has no frame for it, the page goes back to disk and isn't counted
+        let pinned = [0, 2, 6, 8].map(PageId);
+        for page_id in pinned {
+            pbpm.instances[0].lock().unwrap().new_page_with_id(page_id).unwrap();
+        }
+        assert!(matches!(pbpm.repair_routing(), Err(BufferError::NoFreeFrame)));
+        assert!(pbpm.misrouted_pages().is_empty());
+        assert!(!pbpm.is_resident(PageId(4)));
+        for page_id in pinned {
+            pbpm.unpin_page(page_id, false);
+        }
+        let page = pbpm.fetch_page(PageId(4)).unwrap();
+        assert_eq!(page.read().unwrap().0[0], 4);
+        pbpm.unpin_page(PageId(4), false);
+
+        {
+            let mut instance = pbpm.instances[1].lock().unwrap();
+            let page = instance.new_page_with_id(PageId(10)).unwrap();
+            page.write().unwrap().0[0] = 10;
+            instance.unpin_page(PageId(10), true);
+        }
+        assert_eq!(pbpm.repair_routing().unwrap(), 1);
+        assert!(pbpm.misrouted_pages().is_empty());
+        assert!(!pbpm.instances[1].lock().unwrap().page_table.contains_key(&PageId(10)));
+        assert!(pbpm.instances[0].lock().unwrap().page_table.contains_key(&PageId(10)));
+        let page = pbpm.fetch_page(PageId(10)).unwrap();
+        assert_eq!(page.read().unwrap().0[0], 10);
+    }
+
+    #[test]
+    fn delete_unpinned_page_test() {
+        let disk_manager = Arc::new(InMemoryDiskManager::new());
+        let mut bpm = BufferPoolManager::<LRUReplacer, _>::single(3, disk_manager);
+        let mut page_id = PageId(0);
+        bpm.new_page(&mut page_id).unwrap();
+        bpm.unpin_page(page_id, false);
+        bpm.delete_page(page_id).unwrap();
+
+        let mut frames = HashSet::new();
+        for _ in 0..3 {
+            bpm.new_page(&mut page_id).unwrap();
+            assert!(frames.insert(bpm.page_table[&page_id].0));
+        }
+        // every frame is pinned, the deleted page's frame must not come back as a victim
+        assert!(bpm.new_page(&mut page_id).is_none());
+    }
+
+    #[test]
+    fn pin_transitions_test() {
+        let disk_manager = Arc::new(InMemoryDiskManager::new());
+        let mut bpm = BufferPoolManager::<LRUReplacer, _>::single(2, disk_manager);
+        let evictable = |bpm: &BufferPoolManager<LRUReplacer, _>, page_id| {
+            let frame_id = bpm.page_table[&page_id];
+            bpm.replacer.peek_victims(2).contains(&frame_id)
+        };
+        let mut page_id = PageId(0);
+        bpm.new_page(&mut page_id).unwrap();
+        assert!(!evictable(&bpm, page_id));
+        bpm.unpin_page(page_id, true);
+        assert!(evictable(&bpm, page_id));
+
+        bpm.fetch_page(page_id).unwrap();
+        assert!(!evictable(&bpm, page_id));
+        bpm.fetch_page(page_id).unwrap();
+        bpm.unpin_page(page_id, false);
+        assert!(!evictable(&bpm, page_id));
+        bpm.unpin_page(page_id, false);
+        assert!(evictable(&bpm, page_id));
+    }
+
+    #[test]
+    fn warmup_test() {
+        let disk_manager = Arc::new(InMemoryDiskManager::new());
+        for i in 0..8 {
+            disk_manager.write_page(PageId(i), &[i as u8; PAGE_SIZE]).unwrap();
+        }
+        let pbpm = ParallelBufferPoolManager::<LRUReplacer, _>::new(2, 2, disk_manager.clone());
+        let hot_set: Vec<_> = (0..8).map(PageId).collect();
+        // pages 4 to 7 don't fit
+        assert_eq!(pbpm.warmup(&hot_set), 4);
+        assert_eq!(disk_metric(&*disk_manager, "disk_reads"), 4.0);
+
+        for i in 0..4 {
+            let page = pbpm.fetch_page(PageId(i)).unwrap();
+            assert_eq!(page.read().unwrap().0[0], i as u8);
+            pbpm.unpin_page(PageId(i), false);
+        }
+        assert_eq!(disk_metric(&*disk_manager, "disk_reads"), 4.0);
+    }
+
+    #[test]
+    fn flush_in_lsn_order_test() {
+        let disk_manager = Arc::new(RecordingDiskManager::new(InMemoryDiskManager::new()));
+        let pbpm = ParallelBufferPoolManager::<LRUReplacer, _>::new(3, 4, disk_manager.clone());
+        let lsns = [Some(5), None, Some(2), Some(9), Some(0), Some(7), Some(4), Some(3)];
+        let mut page_ids = Vec::new();
+        for (i, lsn) in lsns.into_iter().enumerate() {
+            // spread the pages over the instances
+            let mut page_id = PageId(i as u32);
+            pbpm.new_page(&mut page_id).unwrap();
+            if let Some(lsn) = lsn {
+                pbpm.set_page_lsn(page_id, Lsn(lsn));
+            }
+            pbpm.unpin_page(page_id, true);
+            page_ids.push((lsn, page_id));
+        }
+        // only the larger lsn sticks
+        pbpm.set_page_lsn(page_ids[0].1, Lsn(1));
+
+        pbpm.flush_all_pages().unwrap();
+        let written = take_writes(&disk_manager);
+        let mut expected = page_ids.clone();
+        expected.sort_by_key(|(lsn, _)| *lsn);
+        assert_eq!(written, expected.into_iter().map(|(_, page_id)| page_id).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn metrics_test() {
+        let disk_manager = Arc::new(DiskManagerInstance::new("test_metrics"));
+        let pbpm = ParallelBufferPoolManager::<LRUReplacer, _>::new(2, 2, disk_manager.clone());
+        let mut page_ids = Vec::new();
+        for i in 0..6 {
+            let mut page_id = PageId(i);
+            pbpm.new_page(&mut page_id).unwrap();
+            pbpm.unpin_page(page_id, true);
+            page_ids.push(page_id);
+        }
+        for page_id in page_ids.iter().rev() {
+            pbpm.fetch_page(*page_id).unwrap();
+            pbpm.unpin_page(*page_id, false);
+        }
+        let metrics: HashMap<_, _> = pbpm.snapshot().into_iter().collect();
+        // the 4 most recent pages were still resident
+        assert_eq!(metrics["buffer_pool_hits"], 4.0);
+        assert_eq!(metrics["buffer_pool_misses"], 2.0);
+        assert_eq!(metrics["buffer_pool_hit_ratio"], 4.0 / 6.0);
+        assert_eq!(metrics["buffer_pool_evictions"], 4.0);
+        assert_eq!(metrics["buffer_pool_resident_pages"], 4.0);
+        assert_eq!(metrics["buffer_pool_free_frames"], 0.0);
+
+        let metrics: HashMap<_, _> = disk_manager.snapshot().into_iter().collect();
+        assert_eq!(metrics["disk_reads"], 2.0);
+        assert_eq!(metrics["disk_writes"], 4.0);
+        std::fs::remove_file("test_metrics.db").unwrap();
+    }
+
+    #[test]
+    fn peek_page_test() {
+        let disk_manager = Arc::new(InMemoryDiskManager::new());
+        let pbpm = ParallelBufferPoolManager::<LRUReplacer, _>::new(1, 3, disk_manager.clone());
+        let mut page_ids = Vec::new();
+        for i in 0..3 {
+            let mut page_id = PageId(0);
+            pbpm.new_page(&mut page_id).unwrap().write().unwrap().0[0] = i;
+            pbpm.unpin_page(page_id, true);
+            page_ids.push(page_id);
+        }
+        let victims = |pbpm: &ParallelBufferPoolManager<LRUReplacer, _>| {
+            pbpm.instances[0].lock().unwrap().replacer.peek_victims(3)
+        };
+        let before = victims(&pbpm);
+        // the least recently used page stays first in line
+        let first_byte = |data: &[u8; PAGE_SIZE]| data[0];
+        assert_eq!(pbpm.peek_page(page_ids[0], first_byte), Some(0));
+        assert_eq!(victims(&pbpm), before);
+        assert_eq!(pbpm.peek_page(PageId(100), first_byte), None);
+        assert_eq!(disk_metric(&*disk_manager, "disk_reads"), 0.0);
+
+        // a writer holding the latch can still call into the pool while a peek waits for it
+        let data = pbpm.fetch_page(page_ids[1]).unwrap();
+        let latch = data.write().unwrap();
+        thread::scope(|s| {
+            let peek = s.spawn(|| pbpm.peek_page(page_ids[1], first_byte));
+            thread::sleep(Duration::from_millis(20));
+            assert!(pbpm.is_resident(page_ids[2]));
+            drop(latch);
+            assert_eq!(peek.join().unwrap(), Some(1));
+        });
+
+        // f runs with the instance locked, so nothing can evict the page under it
+        let locked = |_: &[u8; PAGE_SIZE]| pbpm.instances[0].try_lock().is_err();
+        assert_eq!(pbpm.peek_page(page_ids[2], locked), Some(true));
+
+        // a writer that panicked poisons the latch
+        thread::scope(|s| {
+            s.spawn(|| {
+                let _latch = data.write().unwrap();
+                panic!("writer failed");
+            })
+            .join()
+            .unwrap_err();
+        });
+        assert_eq!(pbpm.peek_page(page_ids[1], first_byte), None);
+    }
+
+    #[test]
+    fn new_page_blocking_timeout_test() {
+        let disk_manager = Arc::new(InMemoryDiskManager::new());
+        let pbpm = ParallelBufferPoolManager::<LRUReplacer, _>::new(2, 2, disk_manager);
+        let mut page_id = PageId(0);
+        for i in 0..4 {
+            page_id = PageId(i);
+            pbpm.new_page_blocking_timeout(&mut page_id, Duration::ZERO).unwrap();
+        }
+        let start = Instant::now();
+        let result = pbpm.new_page_blocking_timeout(&mut page_id, Duration::from_millis(50));
+        assert_eq!(result.err(), Some(PoolExhausted));
+        let elapsed = start.elapsed();
+        assert!(elapsed >= Duration::from_millis(50) && elapsed < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn free_page_ids_test() {
+        let disk_manager = Arc::new(InMemoryDiskManager::new());
+        let pbpm = ParallelBufferPoolManager::<LRUReplacer, _>::new(2, 4, disk_manager);
+        let mut page_ids = Vec::new();
+        for i in 0..4 {
+            let mut page_id = PageId(i);
+            pbpm.new_page(&mut page_id).unwrap();
+            pbpm.unpin_page(page_id, false);
+            page_ids.push(page_id);
+        }
+        assert_eq!(pbpm.free_page_ids(), vec![vec![], vec![]]);
+        let deleted = page_ids[1];
+        pbpm.delete_page(deleted).unwrap();
+        let index = deleted.0 as usize % 2;
+        assert_eq!(pbpm.free_page_ids()[index], vec![deleted]);
+
+        let mut page_id = deleted;
+        pbpm.new_page(&mut page_id).unwrap();
+        assert_eq!(page_id, deleted);
+        assert_eq!(pbpm.free_page_ids(), vec![vec![], vec![]]);
+    }
+
+    #[test]
+    fn dirty_range_test() {
+        let disk_manager = Arc::new(DiskManagerInstance::new("test_dirty_range"));
+        let pbpm = ParallelBufferPoolManager::<LRUReplacer, _>::new(1, 2, disk_manager.clone());
+        let mut page_id = PageId(0);
+        pbpm.new_page(&mut page_id).unwrap().write().unwrap().0 = [1; PAGE_SIZE];
+        pbpm.unpin_page(page_id, true);
+        // the page is on disk, and clean again
+        pbpm.flush_page(page_id).unwrap();
+
+        let data = pbpm.fetch_page(page_id).unwrap();
+        {
+            let mut data = data.write().unwrap();
+            data.0[100..104].fill(7);
+            // not marked, so it must not reach the disk
+            data.0[2000] = 9;
+        }
+        assert!(pbpm.mark_dirty_range(page_id, 100, 104));
+        pbpm.unpin_page(page_id, false);
+        pbpm.flush_page(page_id).unwrap();
+
+        let mut buf = [0u8; PAGE_SIZE];
+        disk_manager.read_page(page_id, &mut buf).unwrap();
+        assert_eq!(buf[99..105], [1, 7, 7, 7, 7, 1]);
+        assert_eq!(buf[2000], 1);
+
+        // neither an unpinned page nor one that isn't resident is marked
+        assert!(!pbpm.mark_dirty_range(page_id, 0, 8));
+        assert!(pbpm.lock_instance(0).dirty.is_empty());
+        assert!(!pbpm.mark_dirty_range(PageId(100), 0, 8));
+        std::fs::remove_file("test_dirty_range.db").unwrap();
+    }
+
+    #[test]
+    fn snapshot_to_test() {
+        let disk_manager = Arc::new(DiskManagerInstance::new("test_snapshot"));
+        let pbpm = ParallelBufferPoolManager::<LRUReplacer, _>::new(2, 8, disk_manager);
+        let mut page_ids = Vec::new();
+        for i in 0..8 {
+            let mut page_id = PageId(i);
+            let data = pbpm.new_page(&mut page_id).unwrap();
+            data.write().unwrap().0[0] = i as u8 + 1;
+            // still only in memory
+            pbpm.unpin_page(page_id, true);
+            page_ids.push(page_id);
+        }
+        pbpm.snapshot_to("test_snapshot_copy.db").unwrap();
+        // later changes don't reach the snapshot
+        pbpm.fetch_page(page_ids[0]).unwrap().write().unwrap().0[0] = 0xff;
+        pbpm.unpin_page(page_ids[0], true);
+
+        let disk_manager = Arc::new(DiskManagerInstance::new("test_snapshot_copy"));
+        let snapshot = ParallelBufferPoolManager::<LRUReplacer, _>::new(2, 8, disk_manager);
+        for (i, page_id) in page_ids.into_iter().enumerate() {
+            let data = snapshot.fetch_page(page_id).unwrap();
+            assert_eq!(data.read().unwrap().0[0], i as u8 + 1);
+            snapshot.unpin_page(page_id, false);
+        }
+        std::fs::remove_file("test_snapshot.db").unwrap();
+        std::fs::remove_file("test_snapshot_copy.db").unwrap();
+    }
+
+    #[test]
+    fn set_replacer_test() {
+        let disk_manager = Arc::new(InMemoryDiskManager::new());
+        let mut bpm = BufferPoolManager::<Box<dyn Replacer + Send>, _>::single(5, disk_manager);
+        let mut page_ids = Vec::new();
+        for _ in 0..5 {
+            let mut page_id = PageId(0);
+            bpm.new_page(&mut page_id).unwrap();
+            page_ids.push(page_id);
+        }
+        for i in [3, 0, 2] {
+            bpm.unpin_page(page_ids[i], false);
+        }
+        let evictable = bpm.replacer.peek_victims(5);
+        assert_eq!(evictable.len(), 3);
+
+        bpm.set_replacer(Box::new(FIFOReplacer::new(5)));
+        // same frames, same order, and the pinned ones still left out
+        assert_eq!(bpm.replacer.peek_victims(5), evictable);
+        // a page pinned during the swap becomes evictable in the new replacer
+        bpm.unpin_page(page_ids[1], false);
+        assert_eq!(bpm.replacer.size(), 4);
+    }
+
+    #[derive(Default)]
+    struct EventLog(Mutex<Vec<(&'static str, PageId)>>);
+
+    impl PageEventListener for EventLog {
+        fn on_fetch(&self, page_id: PageId) {
+            self.0.lock().unwrap().push(("fetch", page_id));
+        }
+
+        fn on_evict(&self, page_id: PageId) {
+            self.0.lock().unwrap().push(("evict", page_id));
+        }
+
+        fn on_new(&self, page_id: PageId) {
+            self.0.lock().unwrap().push(("new", page_id));
+        }
+
+        fn on_delete(&self, page_id: PageId) {
+            self.0.lock().unwrap().push(("delete", page_id));
+        }
+
+        fn on_flush(&self, page_id: PageId) {
+            self.0.lock().unwrap().push(("flush", page_id));
+        }
+    }
+
+    #[test]
+    fn page_event_listener_test() {
+        let disk_manager = Arc::new(InMemoryDiskManager::new());
+        let pbpm = ParallelBufferPoolManager::<LRUReplacer, _>::new(1, 2, disk_manager);
+        let events = Arc::new(EventLog::default());
+        pbpm.set_listener(Some(events.clone()));
+        let (mut p0, mut p1, mut p2) = (PageId(0), PageId(0), PageId(0));
+        pbpm.new_page(&mut p0).unwrap();
+        pbpm.new_page(&mut p1).unwrap();
+        pbpm.unpin_page(p0, true);
+        pbpm.unpin_page(p1, false);
+        pbpm.fetch_page(p0).unwrap();
+        pbpm.unpin_page(p0, false);
+        // p1 was unpinned longest ago
+        pbpm.new_page(&mut p2).unwrap();
+        pbpm.unpin_page(p2, false);
+        pbpm.flush_page(p0).unwrap();
+        pbpm.delete_page(p2).unwrap();
+        assert_eq!(
+            *events.0.lock().unwrap(),
+            vec![
+                ("new", p0),
+                ("new", p1),
+                ("fetch", p0),
+                ("evict", p1),
+                ("new", p2),
+                ("flush", p0),
+                ("delete", p2),
+            ]
+        );
+
+        pbpm.set_listener(None);
+        pbpm.fetch_page(p0).unwrap();
+        assert_eq!(events.0.lock().unwrap().len(), 7);
+    }
+
+    #[test]
+    fn read_only_test() {
+        {
+            let disk_manager = Arc::new(DiskManagerInstance::new("test_read_only"));
+            let pbpm = ParallelBufferPoolManager::<LRUReplacer, _>::new(1, 4, disk_manager);
+            for i in 0..4 {
+                let mut page_id = PageId(0);
+                pbpm.new_page(&mut page_id).unwrap().write().unwrap().0[0] = i + 1;
+                pbpm.unpin_page(page_id, true);
+            }
+            pbpm.flush_all_pages().unwrap();
+        }
+        let disk_manager = Arc::new(DiskManagerInstance::open_readonly("test_read_only").unwrap());
+        assert!(disk_manager.write_page(PageId(0), &[0; PAGE_SIZE]).is_err());
+        let pbpm = ParallelBufferPoolManager::<LRUReplacer, _>::read_only(1, 2, disk_manager);
+        for i in 0..4 {
+            let data = pbpm.fetch_page(PageId(i)).unwrap();
+            assert_eq!(data.read().unwrap().0[0], i as u8 + 1);
+            pbpm.unpin_page(PageId(i), false);
+        }
+        assert!(pbpm.new_page(&mut PageId(0)).is_none());
+        assert_eq!(pbpm.try_new_page(&mut PageId(0)).err(), Some(PoolPressure::ReadOnly));
+        assert!(matches!(pbpm.delete_page(PageId(3)), Err(BufferError::ReadOnly)));
+
+        // a page a caller dirtied anyway is never written back
+        pbpm.fetch_page(PageId(3)).unwrap().write().unwrap().0[0] = 0xff;
+        pbpm.unpin_page(PageId(3), true);
+        pbpm.fetch_page(PageId(0)).unwrap();
+        pbpm.unpin_page(PageId(0), false);
+        assert!(matches!(pbpm.try_fetch_page(PageId(1)), Err(BufferError::ReadOnly)));
+        std::fs::remove_file("test_read_only.db").unwrap();
+    }
+
+    #[test]
+    fn memory_usage_test() {
+        let disk_manager = Arc::new(InMemoryDiskManager::new());
+        let pbpm = ParallelBufferPoolManager::<LRUReplacer, _>::new(3, 4, disk_manager);
+        for i in 0..12 {
+            let mut page_id = PageId(i);
+            pbpm.new_page(&mut page_id).unwrap();
+            pbpm.unpin_page(page_id, false);
+        }
+        pbpm.delete_page(PageId(0)).unwrap();
+        let report = pbpm.memory_usage();
+        assert_eq!(report.page_buffers, 3 * 4 * PAGE_SIZE);
+        assert!(report.page_table > 0);
+        assert!(report.replacer > 0);
+        assert!(report.free_page_ids > 0);
+        assert_eq!(report.total(), report.page_buffers + report.overhead());
+    }
+
+    #[test]
+    fn maintenance_lock_test() {
+        let disk_manager = Arc::new(InMemoryDiskManager::new());
+        let pbpm = Arc::new(ParallelBufferPoolManager::<LRUReplacer, _>::new(
+            2,
+            2,
+            disk_manager,
+        ));
+        let mut page_id = PageId(1);
+        pbpm.new_page(&mut page_id).unwrap();
+        pbpm.unpin_page(page_id, true);
+
+        let mut guard = pbpm.maintenance_lock();
+        let fetched = Arc::new(AtomicBool::new(false));
+        let handle = {
+            let (pbpm, fetched) = (pbpm.clone(), fetched.clone());
+            thread::spawn(move || {
+                pbpm.fetch_page(page_id).unwrap();
+                fetched.store(true, Ordering::SeqCst);
+                pbpm.unpin_page(page_id, false);
+            })
+        };
+        thread::sleep(Duration::from_millis(50));
+        assert!(!fetched.load(Ordering::SeqCst));
+        // the routine works on the instances directly
+        assert_eq!(guard.num_instances(), 2);
+        guard.instance(1).flush_page(page_id).unwrap();
+        drop(guard);
+        handle.join().unwrap();
+        assert!(fetched.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn unpin_pages_test() {
+        let disk_manager = Arc::new(InMemoryDiskManager::new());
+        let page_ids: Vec<_> = (0..9).map(PageId).collect();
+        for page_id in &page_ids {
+            disk_manager.write_page(*page_id, &[0; PAGE_SIZE]).unwrap();
+        }
+        let pbpm = ParallelBufferPoolManager::<LRUReplacer, _>::new(3, 4, disk_manager);
+        // pinned twice, unpinned twice in one batch
+        for page_id in &page_ids {
+            pbpm.fetch_page(*page_id).unwrap();
+            pbpm.fetch_page(*page_id).unwrap();
+        }
+        let batch: Vec<_> = page_ids
+            .iter()
+            .flat_map(|page_id| [(*page_id, page_id.0 % 2 == 0), (*page_id, false)])
+            .collect();
+        pbpm.unpin_pages(&batch);
+        for index in 0..3 {
+            let instance = pbpm.lock_instance(index);
+            assert!(instance.frames.iter().all(|page| page.get_pin_count() == 0));
+            assert_eq!(instance.replacer.size(), 3);
+        }
+        for page_id in &page_ids {
+            let instance = pbpm.get_instance(*page_id);
+            let frame_id = instance.page_table[page_id];
+            assert_eq!(instance.frames[frame_id.0].is_dirty(), page_id.0 % 2 == 0);
+        }
+    }
+
+    #[test]
+    fn invariant_checks_test() {
+        let disk_manager = Arc::new(InMemoryDiskManager::new());
+        let pbpm = Arc::new(ParallelBufferPoolManager::<LRUReplacer, _>::new(
+            2,
+            3,
+            disk_manager,
+        ));
+        pbpm.set_invariant_checks(true);
+        let handles: Vec<_> = (0..4u32)
+            .map(|tid| {
+                let pbpm = pbpm.clone();
+                thread::spawn(move || {
+                    let mut page_ids = Vec::new();
+                    for i in 0..200u32 {
+                        let mut page_id = PageId(tid + i);
+                        if pbpm.new_page(&mut page_id).is_some() {
+                            pbpm.unpin_page(page_id, true);
+                            page_ids.push(page_id);
+                        }
+                        if page_ids.is_empty() {
+                            continue;
+                        }
+                        let page_id = page_ids[i as usize * 7 % page_ids.len()];
+                        if pbpm.fetch_page(page_id).is_some() {
+                            pbpm.unpin_page(page_id, i % 3 == 0);
+                        }
+                        if i % 5 == 0 {
+                            pbpm.delete_page(page_ids.swap_remove(0)).unwrap();
+                        }
+                    }
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+        pbpm.check_invariants();
+    }
+
+    #[test]
+    #[should_panic(expected = "both map to")]
+    fn invariant_checks_catch_shared_frame_test() {
+        let disk_manager = Arc::new(InMemoryDiskManager::new());
+        let mut bpm = BufferPoolManager::<LRUReplacer, _>::single(3, disk_manager);
+        let mut page_id = PageId(0);
+        bpm.new_page(&mut page_id).unwrap();
+        bpm.unpin_page(page_id, false);
+        bpm.check_invariants();
+        // as if a racy eviction left the old mapping behind
+        let frame_id = bpm.page_table[&page_id];
+        bpm.page_table.insert(PageId(42), frame_id);
+        bpm.check_invariants();
+    }
+
+    #[test]
+    fn new_page_blocking_backoff_test() {
+        let disk_manager = Arc::new(InMemoryDiskManager::new());
+        let pbpm = Arc::new(ParallelBufferPoolManager::<LRUReplacer, _>::new(1, 1, disk_manager));
+        let mut fastest = Duration::MAX;
+        for _ in 0..10 {
+            // the only frame is pinned until the waiter is about to start
+            let mut held = PageId(0);
+            pbpm.new_page(&mut held).unwrap();
+            let barrier = Arc::new(std::sync::Barrier::new(2));
+            let waiter = {
+                let (pbpm, barrier) = (pbpm.clone(), barrier.clone());
+                thread::spawn(move || {
+                    barrier.wait();
+                    let start = Instant::now();
+                    let mut page_id = PageId(0);
+                    pbpm.new_page_blocking(&mut page_id);
+                    let elapsed = start.elapsed();
+                    pbpm.unpin_page(page_id, false);
+                    elapsed
+                })
+            };
+            barrier.wait();
+            pbpm.unpin_page(held, false);
+            fastest = fastest.min(waiter.join().unwrap());
+        }
+        // a flat 1ms sleep per retry could never beat this
+        assert!(fastest < Duration::from_millis(1), "{:?}", fastest);
+    }
+
+    #[test]
+    fn instance_routing_test() {
+        let disk_manager = Arc::new(DiskManagerInstance::new("test_instance_routing"));
+        let pbpm = ParallelBufferPoolManager::<LRUReplacer, _>::new(5, 10, disk_manager);
+        assert_eq!(pbpm.instances.len(), 5);
+        let page_ids: Vec<PageId> = std::thread::scope(|s| {
+            let handles: Vec<_> = (0..4)
+                .map(|_| {
+                    s.spawn(|| {
+                        (0..10)
+                            .map(|_| {
+                                let mut page_id = PageId(0);
+                                pbpm.new_page(&mut page_id).unwrap();
+                                pbpm.unpin_page(page_id, false);
+                                page_id
+                            })
+                            .collect::<Vec<_>>()
+                    })
+                })
+                .collect();
+            handles.into_iter().flat_map(|handle| handle.join().unwrap()).collect()
+        });
+        let unique: HashSet<_> = page_ids.iter().collect();
+        assert_eq!(unique.len(), 40);
+        // a busy instance may have evicted some, fetching brings them back where they belong
+        for page_id in &page_ids {
+            pbpm.fetch_page(*page_id).unwrap();
+            let index = page_id.0 as usize % 5;
+            assert!(pbpm.lock_instance(index).page_table.contains_key(page_id));
+            pbpm.unpin_page(*page_id, false);
+        }
+        assert!(pbpm.misrouted_pages().is_empty());
+        std::fs::remove_file("test_instance_routing.db").unwrap();
+    }
+
+    #[test]
+    fn page_guard_test() {
+        let disk_manager = Arc::new(InMemoryDiskManager::new());
+        let pbpm = ParallelBufferPoolManager::<LRUReplacer, _>::new(2, 3, disk_manager.clone());
+        let pinned = || -> usize {
+            pbpm.instance_load()
+                .iter()
+                .map(|load| load.pinned_frames)
+                .sum()
+        };
+        let page_id = {
+            let (page_id, guard) = pbpm.new_page_guarded().unwrap();
+            guard.write().unwrap().0[0] = 7;
+            assert_eq!(pinned(), 1);
+            page_id
+        };
+        assert_eq!(pinned(), 0);
+        let is_dirty = |page_id: PageId| {
+            let instance = pbpm.get_instance(page_id);
+            let frame_id = instance.page_table[&page_id];
+            instance.frames[frame_id.0].is_dirty()
+        };
+        assert!(is_dirty(page_id));
+
+        // a page that is clean in the pool stays clean unless the guard is marked
+        let clean_page_id = PageId(100);
+        disk_manager.write_page(clean_page_id, &[7; PAGE_SIZE]).unwrap();
+        {
+            let first = pbpm.fetch_page_guarded(clean_page_id).unwrap();
+            assert_eq!(first.read().unwrap().0[0], 7);
+            assert_eq!(first.page_id(), clean_page_id);
+        }
+        assert!(!is_dirty(clean_page_id));
+        {
+            let first = pbpm.fetch_page_guarded(clean_page_id).unwrap();
+            let mut second = pbpm.fetch_page_guarded(clean_page_id).unwrap();
+            second.write().unwrap().0[0] = 8;
+            second.mark_dirty();
+            assert_eq!(pinned(), 1);
+        }
+        assert_eq!(pinned(), 0);
+        assert!(is_dirty(clean_page_id));
+    }
+
+    #[test]
+    fn read_write_page_guard_test() {
+        use crate::storage::pages::hash_table_directory_page::HashTableDirectoryPage;
+        let disk_manager = Arc::new(InMemoryDiskManager::new());
+        let pbpm = ParallelBufferPoolManager::<LRUReplacer, _>::new(2, 3, disk_manager);
+        let page_id = pbpm.new_page_guarded().unwrap().0;
+        {
+            let mut page = pbpm.fetch_page_write(page_id).unwrap();
+            let dir: &mut HashTableDirectoryPage = page.as_mut();
+            dir.set_global_depth(3);
+            assert_eq!(page.as_ref::<HashTableDirectoryPage>().get_global_depth(), 3);
+        }
+        {
+            let first = pbpm.fetch_page_read(page_id).unwrap();
+            // readers share the lock
+            let second = pbpm.fetch_page_read(page_id).unwrap();
+            assert_eq!(first.as_ref::<HashTableDirectoryPage>().get_global_depth(), 3);
+            assert_eq!(second.page_id(), page_id);
+            let instance = pbpm.get_instance(page_id);
+            let frame_id = instance.page_table[&page_id];
+            assert_eq!(instance.frames[frame_id.0].get_pin_count(), 2);
+            assert!(instance.frames[frame_id.0].is_dirty());
+        }
+        // would block if a guard had kept its lock
+        drop(pbpm.fetch_page(page_id).unwrap().write().unwrap());
+        pbpm.unpin_page(page_id, false);
+        let instance = pbpm.get_instance(page_id);
+        let frame_id = instance.page_table[&page_id];
+        assert_eq!(instance.frames[frame_id.0].get_pin_count(), 0);
+    }
+
+    #[test]
+    fn stats_test() {
+        let disk_manager = Arc::new(InMemoryDiskManager::new());
+        let pbpm = ParallelBufferPoolManager::<LRUReplacer, _>::new(2, 3, disk_manager.clone());
+        let page_id = PageId(3);
+        disk_manager.write_page(page_id, &[1; PAGE_SIZE]).unwrap();
+        pbpm.fetch_page(page_id).unwrap();
+        pbpm.unpin_page(page_id, true);
+        pbpm.fetch_page(page_id).unwrap();
+        pbpm.unpin_page(page_id, false);
+        assert_eq!(
+            pbpm.stats(),
+            BpmStats {
+                hits: 1,
+                misses: 1,
+                flushes: 0,
+            }
+        );
+        pbpm.flush_page(page_id).unwrap();
+        assert_eq!(pbpm.stats().flushes, 1);
+    }
+
+    #[test]
+    fn prefetch_test() {
+        let disk_manager = Arc::new(InMemoryDiskManager::new());
+        let pbpm = ParallelBufferPoolManager::<LRUReplacer, _>::new(2, 3, disk_manager.clone());
+        for i in 0..6 {
+            disk_manager.write_page(PageId(i), &[i as u8; PAGE_SIZE]).unwrap();
+        }
+        let resident = PageId(1);
+        pbpm.fetch_page(resident).unwrap();
+        pbpm.prefetch(&(0..6).map(PageId).collect::<Vec<_>>());
+        // the resident page was skipped, and nothing stays pinned but it
+        assert_eq!(pbpm.stats().misses, 6);
+        let pinned: usize = pbpm.instance_load().iter().map(|load| load.pinned_frames).sum();
+        assert_eq!(pinned, 1);
+        pbpm.unpin_page(resident, false);
+
+        for i in 0..6 {
+            let data = pbpm.fetch_page(PageId(i)).unwrap();
+            assert_eq!(data.read().unwrap().0[0], i as u8);
+            pbpm.unpin_page(PageId(i), false);
+        }
+        assert_eq!(pbpm.stats().misses, 6);
+        assert_eq!(pbpm.stats().hits, 6);
+    }
+
+    #[test]
+    fn single_instance_surface_test() {
+        let disk_manager = Arc::new(InMemoryDiskManager::new());
+        let mut bpm = BufferPoolManager::<LRUReplacer, _>::single(2, disk_manager.clone());
+        let mut page_id = PageId(0);
+        bpm.new_page(&mut page_id).unwrap().write().unwrap().0[0] = 5;
+        bpm.unpin_page(page_id, true);
+        bpm.flush_all_pages().unwrap();
+        let mut buf = [0; PAGE_SIZE];
+        disk_manager.read_page(page_id, &mut buf).unwrap();
+        assert_eq!(buf[0], 5);
+
+        assert_eq!(bpm.try_fetch_page(page_id).unwrap().read().unwrap().0[0], 5);
+        bpm.unpin_page(page_id, false);
+        assert_eq!(
+            bpm.stats(),
+            BpmStats {
+                hits: 1,
+                misses: 0,
+                flushes: 1,
+            }
+        );
+        bpm.delete_page(page_id).unwrap();
+        assert!(bpm.free_page_ids().contains(&page_id));
+    }
+
+    #[test]
+    fn delete_dirty_page_test() {
+        let disk_manager = Arc::new(InMemoryDiskManager::new());
+        let mut bpm = BufferPoolManager::<LRUReplacer, _>::single(2, disk_manager.clone());
+        let mut page_id = PageId(0);
+        bpm.new_page(&mut page_id).unwrap().write().unwrap().0[0] = 9;
+        assert!(matches!(bpm.delete_page(page_id), Err(BufferError::Pinned)));
+        assert!(bpm.page_table.contains_key(&page_id));
+        bpm.unpin_page(page_id, true);
+        let frame_id = bpm.page_table[&page_id];
+        assert!(bpm.delete_page(page_id).unwrap());
+        assert!(!bpm.delete_page(page_id).unwrap());
+
+        let frame = &bpm.frames[frame_id.0];
+        assert_eq!(frame.get_page_id(), None);
+        assert!(!frame.is_dirty());
+        assert!(!bpm.replacer.contains(frame_id));
+        assert_eq!(bpm.replacer.victim(), None);
+        // the deleted page is not written back under its old id
+        bpm.flush_all_pages().unwrap();
+        assert_eq!(bpm.stats().flushes, 0);
+        let mut buf = [1; PAGE_SIZE];
+        disk_manager.read_page(page_id, &mut buf).unwrap();
+        assert_eq!(buf[0], 0);
+    }
+
+    #[test]
+    fn fetch_page_blocking_test() {
+        let disk_manager = Arc::new(InMemoryDiskManager::new());
+        let pbpm = Arc::new(ParallelBufferPoolManager::<LRUReplacer, _>::new(1, 2, disk_manager));
+        let mut page_ids = Vec::new();
+        for _ in 0..2 {
+            let mut page_id = PageId(0);
+            pbpm.new_page(&mut page_id).unwrap();
+            page_ids.push(page_id);
+        }
+        let page_id = PageId(10);
+        assert!(pbpm.fetch_page(page_id).is_none());
+
+        let unpinner = {
+            let pbpm = pbpm.clone();
+            let pinned = page_ids[0];
+            thread::spawn(move || {
+                thread::sleep(Duration::from_millis(20));
+                pbpm.unpin_page(pinned, false);
+            })
+        };
+        pbpm.fetch_page_blocking(page_id).unwrap();
+        unpinner.join().unwrap();
+        assert_eq!(pbpm.get_instance(page_id).page_table.len(), 2);
+        assert!(pbpm.get_instance(page_id).page_table.contains_key(&page_id));
+
+        // a disk error isn't waited out
+        let disk_manager = Arc::new(FaultyDiskManager::new(InMemoryDiskManager::new()));
+        let pbpm = ParallelBufferPoolManager::<LRUReplacer, _>::new(1, 2, disk_manager.clone());
+        disk_manager.fail_nth_read(1);
+        assert!(matches!(
+            pbpm.fetch_page_blocking(PageId(3)),
+            Err(BufferError::Io(_))
+        ));
+        pbpm.fetch_page_blocking(PageId(3)).unwrap();
+    }
+
+    #[test]
+    fn resize_grow_test() {
+        let disk_manager = Arc::new(InMemoryDiskManager::new());
+        let mut bpm = BufferPoolManager::<LRUReplacer, _>::single(2, disk_manager);
+        bpm.set_invariant_checks(true);
+        let mut page_ids = Vec::new();
+        for i in 0..2 {
+            let mut page_id = PageId(0);
+            bpm.new_page(&mut page_id).unwrap().write().unwrap().0[0] = i + 1;
+            page_ids.push(page_id);
+        }
+        assert!(bpm.new_page(&mut PageId(0)).is_none());
+
+        // the pinned pages keep their buffers
+        let data = bpm.fetch_page(page_ids[0]).unwrap();
+        bpm.resize(4).unwrap();
+        for _ in 0..2 {
+            let mut page_id = PageId(0);
+            bpm.new_page(&mut page_id).unwrap();
+            page_ids.push(page_id);
+        }
+        assert!(bpm.new_page(&mut PageId(0)).is_none());
+        assert_eq!(data.read().unwrap().0[0], 1);
+        for page_id in &page_ids {
+            bpm.unpin_page(*page_id, true);
+        }
+        bpm.unpin_page(page_ids[0], false);
+        // the new frames take part in eviction
+        for _ in 0..4 {
+            let mut page_id = PageId(0);
+            bpm.new_page(&mut page_id).unwrap();
+        }
+        assert_eq!(bpm.load(0).resident_pages, 4);
+    }
+
+    #[test]
+    fn resize_shrink_test() {
+        let disk_manager = Arc::new(InMemoryDiskManager::new());
+        let mut bpm = BufferPoolManager::<LRUReplacer, _>::single(4, disk_manager.clone());
+        bpm.set_invariant_checks(true);
+        let mut page_ids = Vec::new();
+        for i in 0..4 {
+            let mut page_id = PageId(0);
+            bpm.new_page(&mut page_id).unwrap().write().unwrap().0[0] = i + 1;
+            page_ids.push(page_id);
+        }
+        for page_id in &page_ids[1..] {
+            bpm.unpin_page(*page_id, true);
+        }
+        // the first page went to the last frame, the free list hands frames out from the back
+        assert_eq!(bpm.page_table[&page_ids[0]], FrameId(3));
+        assert!(matches!(bpm.resize(2), Err(BufferError::NoFreeFrame)));
+        assert_eq!(bpm.load(0).resident_pages, 4);
+
+        bpm.unpin_page(page_ids[0], true);
+        bpm.resize(2).unwrap();
+        assert_eq!(bpm.load(0).resident_pages, 2);
+        assert_eq!(bpm.load(0).free_frames, 0);
+        // the evicted pages were written back and come back through the smaller pool
+        for (i, page_id) in page_ids.iter().enumerate() {
+            let data = bpm.fetch_page(*page_id).unwrap();
+            assert_eq!(data.read().unwrap().0[0], i as u8 + 1);
+            bpm.unpin_page(*page_id, false);
+        }
+        assert!(bpm.page_table.values().all(|frame_id| frame_id.0 < 2));
+        bpm.resize(3).unwrap();
+        assert_eq!(bpm.load(0).free_frames, 1);
+    }
+
+    #[test]
+    fn fetch_page_timeout_test() {
+        let disk_manager = Arc::new(InMemoryDiskManager::new());
+        let pbpm = Arc::new(ParallelBufferPoolManager::<LRUReplacer, _>::new(1, 2, disk_manager));
+        for i in 0..2 {
+            pbpm.new_page(&mut PageId(i)).unwrap();
+        }
+        let page_id = PageId(10);
+        let start = Instant::now();
+        let result = pbpm.fetch_page_timeout(page_id, Duration::from_millis(50));
+        assert!(result.unwrap().is_none());
+        let elapsed = start.elapsed();
+        assert!(elapsed >= Duration::from_millis(50) && elapsed < Duration::from_secs(5));
+
+        let unpinner = {
+            let pbpm = pbpm.clone();
+            thread::spawn(move || {
+                thread::sleep(Duration::from_millis(20));
+                pbpm.unpin_page(PageId(0), false);
+            })
+        };
+        let result = pbpm.fetch_page_timeout(page_id, Duration::from_secs(5));
+        assert!(result.unwrap().is_some());
+        unpinner.join().unwrap();
+
+        // a disk error isn't retried until the deadline
+        let disk_manager = Arc::new(FaultyDiskManager::new(InMemoryDiskManager::new()));
+        let pbpm = ParallelBufferPoolManager::<LRUReplacer, _>::new(1, 2, disk_manager.clone());
+        disk_manager.fail_nth_read(1);
+        let start = Instant::now();
+        assert!(matches!(
+            pbpm.fetch_page_timeout(page_id, Duration::from_secs(5)),
+            Err(BufferError::Io(_))
+        ));
+        assert!(start.elapsed() < Duration::from_secs(1));
+        let result = pbpm.fetch_page_timeout(page_id, Duration::from_secs(5));
+        assert!(result.unwrap().is_some());
+    }
+
+    #[test]
+    fn fetch_pages_test() {
+        let disk_manager = Arc::new(InMemoryDiskManager::new());
+        let page_ids: Vec<_> = [7, 2, 9, 4, 3, 2].into_iter().map(PageId).collect();
+        for page_id in &page_ids {
+            disk_manager.write_page(*page_id, &[page_id.0 as u8; PAGE_SIZE]).unwrap();
+        }
+        let pbpm = ParallelBufferPoolManager::<LRUReplacer, _>::new(3, 2, disk_manager);
+        let pages = pbpm.fetch_pages(&page_ids);
+        assert_eq!(pages.len(), page_ids.len());
+        for (page, page_id) in pages.iter().zip(&page_ids) {
+            assert_eq!(page.as_ref().unwrap().read().unwrap().0[0], page_id.0 as u8);
+        }
+        // page 2 was pinned twice
+        let instance = pbpm.get_instance(PageId(2));
+        assert_eq!(instance.frames[instance.page_table[&PageId(2)].0].get_pin_count(), 2);
+        drop(instance);
+
+        // instance 0 has only two frames for 9, 3 and 6
+        let pages = pbpm.fetch_pages(&[PageId(6), PageId(8)]);
+        assert!(pages[0].is_none());
+        assert_eq!(pages[1].as_ref().unwrap().read().unwrap().0[0], 0);
+    }
+
+    #[test]
+    fn flush_only_dirty_test() {
+        let disk_manager = Arc::new(RecordingDiskManager::new(InMemoryDiskManager::new()));
+        let pbpm = ParallelBufferPoolManager::<LRUReplacer, _>::new(3, 10, disk_manager.clone());
+        pbpm.set_invariant_checks(true);
+        let mut page_ids = Vec::new();
+        for _ in 0..30 {
+            let mut page_id = PageId(0);
+            pbpm.new_page(&mut page_id).unwrap();
+            pbpm.unpin_page(page_id, false);
+            page_ids.push(page_id);
+        }
+        pbpm.flush_all_pages().unwrap();
+        assert_eq!(take_writes(&disk_manager).len(), 30);
+        pbpm.flush_all_pages().unwrap();
+        assert!(take_writes(&disk_manager).is_empty());
+
+        for page_id in [page_ids[4], page_ids[17]] {
+            pbpm.fetch_page(page_id).unwrap();
+            pbpm.unpin_page(page_id, true);
+        }
+        pbpm.flush_all_pages().unwrap();
+        let mut writes = take_writes(&disk_manager);
+        writes.sort_by_key(|page_id| page_id.0);
+        assert_eq!(writes, vec![page_ids[4], page_ids[17]]);
+    }
+
+    #[test]
+    fn parallel_flush_test() {
+        let disk_manager = Arc::new(RecordingDiskManager::new(InMemoryDiskManager::new()));
+        let pbpm = ParallelBufferPoolManager::<LRUReplacer, _>::new(4, 8, disk_manager.clone());
+        let mut page_ids = Vec::new();
+        for i in 0..24 {
+            let mut page_id = PageId(0);
+            pbpm.new_page(&mut page_id).unwrap().write().unwrap().0[0] = i + 1;
+            if i % 3 == 0 {
+                pbpm.set_page_lsn(page_id, Lsn(100 - i as u32));
+            }
+            pbpm.unpin_page(page_id, true);
+            page_ids.push(page_id);
+        }
+        pbpm.flush_all_pages().unwrap();
+        let mut written = take_writes(&disk_manager);
+        written.sort_by_key(|page_id| page_id.0);
+        let mut expected = page_ids.clone();
+        expected.sort_by_key(|page_id| page_id.0);
+        assert_eq!(written, expected);
+        let mut buf = [0; PAGE_SIZE];
+        for (i, page_id) in page_ids.iter().enumerate() {
+            disk_manager.read_page(*page_id, &mut buf).unwrap();
+            assert_eq!(buf[0], i as u8 + 1);
+        }
+    }
+
+    #[test]
+    fn bad_unpin_test() {
+        let disk_manager = Arc::new(InMemoryDiskManager::new());
+        let pbpm = ParallelBufferPoolManager::<LRUReplacer, _>::new(2, 2, disk_manager);
+        pbpm.set_invariant_checks(true);
+        assert!(!pbpm.unpin_page(PageId(7), true));
+
+        let mut page_id = PageId(0);
+        pbpm.new_page(&mut page_id).unwrap();
+        pbpm.flush_page(page_id).unwrap();
+        assert!(pbpm.unpin_page(page_id, false));
+        // a second unpin neither marks the page dirty nor makes it evictable twice
+        assert!(!pbpm.unpin_page(page_id, true));
+        let instance = pbpm.get_instance(page_id);
+        let frame_id = instance.page_table[&page_id];
+        assert_eq!(instance.frames[frame_id.0].get_pin_count(), 0);
+        assert!(!instance.frames[frame_id.0].is_dirty());
+        assert_eq!(instance.replacer.size(), 1);
+    }
+
+    #[test]
+    fn page_router_test() {
+        // blocks of 4 ids per instance, which Striding knows nothing about
+        struct Blocks;
+
+        impl PageRouter for Blocks {
+            fn route(&self, page_id: PageId, num_instances: usize) -> usize {
+                page_id.0 as usize / 4 % num_instances
+            }
+        }
+
+        let disk_manager = Arc::new(InMemoryDiskManager::new());
+        let pbpm = ParallelBufferPoolManager::<LRUReplacer, _>::with_router(
+            3,
+            8,
+            disk_manager,
+            Arc::new(Blocks),
+        );
+        pbpm.set_invariant_checks(true);
+        let mut page_ids = Vec::new();
+        for _ in 0..12 {
+            let mut page_id = PageId(0);
+            pbpm.new_page(&mut page_id).unwrap();
+            pbpm.unpin_page(page_id, true);
+            page_ids.push(page_id);
+        }
+        let resident_in = |page_id: PageId| {
+            (0..3).find(|index| pbpm.lock_instance(*index).page_table.contains_key(&page_id))
+        };
+        for page_id in &page_ids {
+            assert_eq!(resident_in(*page_id), Some(Blocks.route(*page_id, 3)));
+        }
+
+        // deleted ids come back, in the same instance
+        for page_id in &page_ids[..6] {
+            assert!(pbpm.delete_page(*page_id).unwrap());
+        }
+        for _ in 0..6 {
+            let mut page_id = PageId(0);
+            pbpm.new_page(&mut page_id).unwrap();
+            pbpm.unpin_page(page_id, false);
+            assert!(page_ids.contains(&page_id));
+            assert_eq!(resident_in(page_id), Some(Blocks.route(page_id, 3)));
+        }
+        assert!(pbpm.misrouted_pages().is_empty());
+        pbpm.flush_all_pages().unwrap();
+        for page_id in &page_ids {
+            assert!(pbpm.lock_instance(Blocks.route(*page_id, 3)).evict_page(*page_id).unwrap());
+            pbpm.fetch_page(*page_id).unwrap();
+            pbpm.unpin_page(*page_id, false);
+            assert_eq!(resident_in(*page_id), Some(Blocks.route(*page_id, 3)));
+        }
+    }
+
+    #[test]
+    fn pin_count_test() {
+        let disk_manager = Arc::new(InMemoryDiskManager::new());
+        let pbpm = ParallelBufferPoolManager::<LRUReplacer, _>::new(2, 1, disk_manager);
+        let page_id = PageId(3);
+        assert!(!pbpm.is_resident(page_id));
+        assert_eq!(pbpm.pin_count(page_id), None);
+
+        pbpm.fetch_page(page_id).unwrap();
+        assert!(pbpm.is_resident(page_id));
+        assert_eq!(pbpm.pin_count(page_id), Some(1));
+        pbpm.fetch_page(page_id).unwrap();
+        assert_eq!(pbpm.pin_count(page_id), Some(2));
+        pbpm.unpin_pages(&[(page_id, false), (page_id, false)]);
+        assert_eq!(pbpm.pin_count(page_id), Some(0));
+
+        // the only frame of the instance goes to another page
+        pbpm.fetch_page(PageId(5)).unwrap();
+        assert!(!pbpm.is_resident(page_id));
+    }
+
+    #[test]
+    fn checkpoint_test() {
+        let disk_manager = Arc::new(RecordingDiskManager::new(InMemoryDiskManager::new()));
+        let pbpm = ParallelBufferPoolManager::<LRUReplacer, _>::new(3, 4, disk_manager.clone());
+        for _ in 0..9 {
+            let mut page_id = PageId(0);
+            pbpm.new_page(&mut page_id).unwrap();
+            pbpm.unpin_page(page_id, true);
+        }
+        pbpm.checkpoint().unwrap();
+        assert_eq!(take_writes(&disk_manager).len(), 9);
+        assert_eq!(disk_metric(disk_manager.inner(), "disk_syncs"), 1.0);
+    }
+}
@@ -0,0 +1,245 @@
+use crate::storage::disk::disk_manager::PAGE_SIZE;
+use crate::storage::pages::page::Align4096;
+use std::cell::UnsafeCell;
+use std::ops::{Deref, DerefMut};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{
+    Arc, LockResult, Mutex, OnceLock, PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard,
+    TryLockError, TryLockResult,
+};
+
+// How often an arena can grow.
+pub const MAX_CHUNKS: usize = 64;
+
+// The page buffers of a whole pool in one allocation, so every frame starts on a page boundary
+// (as O_DIRECT wants) and neighbouring frames sit next to each other. Each buffer has its own
+// lock, kept apart from the buffers so they stay densely packed. Growing appends another
+// allocation, the buffers already handed out never move.
+pub struct FrameArena {
+    chunks: [OnceLock<Chunk>; MAX_CHUNKS],
+    // frames in the chunks set so far
+    len: AtomicUsize,
+    // held while adding a chunk
+    growing: Mutex<()>,
+}
+
+struct Chunk {
+    // index of the chunk's first frame
+    start: usize,
+    buffers: Box<[UnsafeCell<Align4096>]>,
+    locks: Box<[RwLock<()>]>,
+}
+
+// A buffer is only reached through its lock.
+unsafe impl Sync for FrameArena {}
+
+impl FrameArena {
+    pub fn new(num_frames: usize) -> Arc<Self> {
+        let arena = Arc::new(Self {
+            chunks: [(); MAX_CHUNKS].map(|_| OnceLock::new()),
+            len: AtomicUsize::new(0),
+            growing: Mutex::new(()),
+        });
+        arena.grow(num_frames);
+        arena
+    }
+
+    pub fn len(&self) -> usize {
+        self.len.load(Ordering::Acquire)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    // Adds num_frames zeroed buffers after the existing ones. Panics once the arena is made of
+    // MAX_CHUNKS allocations.
+    pub fn grow(&self, num_frames: usize) {
+        if num_frames == 0 {
+            return;
+        }
+        let _growing = self.growing.lock().unwrap();
+        let start = self.len();
+        let slot = self
+            .chunks
+            .iter()
+            .find(|chunk| chunk.get().is_none())
+            .expect("arena grown too often");
+        let _ = slot.set(Chunk {
+            start,
+            buffers: (0..num_frames)
+                .map(|_| UnsafeCell::new(Align4096([0; PAGE_SIZE])))
+                .collect(),
+            locks: (0..num_frames).map(|_| RwLock::new(())).collect(),
+        });
+        self.len.store(start + num_frames, Ordering::Release);
+    }
+
+    // The buffer at index and its lock. Chunks are set in order, so the last set one that
+    // starts at or before index holds it.
+    fn slot(&self, index: usize) -> (&UnsafeCell<Align4096>, &RwLock<()>) {
+        assert!(index < self.len(), "frame {} out of {}", index, self.len());
+        let chunk = self
+            .chunks
+            .iter()
+            .map_while(OnceLock::get)
+            .take_while(|chunk| chunk.start <= index)
+            .last()
+            .unwrap();
+        let offset = index - chunk.start;
+        (&chunk.buffers[offset], &chunk.locks[offset])
+    }
+
+    // Locks buffer index for as long as the arena is borrowed, without a FrameData to borrow.
+    pub fn read(&self, index: usize) -> LockResult<FrameReadGuard<'_>> {
+        let (buffer, lock) = self.slot(index);
+        let data = unsafe { &*buffer.get() };
+        map_guard(lock.read(), |lock| FrameReadGuard { _lock: lock, data })
+    }
+
+    // Like read, but fails with WouldBlock instead of waiting for a writer.
+    pub fn try_read(&self, index: usize) -> TryLockResult<FrameReadGuard<'_>> {
+        let (buffer, lock) = self.slot(index);
+        let data = unsafe { &*buffer.get() };
+        let wrap = |lock| FrameReadGuard { _lock: lock, data };
+        match lock.try_read() {
+            Ok(lock) => Ok(wrap(lock)),
+            Err(TryLockError::Poisoned(err)) => Err(TryLockError::Poisoned(PoisonError::new(
+                wrap(err.into_inner()),
+            ))),
+            Err(TryLockError::WouldBlock) => Err(TryLockError::WouldBlock),
+        }
+    }
+
+    pub fn write(&self, index: usize) -> LockResult<FrameWriteGuard<'_>> {
+        let (buffer, lock) = self.slot(index);
+        // only made once the write lock is held
+        let wrap = |lock| FrameWriteGuard {
+            _lock: lock,
+            data: unsafe { &mut *buffer.get() },
+        };
+        map_guard(lock.write(), wrap)
+    }
+
+    pub fn frame(self: &Arc<Self>, index: usize) -> FrameData {
+        self.slot(index);
+        FrameData {
+            arena: self.clone(),
+            index,
+        }
+    }
+}
+
+// A handle on one buffer of an arena, locked like a RwLock<Align4096>.
+#[derive(Clone)]
+pub struct FrameData {
+    arena: Arc<FrameArena>,
+    index: usize,
+}
+
+impl FrameData {
+    pub fn read(&self) -> LockResult<FrameReadGuard<'_>> {
+        self.arena.read(self.index)
+    }
+
+    pub fn try_read(&self) -> TryLockResult<FrameReadGuard<'_>> {
+        self.arena.try_read(self.index)
+    }
+
+    pub fn write(&self) -> LockResult<FrameWriteGuard<'_>> {
+        self.arena.write(self.index)
+    }
+
+    // which buffer of its arena this is
+    pub fn index(&self) -> usize {
+        self.index
+    }
+
+    // where the buffer lives, for checking alignment
+    pub fn as_ptr(&self) -> *const u8 {
+        self.arena.slot(self.index).0.get() as *const u8
+    }
+}
+
+fn map_guard<G, T>(result: LockResult<G>, f: impl FnOnce(G) -> T) -> LockResult<T> {
+    match result {
+        Ok(guard) => Ok(f(guard)),
+        Err(err) => Err(PoisonError::new(f(err.into_inner()))),
+    }
+}
+
+pub struct FrameReadGuard<'a> {
+    _lock: RwLockReadGuard<'a, ()>,
+    data: &'a Align4096,
+}
+
+impl Deref for FrameReadGuard<'_> {
+    type Target = Align4096;
+
+    fn deref(&self) -> &Self::Target {
+        self.data
+    }
+}
+
+pub struct FrameWriteGuard<'a> {
+    _lock: RwLockWriteGuard<'a, ()>,
+    data: &'a mut Align4096,
+}
+
+impl Deref for FrameWriteGuard<'_> {
+    type Target = Align4096;
+
+    fn deref(&self) -> &Self::Target {
+        self.data
+    }
+}
+
+impl DerefMut for FrameWriteGuard<'_> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        self.data
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::storage::pages::page::Page;
+
+    #[test]
+    fn frame_arena_test() {
+        let arena = FrameArena::new(4);
+        let base = arena.frame(0).as_ptr() as usize;
+        for i in 0..4 {
+            let frame = arena.frame(i);
+            assert_eq!(frame.as_ptr() as usize % 4096, 0);
+            assert_eq!(frame.as_ptr() as usize, base + i * PAGE_SIZE);
+        }
+
+        let mut page = Page::in_arena(&arena, 2);
+        let ptr = page.get_data().as_ptr();
+        page.get_data().write().unwrap()[0] = 7;
+        assert_eq!(arena.frame(2).read().unwrap()[0], 7);
+        page.reset_data();
+        assert_eq!(page.get_data().as_ptr(), ptr);
+        assert_eq!(page.get_data().read().unwrap()[0], 0);
+    }
+
+    #[test]
+    fn grow_test() {
+        let arena = FrameArena::new(2);
+        let frame = arena.frame(1);
+        let ptr = frame.as_ptr();
+        frame.write().unwrap()[0] = 3;
+        arena.grow(3);
+        assert_eq!(arena.len(), 5);
+        // the old buffers stay put, the new ones are zeroed and aligned
+        assert_eq!(frame.as_ptr(), ptr);
+        assert_eq!(frame.read().unwrap()[0], 3);
+        for i in 2..5 {
+            assert_eq!(arena.frame(i).as_ptr() as usize % 4096, 0);
+            assert_eq!(arena.read(i).unwrap()[0], 0);
+        }
+        arena.write(4).unwrap()[0] = 9;
+        assert_eq!(arena.frame(4).read().unwrap()[0], 9);
+    }
+}
@@ -1,2 +1,7 @@
+pub mod backoff;
 pub mod buffer_pool_manager;
+pub mod frame_arena;
+pub mod page_event_listener;
+pub mod page_id_allocator;
+pub mod page_router;
 pub mod replacer;
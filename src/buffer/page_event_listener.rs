@@ -0,0 +1,19 @@
+use crate::buffer::replacer::PageId;
+
+// Told about the life of pages in a buffer pool, e.g. to keep a cache layer coherent or to
+// collect metrics. Called with the instance lock held, right after the event, so keep the
+// calls cheap and don't call back into the pool. Every method defaults to doing nothing.
+pub trait PageEventListener: Send + Sync {
+    // the page was pinned by a fetch, whether it was resident or read from disk
+    fn on_fetch(&self, page_id: PageId) {}
+
+    // the page left the pool, after being written back if dirty
+    fn on_evict(&self, page_id: PageId) {}
+
+    fn on_new(&self, page_id: PageId) {}
+
+    fn on_delete(&self, page_id: PageId) {}
+
+    // the page was written back by a flush, write-backs on eviction only count as on_evict
+    fn on_flush(&self, page_id: PageId) {}
+}
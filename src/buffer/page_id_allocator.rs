@@ -0,0 +1,115 @@
+use crate::buffer::replacer::PageId;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Mutex;
+
+// Hands out ids for new pages. The page is always created in the instance the pool's
+// PageRouter routes its id to, which is also where it is looked up later.
+pub trait PageIdAllocator: Send + Sync {
+    // instance_index is the instance the caller would like the page to live in; strategies
+    // are free to ignore it.
+    fn allocate(&self, instance_index: usize, num_instances: usize) -> PageId;
+
+    // Makes the id of a deleted (or never used) page available again.
+    fn deallocate(&self, page_id: PageId);
+
+    // Ids given back by deallocate and not handed out again yet.
+    fn free_page_ids(&self) -> Vec<PageId>;
+}
+
+// Every instance owns the ids congruent to its index, so with the Modulo router a page always
+// lands in the instance it was asked for. The ids depend on the number of instances.
+#[derive(Debug, Default)]
+pub struct Striding {
+    next_page_ids: Mutex<HashMap<usize, u32>>,
+    free_page_ids: Mutex<Vec<PageId>>,
+}
+
+impl PageIdAllocator for Striding {
+    fn allocate(&self, instance_index: usize, num_instances: usize) -> PageId {
+        let mut free_page_ids = self.free_page_ids.lock().unwrap();
+        if let Some(position) = free_page_ids
+            .iter()
+            .rposition(|page_id| page_id.0 as usize % num_instances == instance_index)
+        {
+            return free_page_ids.remove(position);
+        }
+        let mut next_page_ids = self.next_page_ids.lock().unwrap();
+        let next_page_id = next_page_ids
+            .entry(instance_index)
+            .or_insert(instance_index as u32);
+        let page_id = PageId(*next_page_id);
+        *next_page_id += num_instances as u32;
+        page_id
+    }
+
+    fn deallocate(&self, page_id: PageId) {
+        self.free_page_ids.lock().unwrap().push(page_id);
+    }
+
+    fn free_page_ids(&self) -> Vec<PageId> {
+        self.free_page_ids.lock().unwrap().clone()
+    }
+}
+
+// Dense ids shared by all instances, so a db file can be reopened with any number of
+// instances. Start it after the pages already in the file.
+#[derive(Debug, Default)]
+pub struct Sequential {
+    next_page_id: AtomicU32,
+    free_page_ids: Mutex<Vec<PageId>>,
+}
+
+impl Sequential {
+    pub fn new(next_page_id: u32) -> Self {
+        Self {
+            next_page_id: AtomicU32::new(next_page_id),
+            free_page_ids: Mutex::new(Vec::new()),
+        }
+    }
+}
+
+impl PageIdAllocator for Sequential {
+    fn allocate(&self, _instance_index: usize, _num_instances: usize) -> PageId {
+        if let Some(page_id) = self.free_page_ids.lock().unwrap().pop() {
+            return page_id;
+        }
+        PageId(self.next_page_id.fetch_add(1, Ordering::Relaxed))
+    }
+
+    fn deallocate(&self, page_id: PageId) {
+        self.free_page_ids.lock().unwrap().push(page_id);
+    }
+
+    fn free_page_ids(&self) -> Vec<PageId> {
+        self.free_page_ids.lock().unwrap().clone()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn striding_test() {
+        let allocator = Striding::default();
+        assert_eq!(allocator.allocate(1, 3), PageId(1));
+        assert_eq!(allocator.allocate(1, 3), PageId(4));
+        assert_eq!(allocator.allocate(0, 3), PageId(0));
+        allocator.deallocate(PageId(1));
+        // a freed id is only reused by the instance it routes to
+        assert_eq!(allocator.allocate(2, 3), PageId(2));
+        assert_eq!(allocator.allocate(1, 3), PageId(1));
+        assert_eq!(allocator.allocate(1, 3), PageId(7));
+    }
+
+    #[test]
+    fn sequential_test() {
+        let allocator = Sequential::new(5);
+        assert_eq!(allocator.allocate(1, 3), PageId(5));
+        assert_eq!(allocator.allocate(1, 3), PageId(6));
+        allocator.deallocate(PageId(5));
+        assert_eq!(allocator.allocate(0, 2), PageId(5));
+        assert_eq!(allocator.allocate(0, 2), PageId(7));
+    }
+}
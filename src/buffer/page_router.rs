@@ -0,0 +1,19 @@
+use crate::buffer::replacer::PageId;
+
+// Picks the instance of a parallel buffer pool a page lives in. Every lookup of the page goes
+// through it, so it has to give the same answer for an id as long as the pool lives, also
+// after the page was deleted and its id reused.
+pub trait PageRouter: Send + Sync {
+    // an index below num_instances
+    fn route(&self, page_id: PageId, num_instances: usize) -> usize;
+}
+
+// page_id % num_instances, which Striding allocates ids for.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Modulo;
+
+impl PageRouter for Modulo {
+    fn route(&self, page_id: PageId, num_instances: usize) -> usize {
+        page_id.0 as usize % num_instances
+    }
+}
@@ -1,5 +1,6 @@
-use std::collections::linked_list::CursorMut;
-use std::collections::LinkedList;
+use std::collections::{BTreeMap, VecDeque};
+use std::mem::size_of;
+use std::sync::Mutex;
 
 #[derive(Clone, Copy, PartialEq, Debug)]
 pub struct FrameId(pub(crate) usize);
@@ -7,63 +8,958 @@ pub struct FrameId(pub(crate) usize);
 #[derive(Clone, Copy, PartialEq, Debug, Eq, Hash,Default)]
 pub struct PageId(pub(crate) u32);
 
+impl PageId {
+    pub fn new(page_id: u32) -> Self {
+        PageId(page_id)
+    }
+}
+
 pub trait Replacer {
-    fn new(pool_size: usize) -> Self;
+    fn new(pool_size: usize) -> Self
+    where
+        Self: Sized;
     fn victim(&mut self) -> Option<FrameId>;
 
+    // Up to n victims at once, in the order victim would return them, e.g. to free many
+    // frames when the pool shrinks. Fewer if fewer frames are evictable.
+    fn victims(&mut self, n: usize) -> Vec<FrameId> {
+        (0..n).map_while(|_| self.victim()).collect()
+    }
+
     fn pin(&mut self, frame_id: FrameId);
 
     fn unpin(&mut self, frame_id: FrameId);
 
     fn size(&self) -> usize;
+
+    // Tracks frames 0..new_size from now on, e.g. when the pool grows or shrinks. Frames
+    // dropped by shrinking must have been removed first.
+    fn resize(&mut self, new_size: usize);
+
+    // Forgets the frame without evicting it, e.g. because its page was deleted. Returns
+    // whether it was evictable.
+    fn remove(&mut self, frame_id: FrameId) -> bool;
+
+    // Whether the frame is evictable, i.e. victim may return it.
+    fn contains(&self, frame_id: FrameId) -> bool;
+
+    // Up to n frames in the order victim would return them, without removing them.
+    fn peek_victims(&self, n: usize) -> Vec<FrameId> {
+        Vec::new()
+    }
+
+    // The frame victim would return next, left in place.
+    fn peek_victim(&self) -> Option<FrameId> {
+        self.peek_victims(1).into_iter().next()
+    }
+
+    // Every evictable frame in eviction order, next victim first, e.g. to dump the cache state
+    // for analysis. Empty for replacers that can't peek.
+    fn snapshot(&self) -> Vec<FrameId> {
+        self.peek_victims(usize::MAX)
+    }
+
+    // Rough number of heap bytes held.
+    fn memory_usage(&self) -> usize {
+        0
+    }
+
+    // Told about each pin, unpin and victim, e.g. by LoggingReplacer. A learned policy can
+    // train on these.
+    fn observe(&mut self, event: AccessEvent) {}
+
+    // Told which page a frame was just given, while it is still pinned. Only policies that
+    // remember pages past their eviction need it, like the ghosts of TwoQReplacer.
+    fn loaded(&mut self, frame_id: FrameId, page_id: PageId) {}
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum AccessKind {
+    Pin,
+    Unpin,
+    Victim,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct AccessEvent {
+    pub kind: AccessKind,
+    pub frame_id: FrameId,
+    // counts the events of one replacer from 0, so a trace can be ordered and merged
+    pub seq: u64,
+}
+
+// An LRUReplacer behind its own lock, so threads can share it through &self. Every _shared
+// method, and the &self methods of Replacer, may be called from any number of threads at once;
+// each takes the lock for the one call. The &mut self methods of Replacer skip the lock.
+#[derive(Debug)]
+pub struct ConcurrentLRUReplacer {
+    inner: Mutex<LRUReplacer>,
+}
+
+impl ConcurrentLRUReplacer {
+    fn lock(&self) -> std::sync::MutexGuard<'_, LRUReplacer> {
+        self.inner.lock().unwrap()
+    }
+
+    pub fn victim_shared(&self) -> Option<FrameId> {
+        self.lock().victim()
+    }
+
+    pub fn pin_shared(&self, frame_id: FrameId) {
+        self.lock().pin(frame_id)
+    }
+
+    pub fn unpin_shared(&self, frame_id: FrameId) {
+        self.lock().unpin(frame_id)
+    }
+
+    pub fn remove_shared(&self, frame_id: FrameId) -> bool {
+        self.lock().remove(frame_id)
+    }
+
+    pub fn stats(&self) -> ReplacerStats {
+        self.lock().stats()
+    }
+}
+
+impl Replacer for ConcurrentLRUReplacer {
+    fn new(pool_size: usize) -> Self {
+        ConcurrentLRUReplacer {
+            inner: Mutex::new(LRUReplacer::new(pool_size)),
+        }
+    }
+
+    fn victim(&mut self) -> Option<FrameId> {
+        self.inner.get_mut().unwrap().victim()
+    }
+
+    fn victims(&mut self, n: usize) -> Vec<FrameId> {
+        self.inner.get_mut().unwrap().victims(n)
+    }
+
+    fn pin(&mut self, frame_id: FrameId) {
+        self.inner.get_mut().unwrap().pin(frame_id)
+    }
+
+    fn unpin(&mut self, frame_id: FrameId) {
+        self.inner.get_mut().unwrap().unpin(frame_id)
+    }
+
+    fn size(&self) -> usize {
+        self.lock().size()
+    }
+
+    fn resize(&mut self, new_size: usize) {
+        self.inner.get_mut().unwrap().resize(new_size)
+    }
+
+    fn remove(&mut self, frame_id: FrameId) -> bool {
+        self.inner.get_mut().unwrap().remove(frame_id)
+    }
+
+    fn contains(&self, frame_id: FrameId) -> bool {
+        self.lock().contains(frame_id)
+    }
+
+    fn peek_victim(&self) -> Option<FrameId> {
+        self.lock().peek_victim()
+    }
+
+    fn peek_victims(&self, n: usize) -> Vec<FrameId> {
+        self.lock().peek_victims(n)
+    }
+
+    fn memory_usage(&self) -> usize {
+        self.lock().memory_usage()
+    }
 }
 
+// Calls a replacer has served since it was created, to see how much a workload churns.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub struct ReplacerStats {
+    // victim calls that returned a frame
+    pub victims: u64,
+    pub pins: u64,
+    pub unpins: u64,
+}
+
+// A doubly linked list of the evictable frames, least recently unpinned first. The links are
+// indexed by frame id, so a frame is found and unlinked in O(1) without any pointers. Slot
+// pool_size is the head: its next is the front of the list, its prev the back.
 #[derive(Debug)]
 pub struct LRUReplacer {
-    container: LinkedList<FrameId>,
-    index: Vec<Option<CursorMut<'static, FrameId>>>,
+    prev: Vec<usize>,
+    next: Vec<usize>,
+    linked: Vec<bool>,
+    size: usize,
+    stats: ReplacerStats,
+}
+
+impl LRUReplacer {
+    pub fn stats(&self) -> ReplacerStats {
+        self.stats
+    }
+
+    fn head(&self) -> usize {
+        self.linked.len()
+    }
+
+    fn unlink(&mut self, frame: usize) {
+        let (prev, next) = (self.prev[frame], self.next[frame]);
+        self.next[prev] = next;
+        self.prev[next] = prev;
+        self.linked[frame] = false;
+        self.size -= 1;
+    }
 }
 
 impl Replacer for LRUReplacer {
     fn new(pool_size: usize) -> Self {
-        let mut index = Vec::new();
-        index.resize_with(pool_size, || None);
+        // the head links to itself while the list is empty
         LRUReplacer {
-            container: LinkedList::new(),
-            index,
+            prev: vec![pool_size; pool_size + 1],
+            next: vec![pool_size; pool_size + 1],
+            linked: vec![false; pool_size],
+            size: 0,
+            stats: ReplacerStats::default(),
         }
     }
     //pop front
     fn victim(&mut self) -> Option<FrameId> {
-        let frame_id = self.container.pop_front()?;
-        debug_assert!(self.index[frame_id.0].is_some());
-        self.index[frame_id.0] = None;
+        let front = self.next[self.head()];
+        if front == self.head() {
+            return None;
+        }
+        self.unlink(front);
+        self.stats.victims += 1;
+        Some(FrameId(front))
+    }
+
+    // Cuts the first n frames off the front in one go.
+    fn victims(&mut self, n: usize) -> Vec<FrameId> {
+        let head = self.head();
+        let mut victims = Vec::new();
+        let mut frame = self.next[head];
+        while frame != head && victims.len() < n {
+            victims.push(FrameId(frame));
+            self.linked[frame] = false;
+            frame = self.next[frame];
+        }
+        self.next[head] = frame;
+        self.prev[frame] = head;
+        self.size -= victims.len();
+        self.stats.victims += victims.len() as u64;
+        victims
+    }
+
+    fn pin(&mut self, frame_id: FrameId) {
+        self.stats.pins += 1;
+        self.remove(frame_id);
+    }
+
+    fn unpin(&mut self, frame_id: FrameId) {
+        //push back
+        debug_assert!(!self.linked[frame_id.0]);
+        self.stats.unpins += 1;
+        self.remove(frame_id);
+        let (head, frame) = (self.head(), frame_id.0);
+        let back = self.prev[head];
+        self.prev[frame] = back;
+        self.next[frame] = head;
+        self.next[back] = frame;
+        self.prev[head] = frame;
+        self.linked[frame] = true;
+        self.size += 1;
+    }
+
+    fn size(&self) -> usize {
+        self.size
+    }
+
+    // The head moves to the new end, the list keeps its order.
+    fn resize(&mut self, new_size: usize) {
+        debug_assert!(self.linked.iter().skip(new_size).all(|linked| !linked));
+        let old_head = self.head();
+        let (front, back) = (self.next[old_head], self.prev[old_head]);
+        self.prev.resize(new_size + 1, new_size);
+        self.next.resize(new_size + 1, new_size);
+        self.linked.resize(new_size, false);
+        let head = self.head();
+        if front == old_head {
+            self.next[head] = head;
+            self.prev[head] = head;
+        } else {
+            self.next[head] = front;
+            self.prev[front] = head;
+            self.prev[head] = back;
+            self.next[back] = head;
+        }
+    }
+
+    fn remove(&mut self, frame_id: FrameId) -> bool {
+        let linked = self.linked[frame_id.0];
+        if linked {
+            self.unlink(frame_id.0);
+        }
+        linked
+    }
+
+    fn contains(&self, frame_id: FrameId) -> bool {
+        self.linked[frame_id.0]
+    }
+
+    fn peek_victim(&self) -> Option<FrameId> {
+        let front = self.next[self.head()];
+        (front != self.head()).then_some(FrameId(front))
+    }
+
+    fn peek_victims(&self, n: usize) -> Vec<FrameId> {
+        let mut victims = Vec::new();
+        let mut frame = self.next[self.head()];
+        while frame != self.head() && victims.len() < n {
+            victims.push(FrameId(frame));
+            frame = self.next[frame];
+        }
+        victims
+    }
+
+    fn memory_usage(&self) -> usize {
+        (self.prev.capacity() + self.next.capacity()) * size_of::<usize>()
+            + self.linked.capacity()
+    }
+}
+
+// Evicts the least recently unpinned frame (LRU) or the most recently unpinned one (MRU).
+// Every WINDOW hits and victims the recent behaviour is checked:
+// - in LRU mode, a hit ratio below 1/16 means pages are evicted before they come back, as in
+//   a looping scan bigger than the pool, so it switches to MRU;
+// - in MRU mode, it switches back to LRU once hits mostly land on recently unpinned frames.
+// The two modes look at different signals, so a loop that MRU serves well doesn't look like a
+// reason to go back to LRU, which avoids flapping.
+#[derive(Debug)]
+pub struct AdaptiveReplacer {
+    // unpin tick -> frame, oldest first
+    frames: BTreeMap<u64, FrameId>,
+    ticks: Vec<Option<u64>>,
+    next_tick: u64,
+    mru: bool,
+    hits: usize,
+    recent_hits: usize,
+    victims: usize,
+}
+
+impl AdaptiveReplacer {
+    const WINDOW: usize = 32;
+
+    pub fn is_mru(&self) -> bool {
+        self.mru
+    }
+
+    fn record(&mut self) {
+        if self.hits + self.victims < Self::WINDOW {
+            return;
+        }
+        if !self.mru {
+            self.mru = self.hits * 16 < Self::WINDOW;
+        } else {
+            self.mru = self.recent_hits * 3 <= self.hits * 2;
+        }
+        self.hits = 0;
+        self.recent_hits = 0;
+        self.victims = 0;
+    }
+}
+
+impl Replacer for AdaptiveReplacer {
+    fn new(pool_size: usize) -> Self {
+        AdaptiveReplacer {
+            frames: BTreeMap::new(),
+            ticks: vec![None; pool_size],
+            next_tick: 0,
+            mru: false,
+            hits: 0,
+            recent_hits: 0,
+            victims: 0,
+        }
+    }
+
+    fn victim(&mut self) -> Option<FrameId> {
+        let (_, frame_id) = if self.mru {
+            self.frames.pop_last()?
+        } else {
+            self.frames.pop_first()?
+        };
+        self.ticks[frame_id.0] = None;
+        self.victims += 1;
+        self.record();
         Some(frame_id)
     }
 
+    // Pinning an evictable frame means its page was found in the pool.
     fn pin(&mut self, frame_id: FrameId) {
-        // delete frame_id
-        if let Some(mut cursor) = self.index[frame_id.0].take() {
-            cursor.remove_current().unwrap();
+        let Some(tick) = self.ticks[frame_id.0] else {
+            return;
+        };
+        // unpinned within the last half of the evictable frames
+        if (self.next_tick - tick) as usize <= self.frames.len() / 2 {
+            self.recent_hits += 1;
         }
+        self.hits += 1;
+        self.remove(frame_id);
+        self.record();
     }
 
     fn unpin(&mut self, frame_id: FrameId) {
-        //push back
-        debug_assert!(self.index[frame_id.0].is_none());
-        self.container.push_back(frame_id);
-        self.index[frame_id.0] =
-            Some(unsafe { core::mem::transmute(self.container.cursor_back_mut()) });
+        debug_assert!(self.ticks[frame_id.0].is_none());
+        self.next_tick += 1;
+        self.frames.insert(self.next_tick, frame_id);
+        self.ticks[frame_id.0] = Some(self.next_tick);
+    }
+
+    fn size(&self) -> usize {
+        self.frames.len()
+    }
+
+    fn resize(&mut self, new_size: usize) {
+        debug_assert!(self.ticks.iter().skip(new_size).all(Option::is_none));
+        self.ticks.resize(new_size, None);
+    }
+
+    fn remove(&mut self, frame_id: FrameId) -> bool {
+        let Some(tick) = self.ticks[frame_id.0].take() else {
+            return false;
+        };
+        self.frames.remove(&tick);
+        true
+    }
+
+    fn contains(&self, frame_id: FrameId) -> bool {
+        self.ticks[frame_id.0].is_some()
+    }
+
+    fn peek_victims(&self, n: usize) -> Vec<FrameId> {
+        if self.mru {
+            self.frames.values().rev().take(n).copied().collect()
+        } else {
+            self.frames.values().take(n).copied().collect()
+        }
+    }
+
+    // not counting the slack in the BTreeMap's nodes
+    fn memory_usage(&self) -> usize {
+        self.ticks.capacity() * size_of::<Option<u64>>()
+            + self.frames.len() * (size_of::<u64>() + size_of::<FrameId>())
+    }
+}
+
+// Evicts frames in the order their pages were loaded, no matter how often they were used
+// since. A frame keeps its place in the queue while pinned, so victim skips over pinned ones.
+#[derive(Debug)]
+pub struct FIFOReplacer {
+    // frames in load order, pinned or not
+    queue: VecDeque<FrameId>,
+    queued: Vec<bool>,
+    evictable: Vec<bool>,
+    size: usize,
+}
+
+impl Replacer for FIFOReplacer {
+    fn new(pool_size: usize) -> Self {
+        FIFOReplacer {
+            queue: VecDeque::new(),
+            queued: vec![false; pool_size],
+            evictable: vec![false; pool_size],
+            size: 0,
+        }
+    }
+
+    fn victim(&mut self) -> Option<FrameId> {
+        let position = self
+            .queue
+            .iter()
+            .position(|frame_id| self.evictable[frame_id.0])?;
+        let frame_id = self.queue.remove(position).unwrap();
+        self.queued[frame_id.0] = false;
+        self.evictable[frame_id.0] = false;
+        self.size -= 1;
+        Some(frame_id)
+    }
+
+    fn pin(&mut self, frame_id: FrameId) {
+        if self.evictable[frame_id.0] {
+            self.evictable[frame_id.0] = false;
+            self.size -= 1;
+        }
+    }
+
+    fn unpin(&mut self, frame_id: FrameId) {
+        if !self.queued[frame_id.0] {
+            self.queue.push_back(frame_id);
+            self.queued[frame_id.0] = true;
+        }
+        if !self.evictable[frame_id.0] {
+            self.evictable[frame_id.0] = true;
+            self.size += 1;
+        }
+    }
+
+    fn size(&self) -> usize {
+        self.size
+    }
+
+    fn resize(&mut self, new_size: usize) {
+        debug_assert!(self.queued.iter().skip(new_size).all(|queued| !queued));
+        self.queued.resize(new_size, false);
+        self.evictable.resize(new_size, false);
+    }
+
+    fn remove(&mut self, frame_id: FrameId) -> bool {
+        let evictable = self.contains(frame_id);
+        self.pin(frame_id);
+        if self.queued[frame_id.0] {
+            self.queue.retain(|queued| *queued != frame_id);
+            self.queued[frame_id.0] = false;
+        }
+        evictable
+    }
+
+    fn contains(&self, frame_id: FrameId) -> bool {
+        self.evictable[frame_id.0]
+    }
+
+    fn peek_victims(&self, n: usize) -> Vec<FrameId> {
+        self.queue
+            .iter()
+            .filter(|frame_id| self.evictable[frame_id.0])
+            .take(n)
+            .copied()
+            .collect()
+    }
+
+    fn memory_usage(&self) -> usize {
+        self.queue.capacity() * size_of::<FrameId>()
+            + self.queued.capacity()
+            + self.evictable.capacity()
+    }
+}
+
+// Second chance: frames sit on a circle with a reference bit that unpin sets. victim sweeps
+// the circle from where the hand stopped, clearing set bits and evicting the first evictable
+// frame whose bit is already clear, so a frame used since the last sweep survives one more.
+// Cheaper than LRU on scans, which mostly touch each frame once.
+#[derive(Debug)]
+pub struct ClockReplacer {
+    referenced: Vec<bool>,
+    evictable: Vec<bool>,
+    hand: usize,
+    size: usize,
+}
+
+impl Replacer for ClockReplacer {
+    fn new(pool_size: usize) -> Self {
+        ClockReplacer {
+            referenced: vec![false; pool_size],
+            evictable: vec![false; pool_size],
+            hand: 0,
+            size: 0,
+        }
+    }
+
+    fn victim(&mut self) -> Option<FrameId> {
+        if self.size == 0 {
+            return None;
+        }
+        // the first lap clears every bit, so the second one finds a victim
+        loop {
+            let frame = self.hand;
+            self.hand = (self.hand + 1) % self.evictable.len();
+            if !self.evictable[frame] {
+                continue;
+            }
+            if self.referenced[frame] {
+                self.referenced[frame] = false;
+            } else {
+                self.evictable[frame] = false;
+                self.size -= 1;
+                return Some(FrameId(frame));
+            }
+        }
+    }
+
+    fn pin(&mut self, frame_id: FrameId) {
+        if self.evictable[frame_id.0] {
+            self.evictable[frame_id.0] = false;
+            self.size -= 1;
+        }
+    }
+
+    fn unpin(&mut self, frame_id: FrameId) {
+        self.referenced[frame_id.0] = true;
+        if !self.evictable[frame_id.0] {
+            self.evictable[frame_id.0] = true;
+            self.size += 1;
+        }
+    }
+
+    fn size(&self) -> usize {
+        self.size
+    }
+
+    fn resize(&mut self, new_size: usize) {
+        debug_assert!(self.evictable.iter().skip(new_size).all(|evictable| !evictable));
+        self.referenced.resize(new_size, false);
+        self.evictable.resize(new_size, false);
+        if self.hand >= new_size {
+            self.hand = 0;
+        }
+    }
+
+    fn remove(&mut self, frame_id: FrameId) -> bool {
+        let evictable = self.contains(frame_id);
+        self.pin(frame_id);
+        self.referenced[frame_id.0] = false;
+        evictable
+    }
+
+    fn contains(&self, frame_id: FrameId) -> bool {
+        self.evictable[frame_id.0]
+    }
+
+    // Frames with a clear bit go first, in sweep order, then the ones the sweep clears on the
+    // way, in the order the next lap reaches them.
+    fn peek_victims(&self, n: usize) -> Vec<FrameId> {
+        let len = self.evictable.len();
+        let circle = || (0..len).map(|i| (self.hand + i) % len);
+        let clear = circle().filter(|frame| !self.referenced[*frame]);
+        let set = circle().filter(|frame| self.referenced[*frame]);
+        clear
+            .chain(set)
+            .filter(|frame| self.evictable[*frame])
+            .take(n)
+            .map(FrameId)
+            .collect()
+    }
+
+    fn memory_usage(&self) -> usize {
+        self.referenced.capacity() + self.evictable.capacity()
+    }
+}
+
+// 2Q: frames seen once wait in A1in, a FIFO, and only frames referenced again move to Am, an
+// LRU. While A1in holds more than its share of the pool victims come from there, so a scan
+// churns through A1in and leaves the hot pages in Am alone. A page evicted from A1in leaves a
+// ghost in A1out, so if it is loaded again soon it goes straight to Am. Ghosts need the page
+// ids from loaded, without them only re-references within A1in promote.
+#[derive(Debug)]
+pub struct TwoQReplacer {
+    // Both are LRU lists. Frames never move within A1in, so it stays in load order.
+    a1in: LRUReplacer,
+    am: LRUReplacer,
+    // A1in frames beyond this are evicted ahead of Am
+    a1in_size: usize,
+    // pages recently evicted from A1in, oldest first
+    a1out: VecDeque<PageId>,
+    a1out_size: usize,
+    // the sizes above as fractions of the pool, to size them again on resize
+    a1in_ratio: f64,
+    a1out_ratio: f64,
+    // whether a frame goes to Am when unpinned
+    hot: Vec<bool>,
+    pages: Vec<Option<PageId>>,
+}
+
+impl TwoQReplacer {
+    pub const DEFAULT_A1IN_RATIO: f64 = 0.25;
+    pub const DEFAULT_A1OUT_RATIO: f64 = 0.5;
+
+    // A1in gets a1in_ratio of the pool and A1out remembers a1out_ratio of it, as fractions of
+    // pool_size.
+    pub fn new_with_ratios(pool_size: usize, a1in_ratio: f64, a1out_ratio: f64) -> Self {
+        assert!(
+            (0.0..=1.0).contains(&a1in_ratio) && a1out_ratio >= 0.0,
+            "bad 2Q ratios {} and {}",
+            a1in_ratio,
+            a1out_ratio
+        );
+        TwoQReplacer {
+            a1in: LRUReplacer::new(pool_size),
+            am: LRUReplacer::new(pool_size),
+            a1in_size: (pool_size as f64 * a1in_ratio) as usize,
+            a1out: VecDeque::new(),
+            a1out_size: (pool_size as f64 * a1out_ratio) as usize,
+            a1in_ratio,
+            a1out_ratio,
+            hot: vec![false; pool_size],
+            pages: vec![None; pool_size],
+        }
+    }
+
+    // A frame that leaves A1in for good takes its page to A1out.
+    fn forget(&mut self, frame_id: FrameId, from_a1in: bool) {
+        let page_id = self.pages[frame_id.0].take();
+        if let (true, Some(page_id)) = (from_a1in, page_id) {
+            if self.a1out_size > 0 {
+                if self.a1out.len() == self.a1out_size {
+                    self.a1out.pop_front();
+                }
+                self.a1out.push_back(page_id);
+            }
+        }
+        self.hot[frame_id.0] = false;
+    }
+}
+
+impl Replacer for TwoQReplacer {
+    fn new(pool_size: usize) -> Self {
+        Self::new_with_ratios(pool_size, Self::DEFAULT_A1IN_RATIO, Self::DEFAULT_A1OUT_RATIO)
+    }
+
+    fn victim(&mut self) -> Option<FrameId> {
+        let from_a1in = self.a1in.size() > self.a1in_size || self.am.size() == 0;
+        let frame_id = if from_a1in {
+            self.a1in.victim()?
+        } else {
+            self.am.victim()?
+        };
+        self.forget(frame_id, from_a1in);
+        Some(frame_id)
+    }
+
+    // Pinning a frame that waits in A1in is a second reference.
+    fn pin(&mut self, frame_id: FrameId) {
+        if self.a1in.remove(frame_id) {
+            self.hot[frame_id.0] = true;
+        }
+        self.am.pin(frame_id);
+    }
+
+    fn unpin(&mut self, frame_id: FrameId) {
+        if self.hot[frame_id.0] {
+            self.am.unpin(frame_id);
+        } else {
+            self.a1in.unpin(frame_id);
+        }
+    }
+
+    fn size(&self) -> usize {
+        self.a1in.size() + self.am.size()
+    }
+
+    // A1in and A1out keep their share of the pool, the oldest ghosts go if A1out shrinks.
+    fn resize(&mut self, new_size: usize) {
+        self.a1in.resize(new_size);
+        self.am.resize(new_size);
+        self.a1in_size = (new_size as f64 * self.a1in_ratio) as usize;
+        self.a1out_size = (new_size as f64 * self.a1out_ratio) as usize;
+        let excess = self.a1out.len().saturating_sub(self.a1out_size);
+        self.a1out.drain(..excess);
+        self.hot.resize(new_size, false);
+        self.pages.resize(new_size, None);
+    }
+
+    fn remove(&mut self, frame_id: FrameId) -> bool {
+        let removed = self.a1in.remove(frame_id) || self.am.remove(frame_id);
+        self.forget(frame_id, false);
+        removed
+    }
+
+    fn contains(&self, frame_id: FrameId) -> bool {
+        self.a1in.contains(frame_id) || self.am.contains(frame_id)
+    }
+
+    fn peek_victims(&self, n: usize) -> Vec<FrameId> {
+        let mut a1in = self.a1in.peek_victims(n).into_iter().peekable();
+        let mut am = self.am.peek_victims(n).into_iter().peekable();
+        let mut a1in_size = self.a1in.size();
+        let mut victims = Vec::new();
+        while victims.len() < n {
+            let from_a1in = a1in_size > self.a1in_size || am.peek().is_none();
+            let next = if from_a1in { a1in.next() } else { am.next() };
+            let Some(frame_id) = next else {
+                break;
+            };
+            if from_a1in {
+                a1in_size -= 1;
+            }
+            victims.push(frame_id);
+        }
+        victims
+    }
+
+    fn memory_usage(&self) -> usize {
+        self.a1in.memory_usage()
+            + self.am.memory_usage()
+            + self.a1out.capacity() * size_of::<PageId>()
+            + self.hot.capacity()
+            + self.pages.capacity() * size_of::<Option<PageId>>()
+    }
+
+    fn loaded(&mut self, frame_id: FrameId, page_id: PageId) {
+        self.pages[frame_id.0] = Some(page_id);
+        if let Some(position) = self.a1out.iter().position(|ghost| *ghost == page_id) {
+            self.a1out.remove(position);
+            self.hot[frame_id.0] = true;
+        }
+    }
+}
+
+// Wraps a replacer and reports every pin, unpin and victim to a callback, e.g. to record
+// (access pattern, eviction decision) traces for offline analysis. The wrapped policy decides
+// as it would on its own.
+pub struct LoggingReplacer<R: Replacer> {
+    inner: R,
+    callback: Box<dyn FnMut(AccessEvent) + Send>,
+    seq: u64,
+}
+
+impl<R: Replacer> LoggingReplacer<R> {
+    pub fn with_callback(inner: R, callback: impl FnMut(AccessEvent) + Send + 'static) -> Self {
+        LoggingReplacer {
+            inner,
+            callback: Box::new(callback),
+            seq: 0,
+        }
+    }
+
+    pub fn inner(&self) -> &R {
+        &self.inner
+    }
+
+    fn record(&mut self, kind: AccessKind, frame_id: FrameId) {
+        let event = AccessEvent {
+            kind,
+            frame_id,
+            seq: self.seq,
+        };
+        self.seq += 1;
+        self.observe(event);
+    }
+}
+
+// new logs nowhere, use with_callback.
+impl<R: Replacer> Replacer for LoggingReplacer<R> {
+    fn new(pool_size: usize) -> Self {
+        Self::with_callback(R::new(pool_size), |_| {})
+    }
+
+    fn victim(&mut self) -> Option<FrameId> {
+        let frame_id = self.inner.victim()?;
+        self.record(AccessKind::Victim, frame_id);
+        Some(frame_id)
+    }
+
+    fn pin(&mut self, frame_id: FrameId) {
+        self.inner.pin(frame_id);
+        self.record(AccessKind::Pin, frame_id);
+    }
+
+    fn unpin(&mut self, frame_id: FrameId) {
+        self.inner.unpin(frame_id);
+        self.record(AccessKind::Unpin, frame_id);
     }
 
     fn size(&self) -> usize {
-        self.container.len()
+        self.inner.size()
+    }
+
+    fn resize(&mut self, new_size: usize) {
+        self.inner.resize(new_size)
+    }
+
+    fn remove(&mut self, frame_id: FrameId) -> bool {
+        self.inner.remove(frame_id)
+    }
+
+    fn contains(&self, frame_id: FrameId) -> bool {
+        self.inner.contains(frame_id)
+    }
+
+    fn peek_victim(&self) -> Option<FrameId> {
+        self.inner.peek_victim()
+    }
+
+    fn peek_victims(&self, n: usize) -> Vec<FrameId> {
+        self.inner.peek_victims(n)
+    }
+
+    fn memory_usage(&self) -> usize {
+        self.inner.memory_usage()
+    }
+
+    fn observe(&mut self, event: AccessEvent) {
+        self.inner.observe(event);
+        (self.callback)(event);
+    }
+
+    fn loaded(&mut self, frame_id: FrameId, page_id: PageId) {
+        self.inner.loaded(frame_id, page_id)
+    }
+}
+
+// Lets the policy be chosen, and swapped with BufferPoolManager::set_replacer, at runtime.
+// new gives an LRU replacer.
+impl Replacer for Box<dyn Replacer + Send> {
+    fn new(pool_size: usize) -> Self {
+        Box::new(LRUReplacer::new(pool_size))
+    }
+
+    fn victim(&mut self) -> Option<FrameId> {
+        (**self).victim()
+    }
+
+    fn victims(&mut self, n: usize) -> Vec<FrameId> {
+        (**self).victims(n)
+    }
+
+    fn pin(&mut self, frame_id: FrameId) {
+        (**self).pin(frame_id)
+    }
+
+    fn unpin(&mut self, frame_id: FrameId) {
+        (**self).unpin(frame_id)
+    }
+
+    fn size(&self) -> usize {
+        (**self).size()
+    }
+
+    fn resize(&mut self, new_size: usize) {
+        (**self).resize(new_size)
+    }
+
+    fn remove(&mut self, frame_id: FrameId) -> bool {
+        (**self).remove(frame_id)
+    }
+
+    fn contains(&self, frame_id: FrameId) -> bool {
+        (**self).contains(frame_id)
+    }
+
+    fn peek_victim(&self) -> Option<FrameId> {
+        (**self).peek_victim()
+    }
+
+    fn peek_victims(&self, n: usize) -> Vec<FrameId> {
+        (**self).peek_victims(n)
+    }
+
+    fn memory_usage(&self) -> usize {
+        (**self).memory_usage()
+    }
+
+    fn observe(&mut self, event: AccessEvent) {
+        (**self).observe(event)
+    }
+
+    fn loaded(&mut self, frame_id: FrameId, page_id: PageId) {
+        (**self).loaded(frame_id, page_id)
     }
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
+    use std::sync::{Arc, Mutex};
 
     #[test]
     fn replacer_test() {
@@ -94,4 +990,403 @@ mod test {
         replacer.unpin(FrameId(6));
         assert_eq!(replacer.size(), 6);
     }
+
+    #[test]
+    fn peek_victims_test() {
+        let mut replacer = LRUReplacer::new(7);
+        for i in [3, 1, 4, 5, 6] {
+            replacer.unpin(FrameId(i));
+        }
+        replacer.pin(FrameId(1));
+        assert_eq!(replacer.peek_victims(3), vec![FrameId(3), FrameId(4), FrameId(5)]);
+        assert_eq!(replacer.peek_victims(10).len(), 4);
+        assert_eq!(replacer.size(), 4);
+        assert_eq!(replacer.victim(), Some(FrameId(3)));
+        assert_eq!(replacer.victim(), Some(FrameId(4)));
+        assert_eq!(replacer.victim(), Some(FrameId(5)));
+        assert_eq!(replacer.peek_victims(3), vec![FrameId(6)]);
+    }
+
+    // Runs the trace against a pool of pool_size frames the way the buffer pool drives its
+    // replacer, and returns the number of hits.
+    fn count_hits<R: Replacer>(trace: &[u32], pool_size: usize) -> (R, usize) {
+        let mut replacer = R::new(pool_size);
+        let mut resident: Vec<Option<u32>> = vec![None; pool_size];
+        let mut hits = 0;
+        for page in trace {
+            let frame_id = match resident.iter().position(|p| *p == Some(*page)) {
+                Some(frame) => {
+                    hits += 1;
+                    replacer.pin(FrameId(frame));
+                    FrameId(frame)
+                }
+                None => {
+                    let frame_id = match resident.iter().position(Option::is_none) {
+                        Some(frame) => FrameId(frame),
+                        None => replacer.victim().unwrap(),
+                    };
+                    resident[frame_id.0] = Some(*page);
+                    frame_id
+                }
+            };
+            replacer.unpin(frame_id);
+        }
+        (replacer, hits)
+    }
+
+    #[test]
+    fn adaptive_looping_scan_test() {
+        let trace: Vec<u32> = (0..200).flat_map(|_| 0..5).collect();
+        let (_, lru_hits) = count_hits::<LRUReplacer>(&trace, 4);
+        let (adaptive, adaptive_hits) = count_hits::<AdaptiveReplacer>(&trace, 4);
+        assert_eq!(lru_hits, 0);
+        assert!(adaptive.is_mru());
+        assert!(adaptive_hits > trace.len() / 2, "{} hits", adaptive_hits);
+    }
+
+    #[test]
+    fn adaptive_stays_lru_on_hot_set_test() {
+        // a hot page between cold ones
+        let trace: Vec<u32> = (0..1000).flat_map(|i| [0, 1 + i % 50]).collect();
+        let (_, lru_hits) = count_hits::<LRUReplacer>(&trace, 4);
+        let (adaptive, adaptive_hits) = count_hits::<AdaptiveReplacer>(&trace, 4);
+        assert!(!adaptive.is_mru());
+        assert_eq!(adaptive_hits, lru_hits);
+    }
+
+    #[test]
+    fn fifo_test() {
+        let mut replacer = FIFOReplacer::new(4);
+        for i in 0..4 {
+            replacer.unpin(FrameId(i));
+        }
+        // a hit doesn't move frame 0 to the back
+        replacer.pin(FrameId(0));
+        replacer.unpin(FrameId(0));
+        replacer.pin(FrameId(1));
+        assert_eq!(replacer.size(), 3);
+        assert_eq!(
+            replacer.peek_victims(4),
+            vec![FrameId(0), FrameId(2), FrameId(3)]
+        );
+        assert_eq!(replacer.victim(), Some(FrameId(0)));
+        replacer.remove(FrameId(2));
+        assert_eq!(replacer.victim(), Some(FrameId(3)));
+        assert_eq!(replacer.victim(), None);
+        replacer.unpin(FrameId(1));
+        assert_eq!(replacer.victim(), Some(FrameId(1)));
+    }
+
+    #[test]
+    fn snapshot_test() {
+        let mut replacer = LRUReplacer::new(7);
+        assert!(replacer.snapshot().is_empty());
+        for i in [5, 0, 3, 6, 2] {
+            replacer.unpin(FrameId(i));
+        }
+        replacer.pin(FrameId(3));
+        replacer.victim();
+        let expected: Vec<_> = [0, 6, 2].into_iter().map(FrameId).collect();
+        assert_eq!(replacer.snapshot(), expected);
+        let boxed: Box<dyn Replacer + Send> = Box::new(replacer);
+        assert_eq!(boxed.snapshot(), expected);
+    }
+
+    #[test]
+    fn logging_replacer_test() {
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let log = events.clone();
+        let mut replacer = LoggingReplacer::with_callback(LRUReplacer::new(4), move |event| {
+            log.lock().unwrap().push(event)
+        });
+        replacer.unpin(FrameId(1));
+        replacer.unpin(FrameId(2));
+        replacer.pin(FrameId(1));
+        assert_eq!(replacer.victim(), Some(FrameId(2)));
+        // nothing to evict, nothing logged
+        assert_eq!(replacer.victim(), None);
+
+        let expected = [
+            (AccessKind::Unpin, 1),
+            (AccessKind::Unpin, 2),
+            (AccessKind::Pin, 1),
+            (AccessKind::Victim, 2),
+        ];
+        let events = events.lock().unwrap();
+        assert_eq!(events.len(), expected.len());
+        for (seq, (event, (kind, frame_id))) in events.iter().zip(expected).enumerate() {
+            assert_eq!(
+                *event,
+                AccessEvent {
+                    kind,
+                    frame_id: FrameId(frame_id),
+                    seq: seq as u64
+                }
+            );
+        }
+    }
+
+    #[test]
+    fn clock_replacer_test() {
+        let mut replacer = ClockReplacer::new(10);
+        for i in 0..10 {
+            replacer.unpin(FrameId(i));
+        }
+        replacer.pin(FrameId(5));
+        assert_eq!(replacer.victim(), Some(FrameId(0)));
+        assert_eq!(replacer.victim(), Some(FrameId(1)));
+        assert_eq!(replacer.victim(), Some(FrameId(2)));
+        assert_eq!(replacer.victim(), Some(FrameId(3)));
+        assert_eq!(replacer.victim(), Some(FrameId(4)));
+        assert_eq!(replacer.victim(), Some(FrameId(6)));
+        assert_eq!(replacer.victim(), Some(FrameId(7)));
+        assert_eq!(replacer.victim(), Some(FrameId(8)));
+        assert_eq!(replacer.victim(), Some(FrameId(9)));
+        assert_eq!(replacer.victim(), None);
+    }
+
+    #[test]
+    fn clock_sample_test() {
+        let mut replacer = ClockReplacer::new(7);
+        replacer.unpin(FrameId(1));
+        replacer.unpin(FrameId(2));
+        replacer.unpin(FrameId(3));
+        replacer.unpin(FrameId(4));
+        replacer.unpin(FrameId(5));
+        replacer.unpin(FrameId(6));
+        assert_eq!(replacer.size(), 6);
+    }
+
+    #[test]
+    fn clock_second_chance_test() {
+        let mut replacer = ClockReplacer::new(4);
+        for i in 0..4 {
+            replacer.unpin(FrameId(i));
+        }
+        // the sweep clears every bit and stops past frame 0
+        assert_eq!(replacer.victim(), Some(FrameId(0)));
+        // frame 2 is used again, so 3 goes before it
+        replacer.pin(FrameId(2));
+        replacer.unpin(FrameId(2));
+        let peeked = replacer.peek_victims(3);
+        assert_eq!(peeked, vec![FrameId(1), FrameId(3), FrameId(2)]);
+        let victims: Vec<_> = (0..3).map_while(|_| replacer.victim()).collect();
+        assert_eq!(victims, peeked);
+    }
+
+    #[test]
+    fn lru_repeated_pin_unpin_test() {
+        let mut replacer = LRUReplacer::new(8);
+        for i in 0..8 {
+            replacer.unpin(FrameId(i));
+        }
+        for round in 0..10_000 {
+            let frame_id = FrameId(round % 3);
+            replacer.pin(frame_id);
+            replacer.unpin(frame_id);
+            assert_eq!(replacer.size(), 8);
+        }
+        // frames 0..3 went to the back, the last of them touched last
+        let expected: Vec<_> = [3, 4, 5, 6, 7, 1, 2, 0].into_iter().map(FrameId).collect();
+        assert_eq!(replacer.peek_victims(8), expected);
+        let victims: Vec<_> = (0..8).map_while(|_| replacer.victim()).collect();
+        assert_eq!(victims, expected);
+        assert_eq!(replacer.victim(), None);
+    }
+
+    #[test]
+    fn contains_and_remove_test() {
+        let mut replacer = LRUReplacer::new(4);
+        assert!(!replacer.contains(FrameId(0)));
+        assert!(!replacer.remove(FrameId(0)));
+        for i in 0..3 {
+            replacer.unpin(FrameId(i));
+        }
+        assert!(replacer.contains(FrameId(1)));
+        replacer.pin(FrameId(1));
+        assert!(!replacer.contains(FrameId(1)));
+        assert!(!replacer.remove(FrameId(1)));
+        assert!(replacer.remove(FrameId(2)));
+        assert!(!replacer.contains(FrameId(2)));
+        assert_eq!(replacer.size(), 1);
+        assert_eq!(replacer.victim(), Some(FrameId(0)));
+        assert_eq!(replacer.victim(), None);
+    }
+
+    #[test]
+    fn peek_victim_test() {
+        let mut replacer = LRUReplacer::new(5);
+        assert_eq!(replacer.peek_victim(), None);
+        for i in [2, 0, 4] {
+            replacer.unpin(FrameId(i));
+        }
+        replacer.pin(FrameId(2));
+        // peeking twice changes nothing
+        assert_eq!(replacer.peek_victim(), Some(FrameId(0)));
+        assert_eq!(replacer.peek_victim(), Some(FrameId(0)));
+        assert_eq!(replacer.size(), 2);
+        for _ in 0..2 {
+            let next = replacer.peek_victim();
+            assert_eq!(replacer.victim(), next);
+        }
+        assert_eq!(replacer.peek_victim(), None);
+        let mut fifo = FIFOReplacer::new(2);
+        fifo.unpin(FrameId(1));
+        assert_eq!(fifo.peek_victim(), Some(FrameId(1)));
+    }
+
+    #[test]
+    fn stats_test() {
+        let mut replacer = LRUReplacer::new(4);
+        for i in 0..4 {
+            replacer.unpin(FrameId(i));
+        }
+        replacer.pin(FrameId(1));
+        replacer.pin(FrameId(2));
+        replacer.unpin(FrameId(2));
+        for _ in 0..4 {
+            replacer.victim();
+        }
+        // the last victim call found nothing
+        assert_eq!(
+            replacer.stats(),
+            ReplacerStats {
+                victims: 3,
+                pins: 2,
+                unpins: 5,
+            }
+        );
+    }
+
+    // Plays a scan of pages first..last through the replacer the way the buffer pool would,
+    // loading each into the next victim (or a free frame while there are some).
+    fn scan(replacer: &mut TwoQReplacer, free: &mut Vec<FrameId>, pages: std::ops::Range<u32>) {
+        for page in pages {
+            let frame_id = free.pop().or_else(|| replacer.victim()).unwrap();
+            replacer.pin(frame_id);
+            replacer.loaded(frame_id, PageId(page));
+            replacer.unpin(frame_id);
+        }
+    }
+
+    #[test]
+    fn two_q_scan_test() {
+        let mut replacer = TwoQReplacer::new(8);
+        let mut free: Vec<_> = (0..8).rev().map(FrameId).collect();
+        scan(&mut replacer, &mut free, 0..1);
+        // page 0 is referenced again while in A1in
+        replacer.pin(FrameId(0));
+        replacer.unpin(FrameId(0));
+        scan(&mut replacer, &mut free, 100..200);
+        // with plain LRU the scan would have evicted it
+        assert!(replacer.am.contains(FrameId(0)));
+        assert_eq!(replacer.size(), 8);
+        // it only goes once A1in is down to its share of the pool
+        let next = replacer.peek_victims(8);
+        assert_eq!(next[5], FrameId(0));
+        let victims: Vec<_> = (0..8).map_while(|_| replacer.victim()).collect();
+        assert_eq!(victims, next);
+    }
+
+    #[test]
+    fn two_q_ghost_test() {
+        let mut replacer = TwoQReplacer::new(8);
+        let mut free: Vec<_> = (0..8).rev().map(FrameId).collect();
+        scan(&mut replacer, &mut free, 0..9);
+        // page 0 was the first out of A1in, so its ghost sends it straight to Am
+        let frame_id = replacer.victim().unwrap();
+        replacer.pin(frame_id);
+        replacer.loaded(frame_id, PageId(0));
+        replacer.unpin(frame_id);
+        scan(&mut replacer, &mut free, 100..200);
+        assert!(replacer.am.contains(frame_id));
+        // a page long gone has no ghost left
+        let frame_id = replacer.victim().unwrap();
+        replacer.pin(frame_id);
+        replacer.loaded(frame_id, PageId(1));
+        replacer.unpin(frame_id);
+        assert!(replacer.a1in.contains(frame_id));
+    }
+
+    #[test]
+    fn victims_test() {
+        let fill = || {
+            let mut replacer = LRUReplacer::new(10);
+            for i in [4, 8, 1, 9, 0, 3] {
+                replacer.unpin(FrameId(i));
+            }
+            replacer.pin(FrameId(9));
+            replacer
+        };
+        let (mut batched, mut one_by_one) = (fill(), fill());
+        let expected: Vec<_> = (0..3).map_while(|_| one_by_one.victim()).collect();
+        assert_eq!(batched.victims(3), expected);
+        assert_eq!(batched.size(), 2);
+        assert_eq!(batched.peek_victims(10), one_by_one.peek_victims(10));
+        // asking for more than there is
+        assert_eq!(batched.victims(5), vec![FrameId(0), FrameId(3)]);
+        assert_eq!(batched.victims(5), vec![]);
+        batched.unpin(FrameId(8));
+        assert_eq!(batched.victim(), Some(FrameId(8)));
+        assert_eq!(batched.stats().victims, 6);
+        let mut fifo = FIFOReplacer::new(4);
+        fifo.unpin(FrameId(2));
+        fifo.unpin(FrameId(0));
+        assert_eq!(fifo.victims(4), vec![FrameId(2), FrameId(0)]);
+    }
+
+    #[test]
+    fn concurrent_lru_replacer_test() {
+        use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+        const FRAMES: usize = 16;
+        let replacer = ConcurrentLRUReplacer::new(FRAMES);
+        for i in 0..FRAMES {
+            replacer.unpin_shared(FrameId(i));
+        }
+        // whether some thread holds the frame it got from victim_shared
+        let taken: Vec<_> = (0..FRAMES).map(|_| AtomicBool::new(false)).collect();
+        let victims = AtomicUsize::new(0);
+        std::thread::scope(|s| {
+            for _ in 0..8 {
+                s.spawn(|| {
+                    for _ in 0..2000 {
+                        let Some(frame_id) = replacer.victim_shared() else {
+                            continue;
+                        };
+                        assert!(!taken[frame_id.0].swap(true, Ordering::SeqCst));
+                        victims.fetch_add(1, Ordering::Relaxed);
+                        // the pin the buffer pool makes after loading, victim already took the frame out
+                        replacer.pin_shared(frame_id);
+                        taken[frame_id.0].store(false, Ordering::SeqCst);
+                        replacer.unpin_shared(frame_id);
+                    }
+                });
+            }
+        });
+        assert_eq!(replacer.size(), FRAMES);
+        assert_eq!(replacer.stats().victims, victims.load(Ordering::Relaxed) as u64);
+    }
+
+    #[test]
+    fn resize_test() {
+        let mut lru = LRUReplacer::new(4);
+        lru.unpin(FrameId(1));
+        lru.unpin(FrameId(0));
+        lru.resize(8);
+        lru.unpin(FrameId(6));
+        assert_eq!(lru.snapshot(), vec![FrameId(1), FrameId(0), FrameId(6)]);
+        lru.remove(FrameId(6));
+        lru.resize(2);
+        assert_eq!(lru.snapshot(), vec![FrameId(1), FrameId(0)]);
+        assert_eq!(lru.victims(3), vec![FrameId(1), FrameId(0)]);
+        lru.resize(0);
+        assert_eq!(lru.victim(), None);
+
+        let mut two_q = TwoQReplacer::new(8);
+        two_q.resize(2);
+        two_q.unpin(FrameId(1));
+        two_q.resize(4);
+        two_q.unpin(FrameId(3));
+        assert_eq!(two_q.victims(2), vec![FrameId(1), FrameId(3)]);
+    }
 }
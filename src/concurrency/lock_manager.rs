@@ -0,0 +1,296 @@
+use crate::buffer::replacer::PageId;
+use crate::concurrency::transaction::{Transaction, TransactionState, TxnId};
+use std::collections::HashMap;
+use std::sync::{Arc, Condvar, Mutex};
+
+#[derive(Clone, Copy, PartialEq, Debug, Eq, Hash, Default)]
+pub struct Rid {
+    pub(crate) page_id: PageId,
+    pub(crate) slot_num: u32,
+}
+
+impl Rid {
+    pub fn new(page_id: PageId, slot_num: u32) -> Self {
+        Self { page_id, slot_num }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug, Eq)]
+pub enum LockMode {
+    Shared,
+    Exclusive,
+}
+
+#[derive(Debug)]
+struct LockRequest {
+    txn_id: TxnId,
+    mode: LockMode,
+    granted: bool,
+}
+
+#[derive(Debug, Default)]
+struct LockRequestQueue {
+    requests: Vec<LockRequest>,
+    cv: Arc<Condvar>,
+}
+
+impl LockRequestQueue {
+    // Requests are granted in FIFO order: a request is grantable once it is compatible with
+    // every request queued ahead of it, granted or not.
+    fn grantable(&self, txn_id: TxnId) -> bool {
+        let position = self
+            .requests
+            .iter()
+            .position(|request| request.txn_id == txn_id)
+            .unwrap();
+        let mode = self.requests[position].mode;
+        self.requests[..position]
+            .iter()
+            .all(|request| mode == LockMode::Shared && request.mode == LockMode::Shared)
+    }
+}
+
+/*
+Deadlocks are prevented with wait-die: a transaction may only wait for transactions younger
+than itself (higher txn id). A younger transaction that would have to wait for an older one
+is aborted instead, so the waits-for graph can never contain a cycle.
+ */
+#[derive(Debug, Default)]
+pub struct LockManager {
+    lock_table: Mutex<HashMap<Rid, LockRequestQueue>>,
+}
+
+impl LockManager {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn lock_shared(&self, txn: &Transaction, rid: Rid) -> bool {
+        if txn.is_shared_locked(&rid) || txn.is_exclusive_locked(&rid) {
+            return true;
+        }
+        self.lock(txn, rid, LockMode::Shared)
+    }
+
+    // Upgrades the lock if the transaction already holds it shared.
+    pub fn lock_exclusive(&self, txn: &Transaction, rid: Rid) -> bool {
+        if txn.is_exclusive_locked(&rid) {
+            return true;
+        }
+        if txn.is_shared_locked(&rid) {
+            return self.upgrade(txn, rid);
+        }
+        self.lock(txn, rid, LockMode::Exclusive)
+    }
+
+    pub fn unlock(&self, txn: &Transaction, rid: Rid) -> bool {
+        let mut lock_table = self.lock_table.lock().unwrap();
+        let Some(queue) = lock_table.get_mut(&rid) else {
+            return false;
+        };
+        let Some(position) = queue
+            .requests
+            .iter()
+            .position(|request| request.txn_id == txn.get_txn_id())
+        else {
+            return false;
+        };
+        queue.requests.remove(position);
+        txn.remove_lock(&rid);
+        if queue.requests.is_empty() {
+            lock_table.remove(&rid);
+        } else {
+            queue.cv.notify_all();
+        }
+        true
+    }
+
+    pub fn unlock_all(&self, txn: &Transaction) {
+        for rid in txn.get_locked_rids() {
+            self.unlock(txn, rid);
+        }
+    }
+
+    fn lock(&self, txn: &Transaction, rid: Rid, mode: LockMode) -> bool {
+        if txn.get_state() != TransactionState::Running {
+            return false;
+        }
+        let txn_id = txn.get_txn_id();
+        let mut lock_table = self.lock_table.lock().unwrap();
+        let queue = lock_table.entry(rid).or_default();
+        let conflicts_with_older = queue.requests.iter().any(|request| {
+            request.txn_id < txn_id
+                && (mode == LockMode::Exclusive || request.mode == LockMode::Exclusive)
+        });
+        if conflicts_with_older {
+            // die
+            if queue.requests.is_empty() {
+                lock_table.remove(&rid);
+            }
+            txn.set_state(TransactionState::Aborted);
+            return false;
+        }
+        queue.requests.push(LockRequest {
+            txn_id,
+            mode,
+            granted: false,
+        });
+        let cv = queue.cv.clone();
+        while !lock_table.get(&rid).unwrap().grantable(txn_id) {
+            lock_table = cv.wait(lock_table).unwrap();
+        }
+        let queue = lock_table.get_mut(&rid).unwrap();
+        queue
+            .requests
+            .iter_mut()
+            .find(|request| request.txn_id == txn_id)
+            .unwrap()
+            .granted = true;
+        txn.add_lock(rid, mode);
+        true
+    }
+
+    // Turns the shared lock into an exclusive one without letting go of it, so no other
+    // transaction can get in between. The request moves to the head of the queue and waits
+    // for the other shared holders to leave, which wait-die only allows if they are all
+    // younger. If the transaction dies it keeps its shared lock.
+    fn upgrade(&self, txn: &Transaction, rid: Rid) -> bool {
+        if txn.get_state() != TransactionState::Running {
+            return false;
+        }
+        let txn_id = txn.get_txn_id();
+        let mut lock_table = self.lock_table.lock().unwrap();
+        let queue = lock_table.get_mut(&rid).unwrap();
+        if queue.requests.iter().any(|request| request.txn_id < txn_id) {
+            // die
+            txn.set_state(TransactionState::Aborted);
+            return false;
+        }
+        let position = queue
+            .requests
+            .iter()
+            .position(|request| request.txn_id == txn_id)
+            .unwrap();
+        let mut request = queue.requests.remove(position);
+        request.mode = LockMode::Exclusive;
+        queue.requests.insert(0, request);
+        let cv = queue.cv.clone();
+        while lock_table.get(&rid).unwrap().requests[1..]
+            .iter()
+            .any(|request| request.granted)
+        {
+            lock_table = cv.wait(lock_table).unwrap();
+        }
+        txn.remove_lock(&rid);
+        txn.add_lock(rid, LockMode::Exclusive);
+        true
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn shared_shared_test() {
+        let lock_manager = LockManager::new();
+        let rid = Rid::new(PageId(0), 0);
+        let txns: Vec<_> = (0..5).map(|i| Transaction::new(TxnId(i))).collect();
+        for txn in txns.iter() {
+            assert!(lock_manager.lock_shared(txn, rid));
+            assert!(txn.is_shared_locked(&rid));
+        }
+        for txn in txns.iter() {
+            assert!(lock_manager.unlock(txn, rid));
+            assert!(!txn.is_shared_locked(&rid));
+        }
+        assert!(lock_manager.lock_table.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn shared_exclusive_test() {
+        let lock_manager = LockManager::new();
+        let rid = Rid::new(PageId(0), 0);
+        let older = Transaction::new(TxnId(0));
+        let younger = Transaction::new(TxnId(1));
+        let acquired = AtomicBool::new(false);
+        assert!(lock_manager.lock_shared(&younger, rid));
+        thread::scope(|s| {
+            s.spawn(|| {
+                assert!(lock_manager.lock_exclusive(&older, rid));
+                acquired.store(true, Ordering::SeqCst);
+            });
+            thread::sleep(Duration::from_millis(50));
+            assert!(!acquired.load(Ordering::SeqCst));
+            lock_manager.unlock(&younger, rid);
+        });
+        assert!(acquired.load(Ordering::SeqCst));
+        assert!(older.is_exclusive_locked(&rid));
+
+        // the younger transaction may not wait for the older exclusive holder
+        assert!(!lock_manager.lock_shared(&younger, rid));
+        assert_eq!(younger.get_state(), TransactionState::Aborted);
+    }
+
+    #[test]
+    fn deadlock_test() {
+        let lock_manager = LockManager::new();
+        let a = Rid::new(PageId(0), 0);
+        let b = Rid::new(PageId(1), 0);
+        let older = Transaction::new(TxnId(0));
+        let younger = Transaction::new(TxnId(1));
+        assert!(lock_manager.lock_exclusive(&older, a));
+        assert!(lock_manager.lock_exclusive(&younger, b));
+        thread::scope(|s| {
+            let waiter = s.spawn(|| lock_manager.lock_exclusive(&older, b));
+            thread::sleep(Duration::from_millis(50));
+            // completing the cycle kills the younger transaction instead of hanging
+            assert!(!lock_manager.lock_exclusive(&younger, a));
+            assert_eq!(younger.get_state(), TransactionState::Aborted);
+            lock_manager.unlock_all(&younger);
+            assert!(waiter.join().unwrap());
+        });
+        assert!(older.is_exclusive_locked(&a));
+        assert!(older.is_exclusive_locked(&b));
+        assert_eq!(older.get_state(), TransactionState::Running);
+    }
+
+    #[test]
+    fn upgrade_test() {
+        let lock_manager = LockManager::new();
+        let rid = Rid::new(PageId(0), 0);
+        let older = Transaction::new(TxnId(0));
+        let younger = Transaction::new(TxnId(1));
+        let late = Transaction::new(TxnId(2));
+        let upgraded = AtomicBool::new(false);
+        assert!(lock_manager.lock_shared(&older, rid));
+        assert!(lock_manager.lock_shared(&younger, rid));
+        thread::scope(|s| {
+            s.spawn(|| {
+                assert!(lock_manager.lock_exclusive(&older, rid));
+                upgraded.store(true, Ordering::SeqCst);
+            });
+            thread::sleep(Duration::from_millis(50));
+            assert!(!upgraded.load(Ordering::SeqCst));
+            // nobody gets in while the upgrade waits
+            assert!(!lock_manager.lock_shared(&late, rid));
+            // the younger holder can't upgrade past the older one, and keeps its shared lock
+            assert!(!lock_manager.lock_exclusive(&younger, rid));
+            assert_eq!(younger.get_state(), TransactionState::Aborted);
+            assert!(younger.is_shared_locked(&rid));
+            lock_manager.unlock_all(&younger);
+        });
+        assert!(upgraded.load(Ordering::SeqCst));
+        assert!(older.is_exclusive_locked(&rid));
+        assert!(!older.is_shared_locked(&rid));
+        assert_eq!(
+            lock_manager.lock_table.lock().unwrap()[&rid].requests.len(),
+            1
+        );
+        assert!(lock_manager.unlock(&older, rid));
+        assert!(lock_manager.lock_table.lock().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,103 @@
+use crate::buffer::replacer::PageId;
+use crate::concurrency::lock_manager::{LockMode, Rid};
+use crate::recovery::log_record::LogRecord;
+use std::collections::HashSet;
+use std::sync::Mutex;
+
+#[derive(Clone, Copy, PartialEq, Debug, Eq, Hash, Default, PartialOrd, Ord)]
+pub struct TxnId(pub(crate) u32);
+
+// Tags log records written outside of any transaction.
+pub const INVALID_TXN_ID: TxnId = TxnId(u32::MAX);
+
+#[derive(Clone, Copy, PartialEq, Debug, Eq)]
+pub enum TransactionState {
+    Running,
+    Committed,
+    Aborted,
+}
+
+#[derive(Debug)]
+pub struct Transaction {
+    txn_id: TxnId,
+    state: Mutex<TransactionState>,
+    // pages modified by this transaction
+    page_set: Mutex<HashSet<PageId>>,
+    // applied changes in order, undone in reverse on abort
+    write_set: Mutex<Vec<LogRecord>>,
+    shared_lock_set: Mutex<HashSet<Rid>>,
+    exclusive_lock_set: Mutex<HashSet<Rid>>,
+}
+
+impl Transaction {
+    pub fn new(txn_id: TxnId) -> Self {
+        Self {
+            txn_id,
+            state: Mutex::new(TransactionState::Running),
+            page_set: Mutex::new(HashSet::new()),
+            write_set: Mutex::new(Vec::new()),
+            shared_lock_set: Mutex::new(HashSet::new()),
+            exclusive_lock_set: Mutex::new(HashSet::new()),
+        }
+    }
+
+    pub fn get_txn_id(&self) -> TxnId {
+        self.txn_id
+    }
+
+    pub fn get_state(&self) -> TransactionState {
+        *self.state.lock().unwrap()
+    }
+
+    pub fn set_state(&self, state: TransactionState) {
+        *self.state.lock().unwrap() = state;
+    }
+
+    pub fn get_page_set(&self) -> HashSet<PageId> {
+        self.page_set.lock().unwrap().clone()
+    }
+
+    pub fn add_write_record(&self, record: LogRecord) {
+        self.page_set
+            .lock()
+            .unwrap()
+            .insert(record.get_bucket_page_id());
+        self.write_set.lock().unwrap().push(record);
+    }
+
+    pub fn take_write_set(&self) -> Vec<LogRecord> {
+        std::mem::take(&mut *self.write_set.lock().unwrap())
+    }
+
+    pub fn is_shared_locked(&self, rid: &Rid) -> bool {
+        self.shared_lock_set.lock().unwrap().contains(rid)
+    }
+
+    pub fn is_exclusive_locked(&self, rid: &Rid) -> bool {
+        self.exclusive_lock_set.lock().unwrap().contains(rid)
+    }
+
+    pub fn get_locked_rids(&self) -> Vec<Rid> {
+        let mut rids: Vec<_> = self
+            .shared_lock_set
+            .lock()
+            .unwrap()
+            .iter()
+            .copied()
+            .collect();
+        rids.extend(self.exclusive_lock_set.lock().unwrap().iter());
+        rids
+    }
+
+    pub(crate) fn add_lock(&self, rid: Rid, mode: LockMode) {
+        match mode {
+            LockMode::Shared => self.shared_lock_set.lock().unwrap().insert(rid),
+            LockMode::Exclusive => self.exclusive_lock_set.lock().unwrap().insert(rid),
+        };
+    }
+
+    pub(crate) fn remove_lock(&self, rid: &Rid) {
+        self.shared_lock_set.lock().unwrap().remove(rid);
+        self.exclusive_lock_set.lock().unwrap().remove(rid);
+    }
+}
@@ -0,0 +1,167 @@
+use crate::buffer::replacer::Replacer;
+use crate::concurrency::transaction::{Transaction, TransactionState, TxnId, INVALID_TXN_ID};
+use crate::container::extendible_hash_table::{ExtendibleHashTable, HashTableError};
+use crate::recovery::log_manager::LogManager;
+use crate::recovery::log_record::{LogRecord, LogRecordType};
+use crate::storage::disk::disk_manager::DiskManager;
+use crate::storage::pages::hash_table_bucket_page::Tool;
+use bytemuck::Pod;
+use std::hash::{BuildHasher, Hash};
+use std::sync::atomic::{AtomicU32, Ordering};
+
+pub struct TransactionManager<'a> {
+    next_txn_id: AtomicU32,
+    log_manager: &'a LogManager,
+}
+
+impl<'a> TransactionManager<'a> {
+    pub fn new(log_manager: &'a LogManager) -> Self {
+        // txn ids only need to be unique within the log, so continue after the ones it holds
+        let next_txn_id = log_manager
+            .read_log_records()
+            .iter()
+            .map(|record| record.get_txn_id())
+            .filter(|txn_id| *txn_id != INVALID_TXN_ID)
+            .map(|txn_id| txn_id.0 + 1)
+            .max()
+            .unwrap_or(0);
+        Self {
+            next_txn_id: AtomicU32::new(next_txn_id),
+            log_manager,
+        }
+    }
+
+    pub fn begin(&self) -> Transaction {
+        Transaction::new(TxnId(self.next_txn_id.fetch_add(1, Ordering::Relaxed)))
+    }
+
+    // The transaction is durable once this returns: the commit record and everything logged
+    // before it are flushed.
+    pub fn commit(&self, txn: &Transaction) {
+        assert_eq!(txn.get_state(), TransactionState::Running);
+        let mut record = LogRecord::new_txn_end(LogRecordType::Commit, txn.get_txn_id());
+        self.log_manager.append_log_record(&mut record);
+        self.log_manager.flush();
+        txn.take_write_set();
+        txn.set_state(TransactionState::Committed);
+    }
+
+    // Undoes the transaction's changes in reverse order. The compensating operations are logged
+    // under the same txn id, so recovery skips them together with the originals. If an undo
+    // fails the transaction is left Running with the remaining changes still in the table;
+    // it never committed, so recovering from the log drops all of it.
+    pub fn abort<R, D, K, V, H>(
+        &self,
+        txn: &Transaction,
+        table: &mut ExtendibleHashTable<'_, R, D, K, V, H>,
+    ) -> Result<(), HashTableError>
+    where
+        R: Replacer,
+        D: DiskManager,
+        K: Hash + Default + Copy + PartialEq + Pod,
+        H: BuildHasher,
+        V: Default + Copy + PartialEq + Pod,
+        [(); Tool::<K, V>::KV_NUM]:,
+        [(); Tool::<K, V>::BYTE_NUM]:,
+        [(); Tool::<K, V>::BLANK_SIZE]:,
+    {
+        assert_eq!(txn.get_state(), TransactionState::Running);
+        for record in txn.take_write_set().iter().rev() {
+            let key = record.get_key::<K>();
+            let value = record.get_value::<V>();
+            match record.get_type() {
+                LogRecordType::Insert => table.remove_with_txn(&key, &value, txn)?,
+                LogRecordType::Delete => table.insert_with_txn(&key, &value, txn)?,
+                LogRecordType::Commit | LogRecordType::Abort => unreachable!(),
+            };
+        }
+        // the compensating operations above went into the write set again
+        txn.take_write_set();
+        let mut record = LogRecord::new_txn_end(LogRecordType::Abort, txn.get_txn_id());
+        self.log_manager.append_log_record(&mut record);
+        txn.set_state(TransactionState::Aborted);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::buffer::buffer_pool_manager::ParallelBufferPoolManager;
+    use crate::buffer::replacer::LRUReplacer;
+    use crate::storage::disk::disk_manager::DiskManagerInstance;
+    use std::collections::hash_map::RandomState;
+    use std::sync::Arc;
+
+    #[test]
+    fn abort_test() {
+        let _ = std::fs::remove_file("test_txn_abort.log");
+        let disk_manager = Arc::new(DiskManagerInstance::new("test_txn_abort"));
+        let bpm =
+            ParallelBufferPoolManager::<LRUReplacer, DiskManagerInstance>::new(5, 10, disk_manager);
+        let log_manager = LogManager::new("test_txn_abort");
+        let txn_manager = TransactionManager::new(&log_manager);
+        let mut eht = ExtendibleHashTable::<_, _, i32, i32, _>::new_with_log_manager(
+            &bpm,
+            RandomState::new(),
+            &log_manager,
+        );
+        eht.insert(&0, &0).unwrap();
+
+        let txn = txn_manager.begin();
+        for i in 1..10 {
+            assert!(eht.insert_with_txn(&i, &i, &txn).unwrap());
+        }
+        assert!(eht.remove_with_txn(&0, &0, &txn).unwrap());
+        assert!(!txn.get_page_set().is_empty());
+        assert_eq!(eht.get_value(&5).unwrap(), vec![5]);
+        txn_manager.abort(&txn, &mut eht).unwrap();
+
+        assert_eq!(txn.get_state(), TransactionState::Aborted);
+        assert_eq!(eht.get_value(&0).unwrap(), vec![0]);
+        for i in 1..10 {
+            assert_eq!(eht.get_value(&i).unwrap(), vec![]);
+        }
+        std::fs::remove_file("test_txn_abort.log").unwrap();
+        std::fs::remove_file("test_txn_abort.db").unwrap();
+    }
+
+    #[test]
+    fn commit_test() {
+        let _ = std::fs::remove_file("test_txn_commit.log");
+        let disk_manager = Arc::new(DiskManagerInstance::new("test_txn_commit"));
+        let bpm =
+            ParallelBufferPoolManager::<LRUReplacer, DiskManagerInstance>::new(5, 10, disk_manager);
+        let log_manager = LogManager::new("test_txn_commit");
+        let txn_manager = TransactionManager::new(&log_manager);
+        let mut eht = ExtendibleHashTable::<_, _, i32, i32, _>::new_with_log_manager(
+            &bpm,
+            RandomState::new(),
+            &log_manager,
+        );
+        let txn = txn_manager.begin();
+        eht.insert_with_txn(&1, &1, &txn).unwrap();
+        eht.insert_with_txn(&2, &2, &txn).unwrap();
+        txn_manager.commit(&txn);
+
+        let records = log_manager.read_log_records();
+        let types: Vec<_> = records.iter().map(|record| record.get_type()).collect();
+        assert_eq!(
+            types,
+            vec![
+                LogRecordType::Insert,
+                LogRecordType::Insert,
+                LogRecordType::Commit
+            ]
+        );
+        assert!(records
+            .iter()
+            .all(|record| record.get_txn_id() == txn.get_txn_id()));
+        assert_eq!(
+            TransactionManager::new(&log_manager).begin().get_txn_id(),
+            TxnId(txn.get_txn_id().0 + 1)
+        );
+        std::fs::remove_file("test_txn_commit.log").unwrap();
+        std::fs::remove_file("test_txn_commit.db").unwrap();
+    }
+}
@@ -0,0 +1,179 @@
+use crate::buffer::buffer_pool_manager::{BufferError, ParallelBufferPoolManager};
+use crate::buffer::replacer::{PageId, Replacer};
+use crate::container::extendible_hash_table::{ExtendibleHashTable, HashTableError};
+use crate::storage::disk::disk_manager::{DiskManager, PAGE_SIZE};
+use std::hash::BuildHasher;
+
+// The length prefix of an overflow page.
+const LEN_SIZE: usize = 4;
+
+pub const MAX_VALUE_SIZE: usize = PAGE_SIZE - LEN_SIZE;
+
+// An i64 -> bytes table for values too big to store inline. Each value lives in its own
+// overflow page and the bucket only holds (key, overflow page id), so buckets stay as dense
+// as an i64 -> u64 table.
+pub struct BlobTable<'a, R, D, H>
+where
+    R: Replacer,
+    D: DiskManager,
+    H: BuildHasher,
+{
+    bpm: &'a ParallelBufferPoolManager<R, D>,
+    table: ExtendibleHashTable<'a, R, D, i64, u64, H>,
+}
+
+impl<'a, R, D, H> BlobTable<'a, R, D, H>
+where
+    R: Replacer,
+    D: DiskManager,
+    H: BuildHasher,
+{
+    pub fn new(
+        bpm: &'a ParallelBufferPoolManager<R, D>,
+        table: ExtendibleHashTable<'a, R, D, i64, u64, H>,
+    ) -> Self {
+        Self { bpm, table }
+    }
+
+    pub fn into_inner(self) -> ExtendibleHashTable<'a, R, D, i64, u64, H> {
+        self.table
+    }
+
+    // Fails if an overflow page can't be read.
+    pub fn get_value(&self, key: i64) -> Result<Vec<Vec<u8>>, HashTableError> {
+        self.table
+            .get_value(&key)?
+            .into_iter()
+            .map(|page_id| self.read_value(PageId::new(page_id as u32)))
+            .collect()
+    }
+
+    // Returns false if the key already has this value. Panics if the value doesn't fit in a
+    // page.
+    pub fn insert(&mut self, key: i64, value: &[u8]) -> Result<bool, HashTableError> {
+        assert!(
+            value.len() <= MAX_VALUE_SIZE,
+            "value of {} bytes doesn't fit in an overflow page",
+            value.len()
+        );
+        if self.get_value(key)?.iter().any(|v| v == value) {
+            return Ok(false);
+        }
+        let mut page_id = PageId::default();
+        let data = self
+            .bpm
+            .new_page(&mut page_id)
+            .ok_or(BufferError::NoFreeFrame)?;
+        {
+            let mut data = data.write().unwrap();
+            data.0[..LEN_SIZE].copy_from_slice(&(value.len() as u32).to_le_bytes());
+            data.0[LEN_SIZE..LEN_SIZE + value.len()].copy_from_slice(value);
+        }
+        self.bpm.unpin_page(page_id, true);
+        match self.table.insert(&key, &(page_id.0 as u64)) {
+            Ok(true) => Ok(true),
+            result => {
+                self.bpm.delete_page(page_id)?;
+                result
+            }
+        }
+    }
+
+    // Also frees the overflow page.
+    pub fn remove(&mut self, key: i64, value: &[u8]) -> Result<bool, HashTableError> {
+        for overflow_id in self.table.get_value(&key)? {
+            let page_id = PageId::new(overflow_id as u32);
+            if self.read_value(page_id)? == value {
+                self.table.remove(&key, &overflow_id)?;
+                self.bpm.delete_page(page_id)?;
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    fn read_value(&self, page_id: PageId) -> Result<Vec<u8>, HashTableError> {
+        let data = self.bpm.try_fetch_page(page_id)?;
+        let value = {
+            let data = data.read().unwrap();
+            let len = u32::from_le_bytes(data.0[..LEN_SIZE].try_into().unwrap()) as usize;
+            data.0[LEN_SIZE..LEN_SIZE + len].to_vec()
+        };
+        self.bpm.unpin_page(page_id, false);
+        Ok(value)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::buffer::replacer::LRUReplacer;
+    use crate::storage::disk::disk_manager::DiskManagerInstance;
+    use crate::storage::disk::faulty_disk_manager::FaultyDiskManager;
+    use crate::storage::disk::in_memory_disk_manager::InMemoryDiskManager;
+    use std::collections::hash_map::RandomState;
+    use std::sync::Arc;
+
+    fn value_of(key: i64) -> Vec<u8> {
+        (0..1024).map(|i| (key as usize * 7 + i) as u8).collect()
+    }
+
+    #[test]
+    fn blob_table_test() {
+        let disk_manager = Arc::new(DiskManagerInstance::new("test_blob_table"));
+        let bpm = ParallelBufferPoolManager::<LRUReplacer, _>::new(5, 10, disk_manager);
+        let table = ExtendibleHashTable::new(&bpm, RandomState::new());
+        let mut blobs = BlobTable::new(&bpm, table);
+        for key in 0..500 {
+            assert!(blobs.insert(key, &value_of(key)).unwrap());
+        }
+        assert!(!blobs.insert(7, &value_of(7)).unwrap());
+        for key in 0..500 {
+            assert_eq!(blobs.get_value(key).unwrap(), vec![value_of(key)]);
+        }
+        assert!(blobs.remove(7, &value_of(7)).unwrap());
+        assert!(!blobs.remove(7, &value_of(7)).unwrap());
+        assert!(blobs.get_value(7).unwrap().is_empty());
+
+        // 1KB values inline would only fit 3 to a page
+        let table = blobs.into_inner();
+        assert_eq!(table.len(), 499);
+        assert!(
+            table
+                .occupancy_histogram()
+                .unwrap()
+                .into_iter()
+                .max()
+                .unwrap()
+                > 100
+        );
+        std::fs::remove_file("test_blob_table.db").unwrap();
+    }
+
+    #[test]
+    fn blob_table_error_test() {
+        let disk_manager = Arc::new(FaultyDiskManager::new(InMemoryDiskManager::new()));
+        let bpm = ParallelBufferPoolManager::<LRUReplacer, _>::new(1, 4, disk_manager.clone());
+        let table = ExtendibleHashTable::new(&bpm, RandomState::new());
+        let mut blobs = BlobTable::new(&bpm, table);
+        assert!(blobs.insert(0, &value_of(0)).unwrap());
+        // every page is dirty, so making room for the overflow page means a write
+        disk_manager.fail_nth_write(1);
+        assert!(matches!(
+            blobs.insert(1, &value_of(1)),
+            Err(HashTableError::Buffer(BufferError::NoFreeFrame))
+        ));
+        assert!(blobs.get_value(1).unwrap().is_empty());
+        for key in 1..5 {
+            assert!(blobs.insert(key, &value_of(key)).unwrap());
+        }
+
+        // the directory and the bucket are resident, the first overflow page is not
+        disk_manager.fail_nth_read(1);
+        assert!(matches!(
+            blobs.get_value(0),
+            Err(HashTableError::Buffer(BufferError::Io(_)))
+        ));
+        assert_eq!(blobs.get_value(0).unwrap(), vec![value_of(0)]);
+    }
+}
@@ -0,0 +1,234 @@
+use crate::buffer::buffer_pool_manager::ParallelBufferPoolManager;
+use crate::buffer::replacer::{PageId, Replacer};
+use crate::container::extendible_hash_table::{ExtendibleHashTable, HashTableError};
+use crate::storage::disk::disk_manager::DiskManager;
+use crate::storage::pages::hash_table_bucket_page::Tool;
+use bytemuck::{bytes_of, Pod};
+use std::collections::{BTreeMap, HashMap};
+use std::hash::{BuildHasher, Hash};
+
+// A bounded key -> value cache kept in an extendible hash table. Keys and values are stored as
+// their Pod bytes. Once more than capacity keys are cached, put drops the least recently used
+// one. Recency lives in memory only, after open every key counts as equally old.
+pub struct DiskCache<'a, R, D, K, V, H>
+where
+    R: Replacer,
+    D: DiskManager,
+    K: Hash,
+    H: BuildHasher,
+{
+    table: ExtendibleHashTable<'a, R, D, K, V, H>,
+    capacity: usize,
+    // key bytes -> last use, and last use -> key
+    last_used: HashMap<Vec<u8>, u64>,
+    by_last_use: BTreeMap<u64, K>,
+    tick: u64,
+}
+
+impl<'a, R, D, K: 'static, V: 'static, H> DiskCache<'a, R, D, K, V, H>
+where
+    R: Replacer,
+    D: DiskManager,
+    K: Hash + Default + Copy + PartialEq + Pod,
+    H: BuildHasher,
+    V: Default + Copy + PartialEq + Pod,
+    [(); Tool::<K, V>::KV_NUM]:,
+    [(); Tool::<K, V>::BYTE_NUM]:,
+    [(); Tool::<K, V>::BLANK_SIZE]:,
+{
+    pub fn new(bpm: &'a ParallelBufferPoolManager<R, D>, hash_fn: H, capacity: usize) -> Self {
+        Self::with_table(ExtendibleHashTable::new(bpm, hash_fn), capacity)
+    }
+
+    // Reopens a cache whose table header is at header_page_id.
+    pub fn open(
+        bpm: &'a ParallelBufferPoolManager<R, D>,
+        hash_fn: H,
+        header_page_id: PageId,
+        capacity: usize,
+    ) -> Result<Self, HashTableError> {
+        let mut cache = Self::with_table(
+            ExtendibleHashTable::open(bpm, hash_fn, header_page_id)?,
+            capacity,
+        );
+        let keys: Vec<K> = cache.table.iter()?.map(|(key, _)| key).collect();
+        for key in keys {
+            cache.touch(&key);
+        }
+        Ok(cache)
+    }
+
+    fn with_table(table: ExtendibleHashTable<'a, R, D, K, V, H>, capacity: usize) -> Self {
+        assert!(capacity > 0, "a cache needs room for at least one key");
+        Self {
+            table,
+            capacity,
+            last_used: HashMap::new(),
+            by_last_use: BTreeMap::new(),
+            tick: 0,
+        }
+    }
+
+    pub fn get_header_page_id(&self) -> PageId {
+        self.table.get_header_page_id()
+    }
+
+    pub fn get(&mut self, key: &K) -> Result<Option<V>, HashTableError> {
+        let Some(value) = self.table.get_value(key)?.first().copied() else {
+            return Ok(None);
+        };
+        self.touch(key);
+        Ok(Some(value))
+    }
+
+    // Doesn't count as a use.
+    pub fn contains(&self, key: &K) -> Result<bool, HashTableError> {
+        Ok(!self.table.get_value(key)?.is_empty())
+    }
+
+    // Replaces the value of key if there was one. The new value goes in before the old one
+    // comes out, so a failed put leaves the old value in place.
+    pub fn put(&mut self, key: &K, value: &V) -> Result<(), HashTableError> {
+        let old_values = self.table.get_value(key)?;
+        self.table.insert(key, value)?;
+        for old_value in old_values.iter().filter(|old_value| *old_value != value) {
+            self.table.remove(key, old_value)?;
+        }
+        self.touch(key);
+        if self.last_used.len() > self.capacity {
+            let (_, oldest) = self.by_last_use.pop_first().unwrap();
+            self.last_used.remove(bytes_of(&oldest));
+            self.table.remove_key(&oldest)?;
+        }
+        Ok(())
+    }
+
+    pub fn remove(&mut self, key: &K) -> Result<bool, HashTableError> {
+        if let Some(tick) = self.last_used.remove(bytes_of(key)) {
+            self.by_last_use.remove(&tick);
+        }
+        Ok(self.table.remove_key(key)? > 0)
+    }
+
+    pub fn len(&self) -> usize {
+        self.last_used.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.last_used.is_empty()
+    }
+
+    fn touch(&mut self, key: &K) {
+        self.tick += 1;
+        if let Some(tick) = self.last_used.insert(bytes_of(key).to_vec(), self.tick) {
+            self.by_last_use.remove(&tick);
+        }
+        self.by_last_use.insert(self.tick, *key);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::buffer::buffer_pool_manager::BufferError;
+    use crate::buffer::page_id_allocator::Sequential;
+    use crate::buffer::replacer::LRUReplacer;
+    use crate::storage::disk::disk_manager::DiskManagerInstance;
+    use crate::storage::disk::in_memory_disk_manager::InMemoryDiskManager;
+    use std::collections::hash_map::DefaultHasher;
+    use std::hash::BuildHasherDefault;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    type Hasher = BuildHasherDefault<DefaultHasher>;
+
+    #[test]
+    fn disk_cache_test() {
+        let disk_manager = Arc::new(DiskManagerInstance::new("test_disk_cache"));
+        let bpm = ParallelBufferPoolManager::<LRUReplacer, _>::new(2, 5, disk_manager);
+        let mut cache = DiskCache::<_, _, i64, i64, _>::new(&bpm, Hasher::default(), 3);
+        cache.put(&1, &10).unwrap();
+        cache.put(&2, &20).unwrap();
+        cache.put(&3, &30).unwrap();
+        cache.put(&1, &11).unwrap();
+        assert_eq!(cache.get(&1).unwrap(), Some(11));
+        assert_eq!(cache.len(), 3);
+        // 2 is the least recently used
+        cache.put(&4, &40).unwrap();
+        assert!(!cache.contains(&2).unwrap());
+        assert_eq!(cache.get(&2).unwrap(), None);
+        assert_eq!(cache.get(&3).unwrap(), Some(30));
+
+        assert!(cache.remove(&3).unwrap());
+        assert!(!cache.remove(&3).unwrap());
+        assert_eq!(cache.len(), 2);
+        cache.put(&5, &50).unwrap();
+        assert!(
+            cache.contains(&1).unwrap()
+                && cache.contains(&4).unwrap()
+                && cache.contains(&5).unwrap()
+        );
+        std::fs::remove_file("test_disk_cache.db").unwrap();
+    }
+
+    #[test]
+    fn disk_cache_reopen_test() {
+        let disk_manager = Arc::new(DiskManagerInstance::new("test_disk_cache_reopen"));
+        let header_page_id = {
+            let bpm = ParallelBufferPoolManager::<LRUReplacer, _>::with_allocator(
+                2,
+                5,
+                disk_manager.clone(),
+                Arc::new(Sequential::new(0)),
+            );
+            let mut cache = DiskCache::<_, _, i64, i64, _>::new(&bpm, Hasher::default(), 100);
+            for i in 0..50 {
+                cache.put(&i, &(i * 2)).unwrap();
+            }
+            let header_page_id = cache.get_header_page_id();
+            drop(cache);
+            bpm.flush_all_pages().unwrap();
+            header_page_id
+        };
+
+        let bpm = ParallelBufferPoolManager::<LRUReplacer, _>::with_allocator(
+            2,
+            5,
+            disk_manager,
+            Arc::new(Sequential::new(1000)),
+        );
+        let mut cache =
+            DiskCache::<_, _, i64, i64, _>::open(&bpm, Hasher::default(), header_page_id, 100)
+                .unwrap();
+        assert_eq!(cache.len(), 50);
+        for i in 0..50 {
+            assert_eq!(cache.get(&i).unwrap(), Some(i * 2));
+        }
+        std::fs::remove_file("test_disk_cache_reopen.db").unwrap();
+    }
+
+    #[test]
+    fn disk_cache_failed_put_test() {
+        let disk_manager = Arc::new(InMemoryDiskManager::new());
+        let bpm = ParallelBufferPoolManager::<LRUReplacer, _>::new(1, 4, disk_manager);
+        let mut cache = DiskCache::<_, _, i64, i64, _>::new(&bpm, Hasher::default(), 1000);
+        cache.table.set_split_timeout(Duration::from_millis(20));
+        let bucket_size = Tool::<i64, i64>::KV_NUM as i64;
+        for i in 0..bucket_size {
+            cache.put(&i, &i).unwrap();
+        }
+        // the only bucket is full, so the new value needs a split, and there is no frame left
+        // for the new bucket
+        let mut page_id = PageId(0);
+        bpm.new_page(&mut page_id).unwrap();
+        assert!(matches!(
+            cache.put(&0, &1),
+            Err(HashTableError::Buffer(BufferError::NoFreeFrame))
+        ));
+        assert_eq!(cache.get(&0).unwrap(), Some(0));
+        bpm.unpin_page(page_id, false);
+        cache.put(&0, &1).unwrap();
+        assert_eq!(cache.get(&0).unwrap(), Some(1));
+        assert_eq!(cache.len(), bucket_size as usize);
+    }
+}
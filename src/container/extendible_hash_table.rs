@@ -1,15 +1,26 @@
-use crate::buffer::buffer_pool_manager::ParallelBufferPoolManager;
+use crate::buffer::backoff::Backoff;
+use crate::buffer::buffer_pool_manager::{BufferError, ParallelBufferPoolManager};
 use crate::buffer::replacer::{PageId, Replacer};
-use crate::storage::disk::disk_manager::DiskManager;
+use crate::concurrency::transaction::Transaction;
+use crate::recovery::log_manager::LogManager;
+use crate::recovery::log_record::{LogRecord, LogRecordType};
+use crate::storage::disk::disk_manager::{DiskManager, PAGE_SIZE};
 use crate::storage::pages::hash_table_bucket_page::{HashTableBucketPage, InertResult, Tool};
-use crate::storage::pages::hash_table_directory_page::HashTableDirectoryPage;
-use crate::storage::pages::page::{Data, Page};
-use bytemuck::{cast_mut, cast_ref};
+use crate::storage::pages::hash_table_directory_page::{HashTableDirectoryPage, MAX_GLOBAL_DEPTH};
+use crate::storage::pages::hash_table_header_page::{
+    HashTableHeaderPage, COUNT_RANGE, HEADER_VERSION,
+};
+use crate::storage::pages::page::{Data, InitPage, Page};
+use bytemuck::{cast_mut, cast_ref, Pod};
 use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, HashSet};
 use std::hash::{BuildHasher, Hash, Hasher};
+use std::io;
 use std::marker::PhantomData;
+use std::ops::Add;
 use std::ptr::hash;
 use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
 struct EHTContext {
     dir_data: Data,
@@ -19,6 +30,44 @@ struct EHTContext {
     bucket_index: usize,
 }
 
+impl EHTContext {
+    // Whether the directory still routes bucket_index where the context was read from. A split
+    // of the bucket deepens the slot, and buckets are never merged.
+    fn is_current(&self, dir: &HashTableDirectoryPage) -> bool {
+        dir.get_bucket_page_id(self.bucket_index) == self.bucket_pid
+            && dir.get_local_depth(self.bucket_index) == self.local_depth
+    }
+}
+
+// Why an operation failed. The table is left as it was before the call.
+#[derive(Debug)]
+pub enum HashTableError {
+    // the bucket is full and the directory can't double any further
+    DirectoryFull,
+    // the page a table was opened by isn't a hash table header of this format version
+    BadHeader { page_id: PageId, version: u32 },
+    // a page could not be fetched, or no frame came free for the new bucket of a split within
+    // the split timeout (pages are probably pinned and leaked)
+    Buffer(BufferError),
+}
+
+impl From<BufferError> for HashTableError {
+    fn from(err: BufferError) -> Self {
+        HashTableError::Buffer(err)
+    }
+}
+
+const DEFAULT_SPLIT_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InsertOutcome {
+    Inserted,
+    // the (key, value) pair was already there, nothing changed
+    AlreadyPresent,
+    // inserted, after splitting the bucket at least once
+    Split,
+}
+
 pub struct ExtendibleHashTable<'a, R, D, K, V, H>
 where
     R: Replacer,
@@ -26,9 +75,16 @@ where
     K: Hash,
     H: BuildHasher,
 {
+    header_page_id: PageId,
+    // pinned for as long as the handle lives, so keeping the count costs no fetch
+    header_data: Data,
     dir_page_id: PageId,
     bpm: &'a ParallelBufferPoolManager<R, D>,
     hash_fn: H,
+    log_manager: Option<&'a LogManager>,
+    // pages this table has pinned and not unpinned yet, released on drop
+    pins: Mutex<HashMap<PageId, usize>>,
+    split_timeout: Duration,
     phantom_data: PhantomData<(K, V)>,
 }
 
@@ -36,217 +92,1009 @@ impl<'a, R, D, K: 'static, V: 'static, H> ExtendibleHashTable<'a, R, D, K, V, H>
 where
     R: Replacer,
     D: DiskManager,
-    K: Hash + Default + Copy + PartialEq,
+    K: Hash + Default + Copy + PartialEq + Pod,
     H: BuildHasher,
-    V: Default + Copy + PartialEq,
+    V: Default + Copy + PartialEq + Pod,
     [(); Tool::<K, V>::KV_NUM]:,
     [(); Tool::<K, V>::BYTE_NUM]:,
     [(); Tool::<K, V>::BLANK_SIZE]:,
 {
     pub fn new(bpm: &'a ParallelBufferPoolManager<R, D>, hash_fn: H) -> Self {
-        let mut dir_page_id = PageId(0);
-        let mut dir_data = bpm.new_page_blocking(&mut dir_page_id);
-        let mut dir_data = dir_data.write().unwrap();
-        let dir: &mut HashTableDirectoryPage = cast_mut(&mut **dir_data);
-        dir.set_page_id(dir_page_id);
-        let mut bucket_page_id = PageId(0);
-        let mut bucket_data = bpm.new_page_blocking(&mut bucket_page_id);
-        dir.set_bucket_page_id(0, bucket_page_id);
-        dir.set_local_depth(0, 0);
+        Self::new_with_depth(bpm, hash_fn, 0)
+    }
+
+    // Starts with 2^depth slots, each with a bucket of its own, so a bulk load of many keys
+    // doesn't go through all the early splits. Panics if depth is over MAX_GLOBAL_DEPTH.
+    pub fn new_with_depth(
+        bpm: &'a ParallelBufferPoolManager<R, D>,
+        hash_fn: H,
+        depth: u32,
+    ) -> Self {
+        assert!(
+            depth <= MAX_GLOBAL_DEPTH,
+            "initial depth {} is over the directory limit of {}",
+            depth,
+            MAX_GLOBAL_DEPTH
+        );
+        let (header_page_id, header_data) = bpm.new_typed_page_blocking::<HashTableHeaderPage>();
+        let (dir_page_id, dir_data) = bpm.new_typed_page_blocking::<HashTableDirectoryPage>();
+        {
+            let mut header_data = header_data.write().unwrap();
+            let header: &mut HashTableHeaderPage = cast_mut(&mut **header_data);
+            header.set_dir_page_id(dir_page_id);
+        }
+        // the handle keeps the header pinned
+        bpm.mark_dirty_range(header_page_id, 0, PAGE_SIZE);
+        {
+            let mut dir_data = dir_data.write().unwrap();
+            let dir: &mut HashTableDirectoryPage = cast_mut(&mut **dir_data);
+            dir.set_global_depth(depth);
+            for i in 0..dir.size() {
+                let (bucket_page_id, _) =
+                    bpm.new_typed_page_blocking::<HashTableBucketPage<K, V>>();
+                dir.set_bucket(i, bucket_page_id, depth as u8);
+                bpm.unpin_page(bucket_page_id, true);
+            }
+        }
         bpm.unpin_page(dir_page_id, true);
-        bpm.unpin_page(bucket_page_id, false);
-        Self {
+        Self::with_header(bpm, hash_fn, header_page_id, header_data)
+            .expect("a new header is of this version")
+    }
+
+    // Another handle on the table whose header is at header_page_id. hash_fn has to hash like
+    // the one the table was created with. The header stays pinned until the handle is dropped.
+    // Fails with BadHeader if the page isn't a header of this format version.
+    pub fn open(
+        bpm: &'a ParallelBufferPoolManager<R, D>,
+        hash_fn: H,
+        header_page_id: PageId,
+    ) -> Result<Self, HashTableError> {
+        let header_data = bpm.fetch_page_blocking(header_page_id)?;
+        Self::with_header(bpm, hash_fn, header_page_id, header_data)
+    }
+
+    // Takes over the pin on the header, and releases it if the header is bad.
+    fn with_header(
+        bpm: &'a ParallelBufferPoolManager<R, D>,
+        hash_fn: H,
+        header_page_id: PageId,
+        header_data: Data,
+    ) -> Result<Self, HashTableError> {
+        let (version, dir_page_id) = {
+            let header_data = header_data.read().unwrap();
+            let header: &HashTableHeaderPage = cast_ref(&**header_data);
+            (header.get_version(), header.get_dir_page_id())
+        };
+        if version != HEADER_VERSION {
+            bpm.unpin_page(header_page_id, false);
+            return Err(HashTableError::BadHeader {
+                page_id: header_page_id,
+                version,
+            });
+        }
+        Ok(Self {
+            header_page_id,
+            header_data,
             dir_page_id,
             bpm,
             hash_fn,
+            log_manager: None,
+            pins: Mutex::new(HashMap::new()),
+            split_timeout: DEFAULT_SPLIT_TIMEOUT,
             phantom_data: PhantomData,
+        })
+    }
+
+    // How long a split waits for a frame to come free before failing with NoFreeFrame.
+    pub fn set_split_timeout(&mut self, split_timeout: Duration) {
+        self.split_timeout = split_timeout;
+    }
+
+    // The page to open the table by.
+    pub fn get_header_page_id(&self) -> PageId {
+        self.header_page_id
+    }
+
+    pub fn get_dir_page_id(&self) -> PageId {
+        self.dir_page_id
+    }
+
+    // Moves the cached entry count. Called with the latch of the bucket that changed held, so
+    // the count never runs behind a change another thread can already see.
+    fn add_to_count(&self, delta: i64) {
+        if delta == 0 {
+            return;
+        }
+        {
+            let mut header_data = self.header_data.write().unwrap();
+            let header: &mut HashTableHeaderPage = cast_mut(&mut **header_data);
+            header.add_count(delta);
         }
+        self.bpm
+            .mark_dirty_range(self.header_page_id, COUNT_RANGE.start, COUNT_RANGE.end);
     }
 
-    fn hash(&self, key: &K) -> u64 {
-        let mut hasher = self.hash_fn.build_hasher();
-        key.hash(&mut hasher);
-        hasher.finish()
+    // Every successful insert/remove appends a log record before the bucket is unpinned dirty.
+    pub fn new_with_log_manager(
+        bpm: &'a ParallelBufferPoolManager<R, D>,
+        hash_fn: H,
+        log_manager: &'a LogManager,
+    ) -> Self {
+        let mut table = Self::new(bpm, hash_fn);
+        table.log_manager = Some(log_manager);
+        table
     }
 
-    // You should call unpin_page the data is not needed anymore.
-    fn pid_to_page_data(&self, page_id: PageId) -> Data {
-        let mut data = self.bpm.fetch_page(page_id);
-        while data.is_none() {
-            data = self.bpm.fetch_page(page_id);
+    // Also stamps the lsn on the bucket, which has to be the one at bucket_pid.
+    fn append_log_record(
+        &self,
+        record_type: LogRecordType,
+        bucket_pid: PageId,
+        bucket: &mut HashTableBucketPage<K, V>,
+        key: &K,
+        value: &V,
+        txn: Option<&Transaction>,
+    ) {
+        let mut record = LogRecord::new(record_type, self.dir_page_id, bucket_pid, key, value);
+        if let Some(txn) = txn {
+            record.set_txn_id(txn.get_txn_id());
+        }
+        if let Some(log_manager) = self.log_manager {
+            let lsn = log_manager.append_log_record(&mut record);
+            bucket.set_lsn(lsn);
+            self.bpm.set_page_lsn(bucket_pid, lsn);
+        }
+        if let Some(txn) = txn {
+            txn.add_write_record(record);
+        }
+    }
+
+    // Another handle on an existing table, logging like new_with_log_manager.
+    pub fn open_with_log_manager(
+        bpm: &'a ParallelBufferPoolManager<R, D>,
+        hash_fn: H,
+        header_page_id: PageId,
+        log_manager: &'a LogManager,
+    ) -> Result<Self, HashTableError> {
+        let mut table = Self::open(bpm, hash_fn, header_page_id)?;
+        table.log_manager = Some(log_manager);
+        Ok(table)
+    }
+
+    // Replays a logged insert/remove without logging it again, unless the bucket the key lives
+    // in already holds it: a bucket is written to disk together with the lsn of the latest
+    // change it took, so records at or below that lsn are skipped. Returns whether the table
+    // changed. The cached count is left alone, see recount.
+    pub fn apply_log_record(&mut self, record: &LogRecord) -> Result<bool, HashTableError> {
+        if !matches!(
+            record.get_type(),
+            LogRecordType::Insert | LogRecordType::Delete
+        ) {
+            return Ok(false);
+        }
+        let (key, value) = (record.get_key::<K>(), record.get_value::<V>());
+        // None if the bucket is full
+        let (context, changed) = self.update_bucket(self.hash_key(&key), |_, bucket| {
+            let changed = if bucket.get_lsn() >= Some(record.get_lsn()) {
+                Some(false)
+            } else if record.get_type() == LogRecordType::Delete {
+                Some(bucket.remove(&key, &value))
+            } else {
+                match bucket.insert(&key, &value) {
+                    InertResult::Success => Some(true),
+                    InertResult::Duplicate => Some(false),
+                    InertResult::Full => None,
+                }
+            };
+            if changed == Some(true) {
+                bucket.set_lsn(record.get_lsn());
+            }
+            changed
+        })?;
+        match changed {
+            Some(changed) => {
+                self.unpin_page(self.dir_page_id, false);
+                self.unpin_page(context.bucket_pid, changed);
+                Ok(changed)
+            }
+            None => {
+                self.bucket_split(&context)?;
+                self.apply_log_record(record)
+            }
         }
-        data.unwrap()
+    }
+
+    // Empties the buckets the disk manager finds torn (see DiskManager::scan_for_torn_pages),
+    // lsn included, so that replaying the log refills them: a torn bucket may carry any lsn.
+    // Torn pages that aren't buckets of this table are left alone, and disk managers that can't
+    // scan are taken to have none. Meant for a table just opened after a crash, a resident
+    // copy of a bucket would be emptied too. Returns the buckets emptied.
+    pub fn reset_torn_buckets(&mut self) -> Result<Vec<PageId>, HashTableError> {
+        let torn: HashSet<_> = match self.bpm.scan_for_torn_pages() {
+            Ok(torn) => torn.into_iter().collect(),
+            Err(err) if err.kind() == io::ErrorKind::Unsupported => return Ok(Vec::new()),
+            Err(err) => return Err(BufferError::Io(err).into()),
+        };
+        let dir_data = self.get_dir_data()?;
+        let mut bucket_page_ids = Self::bucket_page_ids(&dir_data);
+        self.unpin_page(self.dir_page_id, false);
+        bucket_page_ids.retain(|page_id| torn.contains(page_id));
+        self.visit_pages(bucket_page_ids.iter().copied(), |page_id, data| {
+            let mut data = data.write().unwrap();
+            data.0 = [0; PAGE_SIZE];
+            let bucket: &mut HashTableBucketPage<K, V> = cast_mut(&mut **data);
+            bucket.init(page_id);
+            true
+        })?;
+        Ok(bucket_page_ids)
+    }
+
+    // Sets the cached count to the number of entries in the buckets, e.g. after recovery
+    // replayed records into them. Returns the count.
+    pub fn recount(&mut self) -> Result<usize, HashTableError> {
+        let len = self.iter()?.count();
+        self.add_to_count(len as i64 - self.len() as i64);
+        Ok(len)
+    }
+
+    // The hash the table places key by, to compute once for insert_prehashed.
+    pub fn hash_key(&self, key: &K) -> u64 {
+        self.hash_fn.hash_one(key)
+    }
+
+    // You should call unpin_page the data is not needed anymore. Waits for a frame while the
+    // pool is full, but gives up on disk errors.
+    fn try_pid_to_page_data(&self, page_id: PageId) -> Result<Data, BufferError> {
+        let data = self.bpm.fetch_page_blocking(page_id)?;
+        self.track_pin(page_id);
+        Ok(data)
+    }
+
+    // Runs f on each page in turn, pinned only while f runs, and unpins it dirty if f says so.
+    // Stops at the first page that can't be fetched.
+    fn visit_pages(
+        &self,
+        page_ids: impl IntoIterator<Item = PageId>,
+        mut f: impl FnMut(PageId, &Data) -> bool,
+    ) -> Result<(), BufferError> {
+        for page_id in page_ids {
+            let data = self.try_pid_to_page_data(page_id)?;
+            let dirty = f(page_id, &data);
+            self.unpin_page(page_id, dirty);
+        }
+        Ok(())
+    }
+
+    fn track_pin(&self, page_id: PageId) {
+        *self.pins.lock().unwrap().entry(page_id).or_insert(0) += 1;
+    }
+
+    fn unpin_page(&self, page_id: PageId, is_dirty: bool) {
+        let mut pins = self.pins.lock().unwrap();
+        let count = pins.get_mut(&page_id).unwrap();
+        *count -= 1;
+        if *count == 0 {
+            pins.remove(&page_id);
+        }
+        self.bpm.unpin_page(page_id, is_dirty);
     }
     // You should call unpin_page the data is not needed anymore.
-    fn get_dir_data(&self) -> Data {
-        self.pid_to_page_data(self.dir_page_id)
+    fn get_dir_data(&self) -> Result<Data, BufferError> {
+        self.try_pid_to_page_data(self.dir_page_id)
     }
     // You should call unpin_page the data is not needed anymore.Twice,for both dir and bucket!!!
-    fn get_context(&self, key: &K) -> EHTContext {
-        let dir_data = self.get_dir_data();
+    // On error nothing is left pinned.
+    fn get_context_hashed(&self, hash: u64) -> Result<EHTContext, BufferError> {
+        self.get_context_at(|dir| (hash & ((1 << dir.get_global_depth()) - 1)) as usize)
+    }
+
+    // Like get_context, for the slot bucket_index picks from the directory.
+    fn get_context_at(
+        &self,
+        bucket_index: impl FnOnce(&HashTableDirectoryPage) -> usize,
+    ) -> Result<EHTContext, BufferError> {
+        let dir_data = self.try_pid_to_page_data(self.dir_page_id)?;
         let dir_data_rd = dir_data.read().unwrap();
         let dir: &HashTableDirectoryPage = cast_ref(&**dir_data_rd);
-        let bucket_index = self.key_to_index(key, dir_data.clone());
-        let bucket_pid = dir.get_bucket_page_id(bucket_index as usize);
-        EHTContext {
+        let bucket_index = bucket_index(dir);
+        let bucket_pid = dir.get_bucket_page_id(bucket_index);
+        let bucket_data = match self.try_pid_to_page_data(bucket_pid) {
+            Ok(bucket_data) => bucket_data,
+            Err(err) => {
+                self.unpin_page(self.dir_page_id, false);
+                return Err(err);
+            }
+        };
+        Ok(EHTContext {
             dir_data: dir_data.clone(),
-            bucket_data: self.pid_to_page_data(bucket_pid),
-            local_depth: dir.get_local_depth(bucket_index as usize),
+            bucket_data,
+            local_depth: dir.get_local_depth(bucket_index),
             bucket_pid,
-            bucket_index: bucket_index as usize,
+            bucket_index,
+        })
+    }
+
+    // Runs f on the bucket hash routes to, write-latched, and returns its result along with the
+    // context, both pages still pinned. The directory stays read-latched around f so no split
+    // moves entries out from under it. If another handle split the bucket after the context
+    // was read, the lookup starts over.
+    fn update_bucket<T>(
+        &self,
+        hash: u64,
+        f: impl FnOnce(PageId, &mut HashTableBucketPage<K, V>) -> T,
+    ) -> Result<(EHTContext, T), BufferError> {
+        let mut f = Some(f);
+        loop {
+            let context = self.get_context_hashed(hash)?;
+            let result = {
+                let dir_data = context.dir_data.read().unwrap();
+                if context.is_current(cast_ref(&**dir_data)) {
+                    let mut bucket_data = context.bucket_data.write().unwrap();
+                    let f = f.take().unwrap();
+                    Some(f(context.bucket_pid, cast_mut(&mut **bucket_data)))
+                } else {
+                    None
+                }
+            };
+            match result {
+                Some(result) => return Ok((context, result)),
+                None => {
+                    self.unpin_page(self.dir_page_id, false);
+                    self.unpin_page(context.bucket_pid, false);
+                }
+            }
         }
     }
 
-    fn key_to_index(&self, key: &K, dir_data: Data) -> u64 {
-        let dir_data_rd = dir_data.read().unwrap();
-        let dir: &HashTableDirectoryPage = cast_ref(&**dir_data_rd);
-        let global_depth = dir.get_global_depth();
-        let mask = (1 << global_depth) - 1;
-        self.hash(key) & mask
-    }
-
-    pub fn get_value(&self, key: &K) -> Vec<V> {
-        let context = self.get_context(key);
-        let mut result = Vec::new();
-        let bucket_data = context.bucket_data.read().unwrap();
-        let bucket: &HashTableBucketPage<K, V> = cast_ref(&**bucket_data);
-        result = bucket.get_value(key);
-        self.bpm.unpin_page(self.dir_page_id, false);
-        self.bpm.unpin_page(context.bucket_pid, false);
-        result
+    // Doesn't hold the directory lock while reading the bucket. Splits bump the directory's seq
+    // around their changes, so if it moved in the meantime the bucket may have been split under
+    // us and the lookup is retried.
+    pub fn get_value(&self, key: &K) -> Result<Vec<V>, HashTableError> {
+        Ok(self
+            .get_value_located(key)?
+            .into_iter()
+            .map(|(_, _, value)| value)
+            .collect())
     }
 
-    pub fn insert(&mut self, key: &K, value: &V) -> bool {
-        let context = self.get_context(key);
-        let result = {
-            let mut bucket_data = context.bucket_data.write().unwrap();
-            let bucket: &mut HashTableBucketPage<K, V> = cast_mut(&mut **bucket_data);
-            bucket.insert(key, value)
+    // Like get_value, but also says where each value lives as (bucket page id, slot). The
+    // location is only good until the next write to the table.
+    pub fn get_value_located(&self, key: &K) -> Result<Vec<(PageId, usize, V)>, HashTableError> {
+        let dir_data = self.get_dir_data()?;
+        let read_seq = || {
+            let dir_data = dir_data.read().unwrap();
+            let dir: &HashTableDirectoryPage = cast_ref(&**dir_data);
+            let bucket_index = self.hash_key(key) & ((1 << dir.get_global_depth()) - 1);
+            (dir.get_seq(), dir.get_bucket_page_id(bucket_index as usize))
+        };
+        let result = loop {
+            let (seq, bucket_pid) = read_seq();
+            let bucket_data = match self.try_pid_to_page_data(bucket_pid) {
+                Ok(bucket_data) => bucket_data,
+                Err(err) => break Err(err),
+            };
+            let result = {
+                let bucket_data = bucket_data.read().unwrap();
+                let bucket: &HashTableBucketPage<K, V> = cast_ref(&**bucket_data);
+                // the directory may point at a bogus page id
+                debug_assert!(
+                    bucket.is_initialized(),
+                    "bucket {:?} was never initialized",
+                    bucket_pid
+                );
+                debug_assert!(
+                    bucket.checksum_ok(),
+                    "checksum mismatch in bucket {:?}",
+                    bucket_pid
+                );
+                bucket
+                    .get_value_with_slots(key)
+                    .into_iter()
+                    .map(|(slot, value)| (bucket_pid, slot, value))
+                    .collect::<Vec<_>>()
+            };
+            self.unpin_page(bucket_pid, false);
+            if read_seq().0 == seq {
+                break Ok(result);
+            }
         };
+        self.unpin_page(self.dir_page_id, false);
+        Ok(result?)
+    }
+
+    // Ok(false) if the (key, value) pair was already there.
+    pub fn insert(&mut self, key: &K, value: &V) -> Result<bool, HashTableError> {
+        self.insert_status(key, value)
+            .map(|outcome| outcome != InsertOutcome::AlreadyPresent)
+    }
+
+    // Like insert, but also tells whether a bucket had to split.
+    pub fn insert_status(&mut self, key: &K, value: &V) -> Result<InsertOutcome, HashTableError> {
+        self.insert_inner(key, value, self.hash_key(key), None)
+    }
+
+    // Like insert, with hash from hash_key computed once by the caller, e.g. for many values
+    // of one key. The hash alone picks the bucket, so a wrong one puts the pair where lookups
+    // won't find it and verify fails.
+    pub fn insert_prehashed(
+        &mut self,
+        key: &K,
+        value: &V,
+        hash: u64,
+    ) -> Result<bool, HashTableError> {
+        self.insert_inner(key, value, hash, None)
+            .map(|outcome| outcome != InsertOutcome::AlreadyPresent)
+    }
+
+    // Like insert, but the change is tagged with the transaction and undone if it aborts.
+    pub fn insert_with_txn(
+        &mut self,
+        key: &K,
+        value: &V,
+        txn: &Transaction,
+    ) -> Result<bool, HashTableError> {
+        self.insert_inner(key, value, self.hash_key(key), Some(txn))
+            .map(|outcome| outcome != InsertOutcome::AlreadyPresent)
+    }
+
+    fn insert_inner(
+        &mut self,
+        key: &K,
+        value: &V,
+        hash: u64,
+        txn: Option<&Transaction>,
+    ) -> Result<InsertOutcome, HashTableError> {
+        let (context, result) = self.update_bucket(hash, |bucket_pid, bucket| {
+            let result = bucket.insert(key, value);
+            if let InertResult::Success = result {
+                self.append_log_record(LogRecordType::Insert, bucket_pid, bucket, key, value, txn);
+                self.add_to_count(1);
+            }
+            result
+        })?;
         match result {
             InertResult::Success => {
-                self.bpm.unpin_page(self.dir_page_id, false);
-                self.bpm.unpin_page(context.bucket_pid, true);
-                true
+                self.unpin_page(self.dir_page_id, false);
+                self.unpin_page(context.bucket_pid, true);
+                Ok(InsertOutcome::Inserted)
             }
             InertResult::Duplicate => {
-                self.bpm.unpin_page(self.dir_page_id, false);
-                self.bpm.unpin_page(context.bucket_pid, false);
-                false
+                self.unpin_page(self.dir_page_id, false);
+                self.unpin_page(context.bucket_pid, false);
+                Ok(InsertOutcome::AlreadyPresent)
             }
             InertResult::Full => {
-                self.bucket_split(key, value, &context);
-                self.insert(key, value)
+                let split = self.bucket_split(&context)?;
+                self.insert_inner(key, value, hash, txn)
+                    .map(|outcome| match outcome {
+                        InsertOutcome::Inserted if split => InsertOutcome::Split,
+                        outcome => outcome,
+                    })
             }
         }
     }
 
-    pub fn remove(&mut self, key: &K, value: &V) -> bool {
-        let context = self.get_context(key);
-        let mut bucket_data = context.bucket_data.write().unwrap();
-        let bucket: &mut HashTableBucketPage<K, V> = cast_mut(&mut **bucket_data);
-        if bucket.remove(key, value) {
-            self.bpm.unpin_page(self.dir_page_id, false);
-            self.bpm.unpin_page(context.bucket_pid, true);
-            true
-        } else {
-            self.bpm.unpin_page(self.dir_page_id, false);
-            self.bpm.unpin_page(context.bucket_pid, false);
+    // Ok(false) if the (key, value) pair wasn't there.
+    pub fn remove(&mut self, key: &K, value: &V) -> Result<bool, HashTableError> {
+        self.remove_inner(key, value, None)
+    }
+
+    pub fn remove_with_txn(
+        &mut self,
+        key: &K,
+        value: &V,
+        txn: &Transaction,
+    ) -> Result<bool, HashTableError> {
+        self.remove_inner(key, value, Some(txn))
+    }
+
+    // Removes all values of key, returning how many there were.
+    pub fn remove_key(&mut self, key: &K) -> Result<usize, HashTableError> {
+        let (context, removed) = self.update_bucket(self.hash_key(key), |bucket_pid, bucket| {
+            let mut removed = 0;
+            for i in 0..Tool::<K, V>::KV_NUM {
+                if bucket.is_readable(i) && bucket.key_at(i) == *key {
+                    let value = bucket.value_at(i);
+                    bucket.remove_at(i);
+                    self.append_log_record(
+                        LogRecordType::Delete,
+                        bucket_pid,
+                        bucket,
+                        key,
+                        &value,
+                        None,
+                    );
+                    removed += 1;
+                }
+            }
+            self.add_to_count(-(removed as i64));
+            removed
+        })?;
+        self.unpin_page(self.dir_page_id, false);
+        self.unpin_page(context.bucket_pid, removed > 0);
+        Ok(removed)
+    }
+
+    // Adds delta to the value of key in place, or inserts delta if the key is absent, and
+    // returns the new value. Meant for counting maps: it assumes a key has at most one value
+    // and only touches the first one it finds. Logged as a delete of the old value and an
+    // insert of the new one.
+    pub fn increment(&mut self, key: &K, delta: V) -> Result<V, HashTableError>
+    where
+        V: Add<Output = V>,
+    {
+        let (context, result) = self.update_bucket(self.hash_key(key), |bucket_pid, bucket| {
+            let slot = (0..Tool::<K, V>::KV_NUM)
+                .find(|i| bucket.is_readable(*i) && bucket.key_at(*i) == *key);
+            match slot {
+                Some(slot) => {
+                    let old = bucket.value_at(slot);
+                    let new = old + delta;
+                    bucket.set_value_at(slot, &new);
+                    self.append_log_record(
+                        LogRecordType::Delete,
+                        bucket_pid,
+                        bucket,
+                        key,
+                        &old,
+                        None,
+                    );
+                    self.append_log_record(
+                        LogRecordType::Insert,
+                        bucket_pid,
+                        bucket,
+                        key,
+                        &new,
+                        None,
+                    );
+                    Some(new)
+                }
+                None => match bucket.insert(key, &delta) {
+                    InertResult::Success => {
+                        self.append_log_record(
+                            LogRecordType::Insert,
+                            bucket_pid,
+                            bucket,
+                            key,
+                            &delta,
+                            None,
+                        );
+                        self.add_to_count(1);
+                        Some(delta)
+                    }
+                    _ => None,
+                },
+            }
+        })?;
+        match result {
+            Some(value) => {
+                self.unpin_page(self.dir_page_id, false);
+                self.unpin_page(context.bucket_pid, true);
+                Ok(value)
+            }
+            // the key is absent, so only a full bucket stops the insert
+            None => {
+                self.bucket_split(&context)?;
+                self.increment(key, delta)
+            }
+        }
+    }
+
+    // number of (key, value) pairs, as counted in the header
+    pub fn len(&self) -> usize {
+        let header_data = self.header_data.read().unwrap();
+        let header: &HashTableHeaderPage = cast_ref(&**header_data);
+        header.get_count() as usize
+    }
+
+    // Number of entries in each distinct bucket, in directory order. Many empty buckets next
+    // to full ones point at a poor hash function or skewed keys.
+    pub fn occupancy_histogram(&self) -> Result<Vec<usize>, HashTableError> {
+        let dir_data = self.get_dir_data()?;
+        let mut occupancy = Vec::new();
+        let result = self.visit_pages(Self::bucket_page_ids(&dir_data), |_, bucket_data| {
+            let bucket_data = bucket_data.read().unwrap();
+            let bucket: &HashTableBucketPage<K, V> = cast_ref(&**bucket_data);
+            occupancy.push(bucket.num_readable());
             false
+        });
+        self.unpin_page(self.dir_page_id, false);
+        result?;
+        Ok(occupancy)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    // every (key, value) pair, read one bucket at a time
+    pub fn iter(&self) -> Result<impl Iterator<Item = (K, V)>, HashTableError> {
+        self.shard_iter(0, 1)
+    }
+
+    // The pairs of one shard out of num_shards, so a scan can be split between workers. A
+    // bucket belongs to the shard of the first directory slot pointing at it, so every bucket
+    // lands in exactly one shard. Only the directory and the bucket being read are latched.
+    pub fn shard_iter(
+        &self,
+        shard: usize,
+        num_shards: usize,
+    ) -> Result<impl Iterator<Item = (K, V)>, HashTableError> {
+        assert!(shard < num_shards, "shard {} out of {}", shard, num_shards);
+        let dir_data = self.get_dir_data()?;
+        let bucket_pids: Vec<PageId> = {
+            let dir_data = dir_data.read().unwrap();
+            let dir: &HashTableDirectoryPage = cast_ref(&**dir_data);
+            let mut seen = HashSet::new();
+            (0..dir.size())
+                .filter(|i| seen.insert(dir.get_bucket_page_id(*i)) && i % num_shards == shard)
+                .map(|i| dir.get_bucket_page_id(i))
+                .collect()
+        };
+        let mut entries = Vec::new();
+        let result = self.visit_pages(bucket_pids, |_, bucket_data| {
+            let bucket_data = bucket_data.read().unwrap();
+            let bucket: &HashTableBucketPage<K, V> = cast_ref(&**bucket_data);
+            bucket.for_each(|key, value| entries.push((*key, *value)));
+            false
+        });
+        self.unpin_page(self.dir_page_id, false);
+        result?;
+        Ok(entries.into_iter())
+    }
+
+    // Copies every entry into a fresh table hashed with new_hasher, e.g. when the occupancy
+    // histogram shows the current hasher skews. This table is left as it is, so the caller
+    // decides when to switch over to the new directory page. If an insert fails, the pages of
+    // the half-built table are leaked.
+    pub fn rehash_into<H2: BuildHasher>(
+        &self,
+        new_hasher: H2,
+    ) -> Result<ExtendibleHashTable<'a, R, D, K, V, H2>, HashTableError> {
+        let mut table = ExtendibleHashTable::new(self.bpm, new_hasher);
+        for (key, value) in self.iter()? {
+            table.insert(&key, &value)?;
         }
+        Ok(table)
     }
 
-    fn get_global_depth(&self) -> u32 {
-        let dir_data = self.get_dir_data();
+    // distinct buckets in directory order
+    fn bucket_page_ids(dir_data: &Data) -> Vec<PageId> {
+        let dir_data = dir_data.read().unwrap();
+        Self::bucket_page_ids_of(cast_ref(&**dir_data))
+    }
+
+    fn bucket_page_ids_of(dir: &HashTableDirectoryPage) -> Vec<PageId> {
+        let mut seen = HashSet::new();
+        (0..dir.size())
+            .map(|i| dir.get_bucket_page_id(i))
+            .filter(|page_id| seen.insert(*page_id))
+            .collect()
+    }
+
+    fn remove_inner(
+        &mut self,
+        key: &K,
+        value: &V,
+        txn: Option<&Transaction>,
+    ) -> Result<bool, HashTableError> {
+        let (context, removed) = self.update_bucket(self.hash_key(key), |bucket_pid, bucket| {
+            let removed = bucket.remove(key, value);
+            if removed {
+                self.append_log_record(LogRecordType::Delete, bucket_pid, bucket, key, value, txn);
+                self.add_to_count(-1);
+            }
+            removed
+        })?;
+        self.unpin_page(self.dir_page_id, false);
+        self.unpin_page(context.bucket_pid, removed);
+        Ok(removed)
+    }
+
+    // Removes every entry f returns false for, visiting each bucket once. Emptied buckets are
+    // not merged.
+    // A bucket that can't be fetched stops the walk, the buckets before it stay filtered.
+    pub fn retain(&mut self, mut f: impl FnMut(&K, &V) -> bool) -> Result<(), HashTableError> {
+        let dir_data = self.get_dir_data()?;
+        let bucket_pids = Self::bucket_page_ids(&dir_data);
+        let result = self.visit_pages(bucket_pids, |bucket_pid, bucket_data| {
+            let mut bucket_data = bucket_data.write().unwrap();
+            let bucket: &mut HashTableBucketPage<K, V> = cast_mut(&mut **bucket_data);
+            let mut removed = 0;
+            for i in 0..Tool::<K, V>::KV_NUM {
+                if !bucket.is_readable(i) {
+                    continue;
+                }
+                let (key, value) = (bucket.key_at(i), bucket.value_at(i));
+                if !f(&key, &value) {
+                    bucket.remove_at(i);
+                    self.append_log_record(
+                        LogRecordType::Delete,
+                        bucket_pid,
+                        bucket,
+                        &key,
+                        &value,
+                        None,
+                    );
+                    removed += 1;
+                }
+            }
+            self.add_to_count(-removed);
+            removed > 0
+        });
+        self.unpin_page(self.dir_page_id, false);
+        Ok(result?)
+    }
+
+    fn get_global_depth(&self) -> Result<u32, BufferError> {
+        let dir_data = self.get_dir_data()?;
         let dir_data = dir_data.read().unwrap();
         let dir: &HashTableDirectoryPage = cast_ref(&**dir_data);
         let global_depth = dir.get_global_depth();
-        self.bpm.unpin_page(self.dir_page_id, false);
-        global_depth
+        self.unpin_page(self.dir_page_id, false);
+        Ok(global_depth)
     }
 
-    fn get_local_depth(&self, bucket_index: u64) -> u8 {
-        let dir_data = self.get_dir_data();
+    fn get_local_depth(&self, bucket_index: u64) -> Result<u8, BufferError> {
+        let dir_data = self.get_dir_data()?;
         let dir_data = dir_data.read().unwrap();
         let dir: &HashTableDirectoryPage = cast_ref(&**dir_data);
         let local_depth = dir.get_local_depth(bucket_index as usize);
-        self.bpm.unpin_page(self.dir_page_id, false);
-        local_depth
+        self.unpin_page(self.dir_page_id, false);
+        Ok(local_depth)
     }
 
-    fn bucket_split(&mut self, key: &K, value: &V, context: &EHTContext) {
-        if context.local_depth == self.get_global_depth() as u8 {
-            self.bucket_split_dir_double(key, value, context);
-        } else {
-            self.bucket_split_dir_same(key, value, context);
-        }
+    // Splits the bucket at directory slot bucket_index whether it is full or not, doubling the
+    // directory if needed. For shaping the directory in tests, or splitting a hot bucket ahead
+    // of time.
+    pub fn force_split(&mut self, bucket_index: usize) -> Result<(), HashTableError> {
+        let context = self.get_context_at(|dir| {
+            assert!(
+                bucket_index < dir.size(),
+                "slot {} out of {}",
+                bucket_index,
+                dir.size()
+            );
+            bucket_index
+        })?;
+        self.bucket_split(&context).map(|_| ())
     }
 
-    fn bucket_split_dir_double(&mut self, key: &K, value: &V, context: &EHTContext) {
-        let mut dir_data = context.dir_data.write().unwrap();
-        let dir: &mut HashTableDirectoryPage = cast_mut(&mut **dir_data);
-        dir.increase_global_depth();
-        dir.increase_local_depth(context.bucket_index);
-        let num_buckets_before = (1 << dir.get_global_depth()) / 2;
-        for i in 0..num_buckets_before {
-            dir.set_bucket_page_id(num_buckets_before + i, dir.get_bucket_page_id(i));
-            dir.set_local_depth(num_buckets_before + i, dir.get_local_depth(i));
-        }
+    // Splits the bucket in context, doubling the directory first if the bucket is already at
+    // global depth. Another handle may have split the bucket since the context was read, so the
+    // directory is checked again under its write latch: Ok(false) if it changed and nothing was
+    // split, for the caller to start over. Unpins the directory and the bucket either way.
+    //
+    // The slots sharing the bucket are those agreeing with bucket_index on the low local_depth
+    // bits. Bit local_depth of the slot index (and of the entry hashes) picks the half that
+    // moves to the new bucket.
+    fn bucket_split(&mut self, context: &EHTContext) -> Result<bool, HashTableError> {
         let mut new_page_id = PageId(0);
-        let new_bucket_data = self.bpm.new_page_blocking(&mut new_page_id);
-        let mut new_bucket_data = new_bucket_data.write().unwrap();
-        let new_bucket: &mut HashTableBucketPage<K, V> = cast_mut(&mut **new_bucket_data);
-        dir.set_bucket_page_id(context.bucket_index + num_buckets_before, new_page_id);
-        dir.set_local_depth(
-            context.bucket_index + num_buckets_before,
-            context.local_depth,
-        );
-        for i in 0..Tool::<K, V>::KV_NUM {
-            if self.key_to_index(key, context.dir_data.clone()) == context.bucket_index as u64 {
-                continue;
+        let Ok(new_bucket_data) = self
+            .bpm
+            .new_page_blocking_timeout(&mut new_page_id, self.split_timeout)
+        else {
+            self.unpin_page(self.dir_page_id, false);
+            self.unpin_page(context.bucket_pid, false);
+            return Err(HashTableError::Buffer(BufferError::NoFreeFrame));
+        };
+        self.track_pin(new_page_id);
+        let result = {
+            let mut dir_data = context.dir_data.write().unwrap();
+            let dir: &mut HashTableDirectoryPage = cast_mut(&mut **dir_data);
+            let local_depth = context.local_depth as u32;
+            if !context.is_current(dir) {
+                Ok(false)
+            } else if local_depth == dir.get_global_depth() && !dir.can_grow() {
+                Err(HashTableError::DirectoryFull)
+            } else {
+                dir.increase_seq();
+                // split copies, so the table stays as it was if the split can't be written
+                let mut new_dir = *dir;
+                if local_depth == new_dir.get_global_depth() {
+                    new_dir.grow();
+                }
+                let low_mask = (1 << local_depth) - 1;
+                for i in 0..new_dir.size() {
+                    if i & low_mask == context.bucket_index & low_mask {
+                        let page_id = if (i >> local_depth) & 1 == 1 {
+                            new_page_id
+                        } else {
+                            new_dir.get_bucket_page_id(i)
+                        };
+                        new_dir.set_bucket(i, page_id, local_depth as u8 + 1);
+                    }
+                }
+
+                let mut bucket_data = context.bucket_data.write().unwrap();
+                let bucket: &mut HashTableBucketPage<K, V> = cast_mut(&mut **bucket_data);
+                let mut old_bucket = *bucket;
+                let mut new_bucket_data = new_bucket_data.write().unwrap();
+                let new_bucket: &mut HashTableBucketPage<K, V> = cast_mut(&mut **new_bucket_data);
+                new_bucket.init(new_page_id);
+                // both halves hold every change the bucket held
+                if let Some(lsn) = old_bucket.get_lsn() {
+                    new_bucket.set_lsn(lsn);
+                }
+                for i in 0..Tool::<K, V>::KV_NUM {
+                    if !old_bucket.is_readable(i) {
+                        continue;
+                    }
+                    let (key, value) = (old_bucket.key_at(i), old_bucket.value_at(i));
+                    if (self.hash_key(&key) >> local_depth) & 1 == 1 {
+                        new_bucket.insert(&key, &value);
+                        old_bucket.remove(&key, &value);
+                    }
+                }
+                let written = self.write_split([
+                    (new_page_id, cast_ref(new_bucket)),
+                    (self.dir_page_id, cast_ref(&new_dir)),
+                    (context.bucket_pid, cast_ref(&old_bucket)),
+                ]);
+                if written.is_ok() {
+                    *dir = new_dir;
+                    *bucket = old_bucket;
+                }
+                dir.increase_seq();
+                written.map(|()| true)
             }
-            new_bucket.insert(key, value);
-            let mut bucket_data = context.bucket_data.write().unwrap();
-            let bucket: &mut HashTableBucketPage<K, V> = cast_mut(&mut **bucket_data);
-            bucket.remove(key, value);
+        };
+        let split = matches!(result, Ok(true));
+        // After a failed write the disk may hold part of the split: the unsplit directory and
+        // bucket are unpinned dirty to replace it, and the new bucket is kept, as the directory
+        // on disk may point at it until then.
+        let write_failed = matches!(result, Err(HashTableError::Buffer(_)));
+        self.unpin_page(self.dir_page_id, split || write_failed);
+        self.unpin_page(context.bucket_pid, split || write_failed);
+        self.unpin_page(new_page_id, split);
+        if !split && !write_failed {
+            self.bpm.delete_page(new_page_id)?;
         }
-        self.bpm.unpin_page(self.dir_page_id, true);
-        self.bpm.unpin_page(context.bucket_pid, true);
-        self.bpm.unpin_page(new_page_id, true);
+        result
+    }
+
+    // Redo routes records through the directory on disk, so a logging table writes a split
+    // before anyone can see it: the new bucket first, so that the directory on disk never
+    // points at a bucket missing from it, then the directory and then the old bucket. A crash
+    // before the last write leaves the moved entries in the old bucket as well, redo drops
+    // them (see drop_misrouted).
+    fn write_split(&self, pages: [(PageId, &[u8; PAGE_SIZE]); 3]) -> Result<(), HashTableError> {
+        if self.log_manager.is_some() {
+            for (page_id, data) in pages {
+                self.bpm
+                    .write_through(page_id, data)
+                    .map_err(BufferError::Io)?;
+            }
+        }
+        Ok(())
     }
 
-    fn bucket_split_dir_same(&mut self, key: &K, value: &V, context: &EHTContext) {
-        let cycle = 1 << context.local_depth;
-        let index_in_place = if context.bucket_index < cycle {
-            context.bucket_index
-        } else {
-            context.bucket_index - cycle
+    // Removes the entries sitting in a bucket their hash doesn't route to, which a crash in
+    // the middle of writing a split leaves behind (see write_split). Their copies are in the
+    // bucket the directory routes to. Returns how many were removed.
+    pub fn drop_misrouted(&mut self) -> Result<usize, HashTableError> {
+        let dir_data = self.get_dir_data()?;
+        let mut removed = 0;
+        let result = {
+            let dir_data = dir_data.read().unwrap();
+            let dir: &HashTableDirectoryPage = cast_ref(&**dir_data);
+            let mask = (1 << dir.get_global_depth()) - 1;
+            self.visit_pages(Self::bucket_page_ids_of(dir), |bucket_pid, bucket_data| {
+                let mut bucket_data = bucket_data.write().unwrap();
+                let bucket: &mut HashTableBucketPage<K, V> = cast_mut(&mut **bucket_data);
+                let mut dirty = false;
+                for i in 0..Tool::<K, V>::KV_NUM {
+                    if !bucket.is_readable(i) {
+                        continue;
+                    }
+                    let (key, value) = (bucket.key_at(i), bucket.value_at(i));
+                    let index = (self.hash_key(&key) & mask) as usize;
+                    if dir.get_bucket_page_id(index) != bucket_pid {
+                        bucket.remove(&key, &value);
+                        removed += 1;
+                        dirty = true;
+                    }
+                }
+                dirty
+            })
         };
-        let mut dir_data = context.dir_data.write().unwrap();
-        let dir: &mut HashTableDirectoryPage = cast_mut(&mut **dir_data);
-        dir.increase_local_depth(context.bucket_index);
-        let num_buckets = (1 << dir.get_global_depth()) / 2;
-        let start = num_buckets / 2 + context.bucket_index % cycle;
-        let mut new_page_id = PageId(0);
-        let new_bucket_data = self.bpm.new_page_blocking(&mut new_page_id);
-        let mut new_bucket_data = new_bucket_data.write().unwrap();
-        let new_bucket: &mut HashTableBucketPage<K, V> = cast_mut(&mut **new_bucket_data);
-        for i in (start..num_buckets).step_by(cycle) {
-            dir.set_bucket_page_id(i, new_page_id);
-            dir.set_local_depth(i, context.local_depth + 1);
-        }
-        for i in 0..Tool::<K, V>::KV_NUM {
-            if self.key_to_index(key, context.dir_data.clone()) < (num_buckets / 2) as u64 {
-                continue;
+        self.unpin_page(self.dir_page_id, false);
+        result?;
+        Ok(removed)
+    }
+
+    // Panics if the directory is inconsistent or an entry lives in a bucket its hash does not
+    // route to.
+    // Fails only if a page can't be fetched.
+    pub fn verify(&self) -> Result<(), HashTableError> {
+        let dir_data = self.get_dir_data()?;
+        let result = {
+            let dir_data = dir_data.read().unwrap();
+            let dir: &HashTableDirectoryPage = cast_ref(&**dir_data);
+            dir.verify_integrity();
+            let mask = (1 << dir.get_global_depth()) - 1;
+            let mut checked = HashSet::new();
+            let bucket_pids = (0..dir.size())
+                .map(|i| dir.get_bucket_page_id(i))
+                .filter(|bucket_pid| checked.insert(*bucket_pid));
+            self.visit_pages(bucket_pids, |bucket_pid, bucket_data| {
+                let bucket_data = bucket_data.read().unwrap();
+                let bucket: &HashTableBucketPage<K, V> = cast_ref(&**bucket_data);
+                for j in 0..Tool::<K, V>::KV_NUM {
+                    if bucket.is_readable(j) {
+                        let index = (self.hash_key(&bucket.key_at(j)) & mask) as usize;
+                        assert_eq!(
+                            dir.get_bucket_page_id(index),
+                            bucket_pid,
+                            "entry in slot {} of bucket {:?} belongs to directory index {}",
+                            j,
+                            bucket_pid,
+                            index
+                        );
+                    }
+                }
+                false
+            })
+        };
+        self.unpin_page(self.dir_page_id, false);
+        Ok(result?)
+    }
+
+    // Pages below max_page_id that were initialized as bucket pages and still look like one (see
+    // HashTableBucketPage::looks_valid) but that the directory doesn't reach, e.g. buckets leaked
+    // by a crash halfway through a split. Free page ids, holes and pages past the end of the file
+    // are skipped, they read as zeros. Pages of anything else in the file are unreachable too,
+    // so this only makes sense for a file holding just this table, with no split running.
+    pub fn find_orphans(&self, max_page_id: PageId) -> Result<Vec<PageId>, HashTableError> {
+        let dir_data = self.get_dir_data()?;
+        let mut known: HashSet<PageId> = Self::bucket_page_ids(&dir_data).into_iter().collect();
+        self.unpin_page(self.dir_page_id, false);
+        known.insert(self.header_page_id);
+        known.insert(self.dir_page_id);
+        known.extend(self.bpm.free_page_ids().into_iter().flatten());
+        let mut orphans = Vec::new();
+        let unknown = (0..max_page_id.0)
+            .map(PageId)
+            .filter(|page_id| !known.contains(page_id));
+        self.visit_pages(unknown, |page_id, data| {
+            let data = data.read().unwrap();
+            let bucket: &HashTableBucketPage<K, V> = cast_ref(&**data);
+            if bucket.is_initialized() && bucket.looks_valid() {
+                orphans.push(page_id);
+            }
+            false
+        })?;
+        Ok(orphans)
+    }
+
+    // Deletes the pages find_orphans reports. Returns how many were freed.
+    pub fn reclaim_orphans(&mut self, max_page_id: PageId) -> Result<usize, HashTableError> {
+        let orphans = self.find_orphans(max_page_id)?;
+        for page_id in &orphans {
+            self.bpm.delete_page(*page_id)?;
+        }
+        Ok(orphans.len())
+    }
+}
+
+// Releases the header. Operations unpin everything else they fetch, so the rest only has work
+// to do if one of them panicked halfway. Such pages may have been modified, so they are
+// unpinned dirty.
+impl<'a, R, D, K, V, H> Drop for ExtendibleHashTable<'a, R, D, K, V, H>
+where
+    R: Replacer,
+    D: DiskManager,
+    K: Hash,
+    H: BuildHasher,
+{
+    fn drop(&mut self) {
+        let pins = std::mem::take(&mut *self.pins.lock().unwrap_or_else(|err| err.into_inner()));
+        for (page_id, count) in pins {
+            for _ in 0..count {
+                self.bpm.unpin_page(page_id, true);
             }
-            new_bucket.insert(key, value);
-            let mut bucket_data = context.bucket_data.write().unwrap();
-            let bucket: &mut HashTableBucketPage<K, V> = cast_mut(&mut **bucket_data);
-            bucket.remove(key, value);
         }
-        self.bpm.unpin_page(self.dir_page_id, true);
-        self.bpm.unpin_page(context.bucket_pid, true);
-        self.bpm.unpin_page(new_page_id, true);
+        self.bpm.unpin_page(self.header_page_id, false);
     }
 }
 
@@ -254,8 +1102,13 @@ where
 mod test {
     use super::*;
     use crate::buffer::replacer::LRUReplacer;
+    use crate::container::mixing_hasher::MixingBuildHasher;
+    use crate::recovery::log_record::Lsn;
     use crate::storage::disk::disk_manager::DiskManagerInstance;
+    use crate::storage::disk::faulty_disk_manager::FaultyDiskManager;
+    use proptest::prelude::*;
     use std::collections::hash_map::RandomState;
+    use std::collections::HashMap;
     use std::hash::{BuildHasher, BuildHasherDefault};
 
     #[test]
@@ -268,18 +1121,18 @@ mod test {
                 &bpm, hasher,
             );
         for i in 0..100 {
-            eht.insert(&i, &(i + 1));
+            eht.insert(&i, &(i + 1)).unwrap();
         }
         for i in 0..100 {
-            assert_eq!(eht.get_value(&i), vec![i + 1]);
+            assert_eq!(eht.get_value(&i).unwrap(), vec![i + 1]);
         }
 
         for i in 0..100 {
-            eht.remove(&i, &(i + 1));
+            eht.remove(&i, &(i + 1)).unwrap();
         }
 
         for i in 0..100 {
-            assert_eq!(eht.get_value(&i), vec![]);
+            assert_eq!(eht.get_value(&i).unwrap(), vec![]);
         }
     }
 
@@ -293,23 +1146,845 @@ mod test {
                 &bpm, hasher,
             );
         for i in 0..100 {
-            eht.insert(&i, &(i + 1));
+            eht.insert(&i, &(i + 1)).unwrap();
+        }
+
+        for i in 0..100 {
+            eht.insert(&i, &(i)).unwrap();
         }
 
         for i in 0..100 {
-            eht.insert(&i, &(i));
+            assert_eq!(eht.get_value(&i).unwrap().len(), 2);
         }
 
         for i in 0..100 {
-            assert_eq!(eht.get_value(&i).len(), 2);
+            eht.remove(&i, &(i)).unwrap();
         }
 
         for i in 0..100 {
-            eht.remove(&i, &(i));
+            assert_eq!(eht.get_value(&i).unwrap(), vec![i + 1]);
+        }
+    }
+
+    #[test]
+    fn test_log_records() {
+        let _ = std::fs::remove_file("test_wal.log");
+        let disk_manager = Arc::new(DiskManagerInstance::new("test_wal"));
+        let bpm = ParallelBufferPoolManager::new(5, 10, disk_manager);
+        let log_manager = LogManager::new("test_wal");
+        let mut eht = ExtendibleHashTable::<LRUReplacer, DiskManagerInstance, i32, i32, RandomState>::new_with_log_manager(
+            &bpm,
+            RandomState::new(),
+            &log_manager,
+        );
+        assert!(eht.insert(&1, &10).unwrap());
+        assert!(eht.insert(&2, &20).unwrap());
+        assert!(!eht.insert(&1, &10).unwrap());
+        assert!(eht.remove(&1, &10).unwrap());
+        assert!(!eht.remove(&3, &30).unwrap());
+        log_manager.flush();
+
+        let records = log_manager.read_log_records();
+        let expected = [
+            (LogRecordType::Insert, 1, 10),
+            (LogRecordType::Insert, 2, 20),
+            (LogRecordType::Delete, 1, 10),
+        ];
+        assert_eq!(records.len(), expected.len());
+        for (i, (record, (record_type, key, value))) in records.iter().zip(expected).enumerate() {
+            assert_eq!(record.get_lsn(), Lsn(i as u32));
+            assert_eq!(record.get_type(), record_type);
+            assert_eq!(record.get_dir_page_id(), eht.dir_page_id);
+            assert_eq!(record.get_key::<i32>(), key);
+            assert_eq!(record.get_value::<i32>(), value);
+        }
+        std::fs::remove_file("test_wal.log").unwrap();
+        std::fs::remove_file("test_wal.db").unwrap();
+    }
+
+    // Hashes an integer key to itself, so the low bits of the key pick the directory slot.
+    #[derive(Default)]
+    struct IdentityHasher(u64);
+
+    impl Hasher for IdentityHasher {
+        fn finish(&self) -> u64 {
+            self.0
+        }
+
+        fn write(&mut self, bytes: &[u8]) {
+            for byte in bytes {
+                self.0 = (self.0 << 8) | *byte as u64;
+            }
+        }
+
+        fn write_u64(&mut self, i: u64) {
+            self.0 = i;
+        }
+    }
+
+    #[derive(Debug, Clone)]
+    enum Op {
+        Insert(i64, i64),
+        Remove(i64, i64),
+        Get(i64),
+    }
+
+    // Dense keys spread over the directory; shifted keys share their low bits and force
+    // repeated splits of the same bucket, including directory doubling.
+    fn op_strategy(shift: u32) -> impl Strategy<Value = Op> {
+        prop_oneof![
+            4 => (0..1024i64, 0..4i64).prop_map(move |(k, v)| Op::Insert(k << shift, v)),
+            2 => (0..1024i64, 0..4i64).prop_map(move |(k, v)| Op::Remove(k << shift, v)),
+            1 => (0..1024i64).prop_map(move |k| Op::Get(k << shift)),
+        ]
+    }
+
+    fn check_against_model(ops: &[Op], db_name: &str) {
+        let disk_manager = Arc::new(DiskManagerInstance::new(db_name));
+        let bpm = ParallelBufferPoolManager::<LRUReplacer, _>::new(5, 10, disk_manager);
+        let mut eht = ExtendibleHashTable::<_, _, i64, i64, _>::new(
+            &bpm,
+            BuildHasherDefault::<IdentityHasher>::default(),
+        );
+        let mut model: HashMap<i64, Vec<i64>> = HashMap::new();
+        for op in ops {
+            match *op {
+                Op::Insert(k, v) => {
+                    let values = model.entry(k).or_default();
+                    let expected = !values.contains(&v);
+                    if expected {
+                        values.push(v);
+                    }
+                    assert_eq!(eht.insert(&k, &v).unwrap(), expected, "{:?}", op);
+                }
+                Op::Remove(k, v) => {
+                    let values = model.entry(k).or_default();
+                    let position = values.iter().position(|x| *x == v);
+                    if let Some(position) = position {
+                        values.remove(position);
+                    }
+                    assert_eq!(eht.remove(&k, &v).unwrap(), position.is_some(), "{:?}", op);
+                }
+                Op::Get(k) => {
+                    let mut values = eht.get_value(&k).unwrap();
+                    values.sort();
+                    let mut expected = model.get(&k).cloned().unwrap_or_default();
+                    expected.sort();
+                    assert_eq!(values, expected, "{:?}", op);
+                }
+            }
+            eht.verify().unwrap();
+        }
+        for (k, expected) in model.iter_mut() {
+            let mut values = eht.get_value(k).unwrap();
+            values.sort();
+            expected.sort();
+            assert_eq!(&values, expected);
+        }
+    }
+
+    fn proptest_config() -> ProptestConfig {
+        let cases = std::env::var("PROPTEST_CASES")
+            .ok()
+            .and_then(|cases| cases.parse().ok())
+            .unwrap_or(32);
+        ProptestConfig::with_cases(cases)
+    }
+
+    proptest! {
+        #![proptest_config(proptest_config())]
+
+        #[test]
+        fn table_matches_model(
+            ops in (0..=4u32).prop_flat_map(|shift| prop::collection::vec(op_strategy(shift), 0..1500))
+        ) {
+            check_against_model(&ops, "test_eht_model");
+            std::fs::remove_file("test_eht_model.db").unwrap();
+        }
+    }
+
+    #[test]
+    fn split_to_max_local_depth() {
+        // all keys agree on their low 6 bits, so nothing moves until the bucket splits on bit 6
+        let ops: Vec<_> = (0..600i64).map(|k| Op::Insert(k << 6, k)).collect();
+        check_against_model(&ops, "test_eht_split");
+        std::fs::remove_file("test_eht_split.db").unwrap();
+    }
+
+    #[test]
+    fn directory_full_test() {
+        let disk_manager = Arc::new(DiskManagerInstance::new("test_directory_full"));
+        let bpm = ParallelBufferPoolManager::<LRUReplacer, _>::new(5, 10, disk_manager);
+        let mut eht = ExtendibleHashTable::<_, _, i64, i64, _>::new(
+            &bpm,
+            BuildHasherDefault::<IdentityHasher>::default(),
+        );
+        // the low 9 bits of every key are zero, so no split up to the largest directory
+        // separates them
+        let capacity = Tool::<i64, i64>::KV_NUM as i64;
+        for k in 0..capacity {
+            assert!(eht.insert(&(k << 9), &k).unwrap());
         }
+        assert!(matches!(
+            eht.insert(&(capacity << 9), &capacity),
+            Err(HashTableError::DirectoryFull)
+        ));
+        assert_eq!(eht.get_global_depth().unwrap(), 9);
+        assert!(!eht.insert(&0, &0).unwrap());
+        eht.verify().unwrap();
+        // keys in the other buckets still go in
+        assert!(eht.insert(&1, &1).unwrap());
+        for k in 0..capacity {
+            assert_eq!(eht.get_value(&(k << 9)).unwrap(), vec![k]);
+        }
+        std::fs::remove_file("test_directory_full.db").unwrap();
+    }
+
+    #[test]
+    fn drop_releases_pins_test() {
+        let disk_manager = Arc::new(DiskManagerInstance::new("test_eht_drop"));
+        let bpm = ParallelBufferPoolManager::<LRUReplacer, _>::new(5, 10, disk_manager);
+        let pinned = || -> usize {
+            bpm.instance_load()
+                .iter()
+                .map(|load| load.pinned_frames)
+                .sum()
+        };
+        {
+            let mut eht = ExtendibleHashTable::<_, _, i32, i32, _>::new(&bpm, RandomState::new());
+            for i in 0..2000 {
+                eht.insert(&i, &i).unwrap();
+            }
+            for i in (0..2000).step_by(3) {
+                eht.remove(&i, &i).unwrap();
+                eht.get_value(&(i + 1)).unwrap();
+            }
+            eht.verify().unwrap();
+            assert!(eht.pins.lock().unwrap().is_empty());
+            // an operation that died halfway would leave pins like this behind
+            eht.get_context_hashed(eht.hash_key(&7)).unwrap();
+            // the directory and the bucket, next to the header
+            assert_eq!(pinned(), 3);
+        }
+        assert_eq!(pinned(), 0);
+        std::fs::remove_file("test_eht_drop.db").unwrap();
+    }
+
+    #[test]
+    fn retain_test() {
+        let disk_manager = Arc::new(DiskManagerInstance::new("test_eht_retain"));
+        let bpm = ParallelBufferPoolManager::<LRUReplacer, _>::new(5, 10, disk_manager);
+        let mut eht = ExtendibleHashTable::<_, _, i32, i32, _>::new(&bpm, RandomState::new());
+        for i in 0..1000 {
+            eht.insert(&i, &(i * 10)).unwrap();
+        }
+        eht.retain(|key, value| {
+            assert_eq!(*value, key * 10);
+            key % 2 == 0
+        })
+        .unwrap();
+        for i in 0..1000 {
+            let expected = if i % 2 == 0 { vec![i * 10] } else { vec![] };
+            assert_eq!(eht.get_value(&i).unwrap(), expected);
+        }
+        eht.verify().unwrap();
+        std::fs::remove_file("test_eht_retain.db").unwrap();
+    }
+
+    #[test]
+    fn open_bad_header_test() {
+        let disk_manager = Arc::new(DiskManagerInstance::new("test_eht_bad_header"));
+        let bpm = ParallelBufferPoolManager::<LRUReplacer, _>::new(1, 4, disk_manager);
+        let hasher = RandomState::new();
+        let eht = ExtendibleHashTable::<_, _, i32, i32, _>::new(&bpm, hasher.clone());
+        let dir_page_id = eht.get_dir_page_id();
+        let result =
+            ExtendibleHashTable::<_, _, i32, i32, _>::open(&bpm, hasher.clone(), dir_page_id);
+        assert!(matches!(
+            result,
+            Err(HashTableError::BadHeader { page_id, .. }) if page_id == dir_page_id
+        ));
+        // a page never written reads as zeros
+        let result = ExtendibleHashTable::<_, _, i32, i32, _>::open(&bpm, hasher, PageId(100));
+        assert!(matches!(
+            result,
+            Err(HashTableError::BadHeader {
+                page_id: PageId(100),
+                version: 0
+            })
+        ));
+        // the failed opens left nothing pinned
+        for _ in 0..3 {
+            let mut page_id = PageId(0);
+            bpm.new_page(&mut page_id).unwrap();
+        }
+        std::fs::remove_file("test_eht_bad_header.db").unwrap();
+    }
+
+    #[test]
+    fn concurrent_get_value_test() {
+        use std::sync::atomic::{AtomicI32, Ordering};
+        const NUM_KEYS: i32 = 5000;
+        let disk_manager = Arc::new(DiskManagerInstance::new("test_eht_seqlock"));
+        let bpm = ParallelBufferPoolManager::<LRUReplacer, _>::new(5, 10, disk_manager);
+        let hasher = RandomState::new();
+        let mut writer = ExtendibleHashTable::<_, _, i32, i32, _>::new(&bpm, hasher.clone());
+        let reader = ExtendibleHashTable::<_, _, i32, i32, _>::open(
+            &bpm,
+            hasher,
+            writer.get_header_page_id(),
+        )
+        .unwrap();
+        // keys below this are in the table
+        let inserted = AtomicI32::new(0);
+        std::thread::scope(|s| {
+            for t in 0..4 {
+                let (reader, inserted) = (&reader, &inserted);
+                s.spawn(move || {
+                    let mut k = t;
+                    loop {
+                        let upto = inserted.load(Ordering::Acquire);
+                        if upto == NUM_KEYS {
+                            break;
+                        }
+                        if upto > 0 {
+                            k = (k * 31 + 7) % upto;
+                            assert_eq!(reader.get_value(&k).unwrap(), vec![k]);
+                        }
+                    }
+                });
+            }
+            for k in 0..NUM_KEYS {
+                writer.insert(&k, &k).unwrap();
+                inserted.store(k + 1, Ordering::Release);
+            }
+        });
+        writer.verify().unwrap();
+        std::fs::remove_file("test_eht_seqlock.db").unwrap();
+    }
+
+    #[test]
+    fn remove_key_len_test() {
+        let disk_manager = Arc::new(DiskManagerInstance::new("test_eht_remove_key"));
+        let bpm = ParallelBufferPoolManager::<LRUReplacer, _>::new(5, 10, disk_manager);
+        let mut eht = ExtendibleHashTable::<_, _, i32, i32, _>::new(&bpm, RandomState::new());
+        assert!(eht.is_empty());
+        for i in 0..600 {
+            eht.insert(&(i % 200), &i).unwrap();
+        }
+        assert_eq!(eht.len(), 600);
+        assert_eq!(eht.remove_key(&7).unwrap(), 3);
+        assert_eq!(eht.remove_key(&7).unwrap(), 0);
+        assert_eq!(eht.get_value(&7).unwrap(), vec![]);
+        assert_eq!(eht.len(), 597);
+        std::fs::remove_file("test_eht_remove_key.db").unwrap();
+    }
+
+    #[test]
+    fn occupancy_histogram_test() {
+        let disk_manager = Arc::new(DiskManagerInstance::new("test_eht_occupancy"));
+        let bpm = ParallelBufferPoolManager::<LRUReplacer, _>::new(5, 10, disk_manager);
+        let mut good = ExtendibleHashTable::<_, _, i64, i64, _>::new(&bpm, RandomState::new());
+        // the low 4 bits are always 0, so the first splits leave empty buckets behind
+        let mut bad = ExtendibleHashTable::<_, _, i64, i64, _>::new(
+            &bpm,
+            BuildHasherDefault::<IdentityHasher>::default(),
+        );
+        for i in 0..2000i64 {
+            good.insert(&i, &i).unwrap();
+            bad.insert(&(i << 4), &i).unwrap();
+        }
+        let good = good.occupancy_histogram().unwrap();
+        let bad = bad.occupancy_histogram().unwrap();
+        assert_eq!(good.iter().sum::<usize>(), 2000);
+        assert_eq!(bad.iter().sum::<usize>(), 2000);
+        assert!(good.iter().all(|n| *n > 0), "{:?}", good);
+        assert!(bad.iter().filter(|n| **n == 0).count() >= 4, "{:?}", bad);
+        std::fs::remove_file("test_eht_occupancy.db").unwrap();
+    }
+
+    #[test]
+    fn get_value_located_test() {
+        let disk_manager = Arc::new(DiskManagerInstance::new("test_eht_located"));
+        let bpm = ParallelBufferPoolManager::<LRUReplacer, _>::new(5, 10, disk_manager);
+        let mut eht = ExtendibleHashTable::<_, _, i32, i32, _>::new(&bpm, RandomState::new());
+        for i in 0..1000 {
+            eht.insert(&i, &i).unwrap();
+            eht.insert(&i, &(i + 1)).unwrap();
+        }
+        let located = eht.get_value_located(&500).unwrap();
+        assert_eq!(located.len(), 2);
+        let (bucket_pid, slot, value) = located[0];
+        {
+            let bucket_data = bpm.fetch_page(bucket_pid).unwrap();
+            let mut bucket_data = bucket_data.write().unwrap();
+            let bucket: &mut HashTableBucketPage<i32, i32> = cast_mut(&mut **bucket_data);
+            assert_eq!((bucket.key_at(slot), bucket.value_at(slot)), (500, value));
+            bucket.remove_at(slot);
+        }
+        bpm.unpin_page(bucket_pid, true);
+        assert_eq!(eht.get_value(&500).unwrap(), vec![located[1].2]);
+        assert_eq!(eht.iter().unwrap().count(), 1999);
+        std::fs::remove_file("test_eht_located.db").unwrap();
+    }
+
+    #[test]
+    fn insert_status_test() {
+        let disk_manager = Arc::new(DiskManagerInstance::new("test_eht_insert_status"));
+        let bpm = ParallelBufferPoolManager::<LRUReplacer, _>::new(5, 10, disk_manager);
+        let mut eht = ExtendibleHashTable::<_, _, i32, i32, _>::new(&bpm, RandomState::new());
+        assert_eq!(eht.insert_status(&1, &1).unwrap(), InsertOutcome::Inserted);
+        assert_eq!(
+            eht.insert_status(&1, &1).unwrap(),
+            InsertOutcome::AlreadyPresent
+        );
+        assert_eq!(eht.insert_status(&1, &2).unwrap(), InsertOutcome::Inserted);
+        // the first bucket fills up after KV_NUM pairs
+        let outcomes: Vec<_> = (2..Tool::<i32, i32>::KV_NUM as i32)
+            .map(|i| eht.insert_status(&i, &i).unwrap())
+            .collect();
+        assert!(outcomes.iter().all(|o| *o == InsertOutcome::Inserted));
+        assert_eq!(eht.insert_status(&-1, &-1).unwrap(), InsertOutcome::Split);
+        assert_eq!(
+            eht.insert_status(&-1, &-1).unwrap(),
+            InsertOutcome::AlreadyPresent
+        );
+        eht.verify().unwrap();
+        std::fs::remove_file("test_eht_insert_status.db").unwrap();
+    }
+
+    #[test]
+    fn split_pool_exhausted_test() {
+        let disk_manager = Arc::new(DiskManagerInstance::new("test_eht_pool_exhausted"));
+        let bpm = ParallelBufferPoolManager::<LRUReplacer, _>::new(1, 4, disk_manager);
+        let mut eht = ExtendibleHashTable::<_, _, i32, i32, _>::new(&bpm, RandomState::new());
+        eht.set_split_timeout(Duration::from_millis(20));
+        // a leaked pin takes the only frame the table doesn't use
+        let mut leaked = PageId(0);
+        bpm.new_page(&mut leaked).unwrap();
+        for i in 0..Tool::<i32, i32>::KV_NUM as i32 {
+            eht.insert(&i, &i).unwrap();
+        }
+        assert!(matches!(
+            eht.insert(&-1, &-1),
+            Err(HashTableError::Buffer(BufferError::NoFreeFrame))
+        ));
+        // nothing was left pinned by the failed split
+        bpm.unpin_page(leaked, false);
+        assert!(eht.insert(&-1, &-1).unwrap());
+        eht.verify().unwrap();
+        std::fs::remove_file("test_eht_pool_exhausted.db").unwrap();
+    }
+
+    #[test]
+    fn shard_iter_test() {
+        let disk_manager = Arc::new(DiskManagerInstance::new("test_eht_shard_iter"));
+        let bpm = ParallelBufferPoolManager::<LRUReplacer, _>::new(5, 10, disk_manager);
+        let mut eht = ExtendibleHashTable::<_, _, i32, i32, _>::new(&bpm, RandomState::new());
+        for i in 0..1000 {
+            eht.insert(&i, &(i * 2)).unwrap();
+        }
+        let mut all: Vec<_> = eht.iter().unwrap().collect();
+        all.sort();
+        assert_eq!(all, (0..1000).map(|i| (i, i * 2)).collect::<Vec<_>>());
+        let mut union = Vec::new();
+        for shard in 0..3 {
+            union.extend(eht.shard_iter(shard, 3).unwrap());
+        }
+        // no pair shows up in two shards
+        union.sort();
+        assert_eq!(union, all);
+        std::fs::remove_file("test_eht_shard_iter.db").unwrap();
+    }
+
+    #[test]
+    fn disk_error_test() {
+        let disk_manager = Arc::new(FaultyDiskManager::new(DiskManagerInstance::new(
+            "test_eht_disk_error",
+        )));
+        let bpm = ParallelBufferPoolManager::<LRUReplacer, _>::new(1, 4, disk_manager.clone());
+        let mut eht = ExtendibleHashTable::<_, _, i32, i32, _>::new(&bpm, RandomState::new());
+        assert!(eht.insert(&1, &1).unwrap());
+        // push the directory and the bucket out of the pool
+        let evict = || {
+            for _ in 0..3 {
+                let mut page_id = PageId(0);
+                bpm.new_page(&mut page_id).unwrap();
+                bpm.unpin_page(page_id, false);
+            }
+        };
+        evict();
+        disk_manager.fail_nth_read(1);
+        assert!(matches!(
+            eht.remove(&1, &1),
+            Err(HashTableError::Buffer(BufferError::Io(_)))
+        ));
+        assert!(eht.remove(&1, &1).unwrap());
+
+        // reads give up on the error too, rather than retrying it
+        assert!(eht.insert(&1, &1).unwrap());
+        evict();
+        disk_manager.fail_nth_read(2);
+        assert!(matches!(
+            eht.get_value(&1),
+            Err(HashTableError::Buffer(BufferError::Io(_)))
+        ));
+        evict();
+        disk_manager.fail_nth_read(1);
+        assert!(matches!(
+            eht.iter().map(|entries| entries.count()),
+            Err(HashTableError::Buffer(BufferError::Io(_)))
+        ));
+        evict();
+        disk_manager.fail_nth_read(1);
+        assert!(matches!(
+            eht.verify(),
+            Err(HashTableError::Buffer(BufferError::Io(_)))
+        ));
+        assert_eq!(eht.get_value(&1).unwrap(), vec![1]);
+        assert!(eht.pins.lock().unwrap().is_empty());
+        std::fs::remove_file("test_eht_disk_error.db").unwrap();
+    }
+
+    #[test]
+    fn new_with_depth_test() {
+        let disk_manager = Arc::new(DiskManagerInstance::new("test_eht_depth"));
+        let bpm = ParallelBufferPoolManager::<LRUReplacer, _>::new(5, 10, disk_manager);
+        let hasher = RandomState::new();
+        let mut splits = Vec::new();
+        for depth in [0, 4] {
+            let mut eht = ExtendibleHashTable::<_, _, i32, i32, _>::new_with_depth(
+                &bpm,
+                hasher.clone(),
+                depth,
+            );
+            assert_eq!(eht.get_global_depth().unwrap(), depth);
+            eht.verify().unwrap();
+            let outcomes: Vec<_> = (0..5000)
+                .map(|i| eht.insert_status(&i, &i).unwrap())
+                .collect();
+            splits.push(
+                outcomes
+                    .iter()
+                    .filter(|o| **o == InsertOutcome::Split)
+                    .count(),
+            );
+            eht.verify().unwrap();
+            assert_eq!(eht.len(), 5000);
+        }
+        assert!(splits[1] < splits[0], "{:?}", splits);
+        std::fs::remove_file("test_eht_depth.db").unwrap();
+    }
+
+    #[test]
+    #[should_panic(expected = "directory limit")]
+    fn new_with_depth_too_deep_test() {
+        let disk_manager = Arc::new(DiskManagerInstance::new("test_eht_too_deep"));
+        let bpm = ParallelBufferPoolManager::<LRUReplacer, _>::new(1, 4, disk_manager);
+        std::fs::remove_file("test_eht_too_deep.db").unwrap();
+        ExtendibleHashTable::<_, _, i32, i32, _>::new_with_depth(
+            &bpm,
+            RandomState::new(),
+            MAX_GLOBAL_DEPTH + 1,
+        );
+    }
+
+    #[test]
+    fn orphans_test() {
+        let disk_manager = Arc::new(DiskManagerInstance::new("test_orphans"));
+        let bpm = ParallelBufferPoolManager::<LRUReplacer, _>::new(1, 10, disk_manager);
+        let mut eht = ExtendibleHashTable::<_, _, i32, i32, _>::new(&bpm, RandomState::new());
+        for i in 0..10 {
+            eht.insert(&i, &i).unwrap();
+        }
+        // a bucket nothing points at, as a split would leave it before linking it in
+        let (stray_page_id, data) = bpm
+            .new_typed_page::<HashTableBucketPage<i32, i32>>()
+            .unwrap();
+        {
+            let mut data = data.write().unwrap();
+            let bucket: &mut HashTableBucketPage<i32, i32> = cast_mut(&mut **data);
+            bucket.insert(&1, &1);
+        }
+        bpm.unpin_page(stray_page_id, true);
+        let max_page_id = PageId(stray_page_id.0 + 1);
+        assert_eq!(eht.find_orphans(max_page_id).unwrap(), vec![stray_page_id]);
+
+        assert_eq!(eht.reclaim_orphans(max_page_id).unwrap(), 1);
+        assert!(eht.find_orphans(max_page_id).unwrap().is_empty());
+        for i in 0..10 {
+            assert_eq!(eht.get_value(&i).unwrap(), vec![i]);
+        }
+        eht.verify().unwrap();
+        std::fs::remove_file("test_orphans.db").unwrap();
+    }
+
+    #[test]
+    fn orphans_past_eof_test() {
+        let disk_manager = Arc::new(DiskManagerInstance::new("test_orphans_past_eof"));
+        // the header, the directory and one bucket fill the pool
+        let bpm = ParallelBufferPoolManager::<LRUReplacer, _>::new(1, 3, disk_manager);
+        let mut eht = ExtendibleHashTable::<_, _, i32, i32, _>::new(&bpm, RandomState::new());
+        for i in 0..10 {
+            eht.insert(&i, &i).unwrap();
+        }
+        bpm.flush_all_pages().unwrap();
+        // every candidate lies past the end of the file and lands in the frame a bucket or the
+        // directory was just evicted from
+        let max_page_id = PageId(20);
+        assert!(eht.find_orphans(max_page_id).unwrap().is_empty());
+        assert_eq!(eht.reclaim_orphans(max_page_id).unwrap(), 0);
+        for i in 0..10 {
+            assert_eq!(eht.get_value(&i).unwrap(), vec![i]);
+        }
+        eht.verify().unwrap();
+        std::fs::remove_file("test_orphans_past_eof.db").unwrap();
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "checksum mismatch in bucket")]
+    fn bucket_checksum_test() {
+        let disk_manager = Arc::new(DiskManagerInstance::new("test_bucket_checksum"));
+        let bpm = ParallelBufferPoolManager::<LRUReplacer, _>::new(1, 10, disk_manager);
+        let mut eht = ExtendibleHashTable::<_, _, u64, u64, _>::new(&bpm, RandomState::new());
+        let marker = 0x0123_4567_89ab_cdefu64;
+        eht.insert(&1, &marker).unwrap();
+        let (bucket_pid, _, _) = eht.get_value_located(&1).unwrap()[0];
+        // overwrite the value through the raw page, as a bad cast would
+        let data = bpm.fetch_page(bucket_pid).unwrap();
+        {
+            let mut data = data.write().unwrap();
+            let offset = data
+                .windows(8)
+                .position(|bytes| bytes == marker.to_ne_bytes())
+                .unwrap();
+            data[offset] ^= 1;
+        }
+        bpm.unpin_page(bucket_pid, true);
+        std::fs::remove_file("test_bucket_checksum.db").unwrap();
+        eht.get_value(&1).unwrap();
+    }
+
+    #[test]
+    fn force_split_test() {
+        let disk_manager = Arc::new(DiskManagerInstance::new("test_force_split"));
+        let bpm = ParallelBufferPoolManager::<LRUReplacer, _>::new(2, 10, disk_manager);
+        let mut eht = ExtendibleHashTable::<_, _, i32, i32, _>::new(&bpm, RandomState::new());
+        eht.force_split(0).unwrap();
+        assert_eq!(eht.get_global_depth().unwrap(), 1);
+        assert_eq!(
+            (
+                eht.get_local_depth(0).unwrap(),
+                eht.get_local_depth(1).unwrap()
+            ),
+            (1, 1)
+        );
+        assert!(eht.is_empty());
+        eht.verify().unwrap();
 
         for i in 0..100 {
-            assert_eq!(eht.get_value(&i), vec![i + 1]);
+            eht.insert(&i, &i).unwrap();
         }
+        // slot 1 is at global depth, so the directory doubles
+        eht.force_split(1).unwrap();
+        assert_eq!(eht.get_global_depth().unwrap(), 2);
+        assert_eq!(eht.get_local_depth(0).unwrap(), 1);
+        assert_eq!(
+            (
+                eht.get_local_depth(1).unwrap(),
+                eht.get_local_depth(3).unwrap()
+            ),
+            (2, 2)
+        );
+        // slot 2 still shares bucket 0, which splits without doubling
+        eht.force_split(2).unwrap();
+        assert_eq!(eht.get_global_depth().unwrap(), 2);
+        assert_eq!(
+            (
+                eht.get_local_depth(0).unwrap(),
+                eht.get_local_depth(2).unwrap()
+            ),
+            (2, 2)
+        );
+        eht.verify().unwrap();
+        assert_eq!(eht.len(), 100);
+        for i in 0..100 {
+            assert_eq!(eht.get_value(&i).unwrap(), vec![i]);
+        }
+        std::fs::remove_file("test_force_split.db").unwrap();
+    }
+
+    #[test]
+    fn increment_test() {
+        let disk_manager = Arc::new(DiskManagerInstance::new("test_increment"));
+        let bpm = ParallelBufferPoolManager::<LRUReplacer, _>::new(2, 10, disk_manager);
+        let mut eht = ExtendibleHashTable::<_, _, i32, i32, _>::new(&bpm, RandomState::new());
+        for i in 1..=1000 {
+            assert_eq!(eht.increment(&7, 1).unwrap(), i);
+        }
+        assert_eq!(eht.get_value(&7).unwrap(), vec![1000]);
+        // enough keys to split buckets on the way
+        for _ in 0..3 {
+            for i in 0..2000 {
+                eht.increment(&i, i).unwrap();
+            }
+        }
+        for i in 0..2000 {
+            let expected = if i == 7 { 1000 + 21 } else { 3 * i };
+            assert_eq!(eht.get_value(&i).unwrap(), vec![expected]);
+        }
+        assert_eq!(eht.len(), 2000);
+        eht.verify().unwrap();
+        std::fs::remove_file("test_increment.db").unwrap();
+    }
+
+    #[test]
+    fn insert_prehashed_test() {
+        let disk_manager = Arc::new(DiskManagerInstance::new("test_insert_prehashed"));
+        let bpm = ParallelBufferPoolManager::<LRUReplacer, _>::new(2, 10, disk_manager);
+        let hasher = RandomState::new();
+        let mut eht = ExtendibleHashTable::<_, _, i32, i32, _>::new(&bpm, hasher.clone());
+        let mut expected = ExtendibleHashTable::<_, _, i32, i32, _>::new(&bpm, hasher);
+        for key in 0..10 {
+            let hash = eht.hash_key(&key);
+            for value in 0..100 {
+                assert!(eht.insert_prehashed(&key, &value, hash).unwrap());
+                expected.insert(&key, &value).unwrap();
+            }
+            assert!(!eht.insert_prehashed(&key, &0, hash).unwrap());
+        }
+        for key in 0..10 {
+            let mut values = eht.get_value(&key).unwrap();
+            values.sort();
+            assert_eq!(values, (0..100).collect::<Vec<_>>());
+        }
+        assert_eq!(
+            eht.occupancy_histogram().unwrap(),
+            expected.occupancy_histogram().unwrap()
+        );
+        eht.verify().unwrap();
+        std::fs::remove_file("test_insert_prehashed.db").unwrap();
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "was never initialized")]
+    fn uninitialized_bucket_test() {
+        let disk_manager = Arc::new(DiskManagerInstance::new("test_uninitialized_bucket"));
+        let bpm = ParallelBufferPoolManager::<LRUReplacer, _>::new(1, 10, disk_manager);
+        let mut eht = ExtendibleHashTable::<_, _, u64, u64, _>::new(&bpm, RandomState::new());
+        for i in 0..10 {
+            eht.insert(&i, &i).unwrap();
+        }
+        assert_eq!(eht.get_value(&0).unwrap(), vec![0]);
+        // a page that is all zeros, but not a bucket
+        let mut bogus_page_id = PageId(0);
+        bpm.new_page(&mut bogus_page_id).unwrap();
+        bpm.unpin_page(bogus_page_id, true);
+        let dir_data = bpm.fetch_page(eht.get_dir_page_id()).unwrap();
+        {
+            let mut dir_data = dir_data.write().unwrap();
+            let dir: &mut HashTableDirectoryPage = cast_mut(&mut **dir_data);
+            // the directory is a single slot
+            dir.set_bucket_page_id(0, bogus_page_id);
+        }
+        bpm.unpin_page(eht.get_dir_page_id(), true);
+        std::fs::remove_file("test_uninitialized_bucket.db").unwrap();
+        eht.get_value(&0).unwrap();
+    }
+
+    #[test]
+    fn rehash_into_test() {
+        let disk_manager = Arc::new(DiskManagerInstance::new("test_rehash_into"));
+        let bpm = ParallelBufferPoolManager::<LRUReplacer, _>::new(5, 10, disk_manager);
+        let mut skewed = ExtendibleHashTable::<_, _, i64, i64, _>::new(
+            &bpm,
+            BuildHasherDefault::<IdentityHasher>::default(),
+        );
+        // the low 4 bits never tell keys apart, so splits on them leave empty buckets
+        for i in 0..2000 {
+            skewed.insert(&(i << 4), &i).unwrap();
+        }
+        let histogram = skewed.occupancy_histogram().unwrap();
+        assert!(histogram.contains(&0), "{:?}", histogram);
+
+        let rehashed = skewed.rehash_into(MixingBuildHasher).unwrap();
+        let histogram = rehashed.occupancy_histogram().unwrap();
+        assert!(histogram.iter().all(|n| *n > 0), "{:?}", histogram);
+        rehashed.verify().unwrap();
+        let mut before: Vec<_> = skewed.iter().unwrap().collect();
+        let mut after: Vec<_> = rehashed.iter().unwrap().collect();
+        before.sort();
+        after.sort();
+        assert_eq!(before, after);
+        std::fs::remove_file("test_rehash_into.db").unwrap();
+    }
+
+    #[test]
+    fn cached_len_test() {
+        let disk_manager = Arc::new(DiskManagerInstance::new("test_eht_cached_len"));
+        let (header_page_id, len) = {
+            let bpm = ParallelBufferPoolManager::<LRUReplacer, _>::new(5, 10, disk_manager.clone());
+            let hasher = RandomState::new();
+            let mut eht = ExtendibleHashTable::<_, _, i32, i32, _>::new(&bpm, hasher.clone());
+            let header_page_id = eht.get_header_page_id();
+            std::thread::scope(|s| {
+                for t in 0..4 {
+                    let hasher = hasher.clone();
+                    let bpm = &bpm;
+                    s.spawn(move || {
+                        let mut eht = ExtendibleHashTable::<_, _, i32, i32, _>::open(
+                            bpm,
+                            hasher,
+                            header_page_id,
+                        )
+                        .unwrap();
+                        for i in (t..2000).step_by(4) {
+                            eht.insert(&i, &i).unwrap();
+                            eht.insert(&i, &(i + 1)).unwrap();
+                            if i % 3 == 0 {
+                                eht.remove(&i, &i).unwrap();
+                            }
+                        }
+                    });
+                }
+            });
+            // the writers split the buckets under each other
+            eht.verify().unwrap();
+            for i in 0..2000 {
+                let mut values = eht.get_value(&i).unwrap();
+                values.sort();
+                let expected = if i % 3 == 0 {
+                    vec![i + 1]
+                } else {
+                    vec![i, i + 1]
+                };
+                assert_eq!(values, expected);
+            }
+            assert_eq!(eht.len(), eht.iter().unwrap().count());
+            eht.insert(&0, &1).unwrap();
+            eht.remove(&1, &5).unwrap();
+            assert_eq!(eht.remove_key(&5).unwrap(), 2);
+            eht.increment(&6, 10).unwrap();
+            eht.increment(&5000, 1).unwrap();
+            eht.retain(|key, _| key % 7 != 0).unwrap();
+            let len = eht.len();
+            assert_eq!(len, eht.iter().unwrap().count());
+            drop(eht);
+            bpm.flush_all_pages().unwrap();
+            (header_page_id, len)
+        };
+
+        let bpm = ParallelBufferPoolManager::<LRUReplacer, _>::new(5, 10, disk_manager);
+        let eht = ExtendibleHashTable::<_, _, i32, i32, _>::open(
+            &bpm,
+            RandomState::new(),
+            header_page_id,
+        )
+        .unwrap();
+        assert_eq!(eht.len(), len);
+        assert_eq!(eht.iter().unwrap().count(), len);
+        std::fs::remove_file("test_eht_cached_len.db").unwrap();
     }
 }
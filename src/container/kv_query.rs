@@ -0,0 +1,82 @@
+use crate::buffer::replacer::Replacer;
+use crate::container::extendible_hash_table::{ExtendibleHashTable, HashTableError};
+use crate::storage::disk::disk_manager::DiskManager;
+use std::hash::BuildHasher;
+
+// Treats an i64 -> i64 hash table as a map with a single value per key.
+pub struct KvQuery<'a, R, D, H>
+where
+    R: Replacer,
+    D: DiskManager,
+    H: BuildHasher,
+{
+    table: ExtendibleHashTable<'a, R, D, i64, i64, H>,
+}
+
+impl<'a, R, D, H> KvQuery<'a, R, D, H>
+where
+    R: Replacer,
+    D: DiskManager,
+    H: BuildHasher,
+{
+    pub fn new(table: ExtendibleHashTable<'a, R, D, i64, i64, H>) -> Self {
+        Self { table }
+    }
+
+    pub fn into_inner(self) -> ExtendibleHashTable<'a, R, D, i64, i64, H> {
+        self.table
+    }
+
+    // If the key somehow has several values, any one of them.
+    pub fn point_lookup(&self, key: i64) -> Result<Option<i64>, HashTableError> {
+        Ok(self.table.get_value(&key)?.first().copied())
+    }
+
+    pub fn exists(&self, key: i64) -> Result<bool, HashTableError> {
+        Ok(self.point_lookup(key)?.is_some())
+    }
+
+    // Replaces whatever values the key had with value.
+    pub fn upsert(&mut self, key: i64, value: i64) -> Result<(), HashTableError> {
+        self.table.remove_key(&key)?;
+        self.table.insert(&key, &value)?;
+        Ok(())
+    }
+
+    pub fn count(&self) -> usize {
+        self.table.len()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::buffer::buffer_pool_manager::ParallelBufferPoolManager;
+    use crate::buffer::replacer::LRUReplacer;
+    use crate::storage::disk::disk_manager::DiskManagerInstance;
+    use std::collections::hash_map::RandomState;
+    use std::sync::Arc;
+
+    #[test]
+    fn kv_query_test() {
+        let disk_manager = Arc::new(DiskManagerInstance::new("test_kv_query"));
+        let bpm = ParallelBufferPoolManager::<LRUReplacer, _>::new(5, 10, disk_manager);
+        let mut table = ExtendibleHashTable::new(&bpm, RandomState::new());
+        table.insert(&1, &10).unwrap();
+        table.insert(&1, &11).unwrap();
+        let mut query = KvQuery::new(table);
+        assert_eq!(query.count(), 2);
+
+        query.upsert(1, 12).unwrap();
+        assert_eq!(query.point_lookup(1).unwrap(), Some(12));
+        assert_eq!(query.count(), 1);
+        query.upsert(2, 20).unwrap();
+        query.upsert(2, 20).unwrap();
+        assert!(query.exists(2).unwrap());
+        assert!(!query.exists(3).unwrap());
+        assert_eq!(query.point_lookup(3).unwrap(), None);
+        assert_eq!(query.count(), 2);
+        assert_eq!(query.into_inner().get_value(&2).unwrap(), vec![20]);
+        std::fs::remove_file("test_kv_query.db").unwrap();
+    }
+}
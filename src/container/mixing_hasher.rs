@@ -0,0 +1,104 @@
+use std::hash::{BuildHasher, Hasher};
+
+// A fast hasher for integer keys. Input is folded in cheaply and finish runs the splitmix64
+// finalizer, so every input bit reaches the low bits the directory indexes with. Keys that
+// only differ in their high bits, like multiples of a power of two, still spread over buckets.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct MixingBuildHasher;
+
+impl BuildHasher for MixingBuildHasher {
+    type Hasher = MixingHasher;
+
+    fn build_hasher(&self) -> MixingHasher {
+        MixingHasher(0)
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct MixingHasher(u64);
+
+impl MixingHasher {
+    fn add(&mut self, i: u64) {
+        self.0 = (self.0.rotate_left(5) ^ i).wrapping_mul(0x517c_c1b7_2722_0a95);
+    }
+}
+
+impl Hasher for MixingHasher {
+    fn finish(&self) -> u64 {
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.add(*byte as u64);
+        }
+    }
+
+    fn write_u8(&mut self, i: u8) {
+        self.add(i as u64);
+    }
+
+    fn write_u16(&mut self, i: u16) {
+        self.add(i as u64);
+    }
+
+    fn write_u32(&mut self, i: u32) {
+        self.add(i as u64);
+    }
+
+    fn write_u64(&mut self, i: u64) {
+        self.add(i);
+    }
+
+    fn write_usize(&mut self, i: usize) {
+        self.add(i as u64);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::buffer::buffer_pool_manager::ParallelBufferPoolManager;
+    use crate::buffer::replacer::LRUReplacer;
+    use crate::container::extendible_hash_table::ExtendibleHashTable;
+    use crate::storage::disk::disk_manager::DiskManagerInstance;
+    use std::collections::hash_map::DefaultHasher;
+    use std::hash::BuildHasherDefault;
+    use std::sync::Arc;
+
+    #[test]
+    fn low_bits_test() {
+        // multiplying alone would keep the low 16 bits at 0 for all of these
+        let low_bits: std::collections::HashSet<_> = (0..64u64)
+            .map(|i| MixingBuildHasher.hash_one(i << 16) & 0xff)
+            .collect();
+        assert!(low_bits.len() > 48, "{}", low_bits.len());
+    }
+
+    #[test]
+    fn mixing_hasher_balance_test() {
+        let disk_manager = Arc::new(DiskManagerInstance::new("test_mixing_hasher"));
+        let bpm = ParallelBufferPoolManager::<LRUReplacer, _>::new(5, 10, disk_manager);
+        let mut default = ExtendibleHashTable::<_, _, i64, i64, _>::new(
+            &bpm,
+            BuildHasherDefault::<DefaultHasher>::default(),
+        );
+        let mut mixing = ExtendibleHashTable::<_, _, i64, i64, _>::new(&bpm, MixingBuildHasher);
+        for i in 0..3000i64 {
+            assert!(default.insert(&(i << 16), &i).unwrap());
+            assert!(mixing.insert(&(i << 16), &i).unwrap());
+        }
+        for table in [
+            default.occupancy_histogram().unwrap(),
+            mixing.occupancy_histogram().unwrap(),
+        ] {
+            assert_eq!(table.iter().sum::<usize>(), 3000);
+            assert!(table.iter().all(|n| *n > 0), "{:?}", table);
+        }
+        assert_eq!(mixing.get_value(&(42 << 16)).unwrap(), vec![42]);
+        std::fs::remove_file("test_mixing_hasher.db").unwrap();
+    }
+}
@@ -1,3 +1,8 @@
-mod extendible_hash_table;
+pub mod blob_table;
+pub mod disk_cache;
+pub mod extendible_hash_table;
+pub mod kv_query;
+pub mod mixing_hasher;
+pub mod tagged_value;
 
 
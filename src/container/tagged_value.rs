@@ -0,0 +1,110 @@
+use bytemuck::{Pod, Zeroable};
+
+// Longest byte string a TaggedValue holds.
+pub const MAX_BYTES: usize = 14;
+
+const EMPTY: u8 = 0;
+const INT: u8 = 1;
+const FLOAT: u8 = 2;
+const BYTES: u8 = 3;
+
+// A value of one of a few kinds in 16 bytes, so one table can map keys to ints, floats and
+// short byte strings. A tag byte tells the kind, the payload is little-endian and zero past
+// the value, so equal values compare equal byte for byte. Zeroed, it is Empty.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct TaggedValue {
+    tag: u8,
+    // length of a byte string, 0 for the other kinds
+    len: u8,
+    payload: [u8; MAX_BYTES],
+}
+
+// Only u8 fields, so there is no padding.
+unsafe impl Zeroable for TaggedValue {}
+
+unsafe impl Pod for TaggedValue {}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Tagged<'a> {
+    Empty,
+    Int(i64),
+    Float(f64),
+    Bytes(&'a [u8]),
+}
+
+impl TaggedValue {
+    fn new(tag: u8, bytes: &[u8]) -> Self {
+        let mut payload = [0; MAX_BYTES];
+        payload[..bytes.len()].copy_from_slice(bytes);
+        TaggedValue {
+            tag,
+            len: if tag == BYTES { bytes.len() as u8 } else { 0 },
+            payload,
+        }
+    }
+
+    pub fn int(value: i64) -> Self {
+        Self::new(INT, &value.to_le_bytes())
+    }
+
+    pub fn float(value: f64) -> Self {
+        Self::new(FLOAT, &value.to_le_bytes())
+    }
+
+    // None if bytes is longer than MAX_BYTES.
+    pub fn bytes(bytes: &[u8]) -> Option<Self> {
+        (bytes.len() <= MAX_BYTES).then(|| Self::new(BYTES, bytes))
+    }
+
+    // Panics on a tag no constructor writes, e.g. after reading a page that isn't a table.
+    pub fn get(&self) -> Tagged<'_> {
+        let word = || self.payload[..8].try_into().unwrap();
+        match self.tag {
+            EMPTY => Tagged::Empty,
+            INT => Tagged::Int(i64::from_le_bytes(word())),
+            FLOAT => Tagged::Float(f64::from_le_bytes(word())),
+            BYTES => Tagged::Bytes(&self.payload[..self.len as usize]),
+            tag => panic!("bad tag {}", tag),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::buffer::buffer_pool_manager::ParallelBufferPoolManager;
+    use crate::buffer::replacer::LRUReplacer;
+    use crate::container::extendible_hash_table::ExtendibleHashTable;
+    use crate::storage::disk::in_memory_disk_manager::InMemoryDiskManager;
+    use std::collections::hash_map::RandomState;
+    use std::mem::size_of;
+    use std::sync::Arc;
+
+    #[test]
+    fn tagged_value_test() {
+        assert_eq!(size_of::<TaggedValue>(), 16);
+        assert_eq!(TaggedValue::default().get(), Tagged::Empty);
+        assert!(TaggedValue::bytes(&[0; MAX_BYTES + 1]).is_none());
+
+        let disk_manager = Arc::new(InMemoryDiskManager::new());
+        let bpm = ParallelBufferPoolManager::<LRUReplacer, _>::new(2, 10, disk_manager);
+        let mut eht =
+            ExtendibleHashTable::<_, _, i32, TaggedValue, _>::new(&bpm, RandomState::new());
+        eht.insert(&1, &TaggedValue::int(-7)).unwrap();
+        eht.insert(&2, &TaggedValue::float(2.5)).unwrap();
+        eht.insert(&3, &TaggedValue::bytes(b"hello").unwrap())
+            .unwrap();
+        eht.insert(&4, &TaggedValue::bytes(&[]).unwrap()).unwrap();
+        // kinds don't mix: the same bits under another tag are another value
+        eht.insert(&1, &TaggedValue::float(f64::from_bits(-7i64 as u64)))
+            .unwrap();
+
+        let values = eht.get_value(&1).unwrap();
+        assert_eq!(values.len(), 2);
+        assert!(values.iter().any(|value| value.get() == Tagged::Int(-7)));
+        assert_eq!(eht.get_value(&2).unwrap()[0].get(), Tagged::Float(2.5));
+        assert_eq!(eht.get_value(&3).unwrap()[0].get(), Tagged::Bytes(b"hello"));
+        assert_eq!(eht.get_value(&4).unwrap()[0].get(), Tagged::Bytes(&[]));
+    }
+}
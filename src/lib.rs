@@ -0,0 +1,10 @@
+#![allow(unused)]
+#![allow(incomplete_features)]
+#![feature(generic_const_exprs)]
+
+pub mod buffer;
+pub mod concurrency;
+pub mod container;
+pub mod metrics;
+pub mod recovery;
+pub mod storage;
@@ -1,12 +1 @@
-#![feature(linked_list_cursors)]
-#![allow(unused)]
-#![feature(scoped_threads)]
-#![feature(generic_associated_types)]
-#![feature(generic_const_exprs)]
-#![feature(generic_arg_infer)]
-
-mod buffer;
-mod storage;
-mod container;
-
 fn main() {}
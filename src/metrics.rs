@@ -0,0 +1,6 @@
+// Counters in a pull-based form, for feeding an exporter such as Prometheus from user code
+// without this crate depending on it. Names are snake_case and stable, values are the
+// current reading, not a delta since the last snapshot.
+pub trait Metrics {
+    fn snapshot(&self) -> Vec<(String, f64)>;
+}
@@ -0,0 +1,105 @@
+use crate::recovery::log_record::{LogRecord, Lsn};
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::sync::Mutex;
+
+#[derive(Debug)]
+struct LogBuffer {
+    buf: Vec<u8>,
+    next_lsn: Lsn,
+}
+
+#[derive(Debug)]
+pub struct LogManager {
+    file: Mutex<File>,
+    buffer: Mutex<LogBuffer>,
+}
+
+impl LogManager {
+    pub fn new(dbname: &str) -> Self {
+        let file_name = format!("{}.log", dbname);
+        let file = File::options()
+            .read(true)
+            .append(true)
+            .create(true)
+            .open(file_name)
+            .unwrap();
+        let log_manager = Self {
+            file: Mutex::new(file),
+            buffer: Mutex::new(LogBuffer {
+                buf: Vec::new(),
+                next_lsn: Lsn(0),
+            }),
+        };
+        // continue numbering after whatever an earlier run left behind
+        let next_lsn = log_manager
+            .read_log_records()
+            .last()
+            .map_or(Lsn(0), |record| Lsn(record.get_lsn().0 + 1));
+        log_manager.buffer.lock().unwrap().next_lsn = next_lsn;
+        log_manager
+    }
+
+    // Assigns the next lsn to the record and buffers it. The record is only durable after flush.
+    pub fn append_log_record(&self, record: &mut LogRecord) -> Lsn {
+        let mut buffer = self.buffer.lock().unwrap();
+        let lsn = buffer.next_lsn;
+        buffer.next_lsn = Lsn(lsn.0 + 1);
+        record.set_lsn(lsn);
+        record.encode(&mut buffer.buf);
+        lsn
+    }
+
+    pub fn flush(&self) {
+        let mut buffer = self.buffer.lock().unwrap();
+        let mut file = self.file.lock().unwrap();
+        file.write_all(&buffer.buf).unwrap();
+        file.sync_data().unwrap();
+        buffer.buf.clear();
+    }
+
+    pub fn get_next_lsn(&self) -> Lsn {
+        self.buffer.lock().unwrap().next_lsn
+    }
+
+    // Decodes every durable record from the start of the log file, stopping at a torn tail.
+    pub fn read_log_records(&self) -> Vec<LogRecord> {
+        let mut bytes = Vec::new();
+        {
+            let mut file = self.file.lock().unwrap();
+            file.seek(SeekFrom::Start(0)).unwrap();
+            file.read_to_end(&mut bytes).unwrap();
+        }
+        let mut records = Vec::new();
+        let mut offset = 0;
+        while let Some((record, size)) = LogRecord::decode(&bytes[offset..]) {
+            records.push(record);
+            offset += size;
+        }
+        records
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::buffer::replacer::PageId;
+    use crate::recovery::log_record::LogRecordType;
+
+    #[test]
+    fn log_manager_test() {
+        let _ = std::fs::remove_file("test_log_manager.log");
+        let log_manager = LogManager::new("test_log_manager");
+        let mut insert = LogRecord::new(LogRecordType::Insert, PageId(0), PageId(1), &1u64, &2u64);
+        let mut delete = LogRecord::new(LogRecordType::Delete, PageId(0), PageId(1), &1u64, &2u64);
+        assert_eq!(log_manager.append_log_record(&mut insert), Lsn(0));
+        assert_eq!(log_manager.append_log_record(&mut delete), Lsn(1));
+        assert!(log_manager.read_log_records().is_empty());
+        log_manager.flush();
+        assert_eq!(log_manager.read_log_records(), vec![insert, delete]);
+        drop(log_manager);
+        let log_manager = LogManager::new("test_log_manager");
+        assert_eq!(log_manager.get_next_lsn(), Lsn(2));
+        std::fs::remove_file("test_log_manager.log").unwrap();
+    }
+}
@@ -0,0 +1,202 @@
+use crate::buffer::replacer::PageId;
+use crate::concurrency::transaction::{TxnId, INVALID_TXN_ID};
+use bytemuck::{bytes_of, pod_read_unaligned, Pod};
+use std::mem::size_of;
+
+pub const LOG_FORMAT_VERSION: u8 = 2;
+
+// size(4) + version(1) + type(1) + lsn(4) + txn_id(4) + dir(4) + bucket(4) + key_len(2) + value_len(2)
+pub const LOG_HEADER_SIZE: usize = 26;
+
+#[derive(Clone, Copy, PartialEq, Debug, Eq, Hash, Default, PartialOrd, Ord)]
+pub struct Lsn(pub(crate) u32);
+
+#[derive(Clone, Copy, PartialEq, Debug, Eq)]
+pub enum LogRecordType {
+    Insert = 1,
+    Delete = 2,
+    Commit = 3,
+    Abort = 4,
+}
+
+impl LogRecordType {
+    fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            1 => Some(LogRecordType::Insert),
+            2 => Some(LogRecordType::Delete),
+            3 => Some(LogRecordType::Commit),
+            4 => Some(LogRecordType::Abort),
+            _ => None,
+        }
+    }
+}
+
+/*
+| size | version | type | lsn | txn_id | dir_page_id | bucket_page_id | key_len | value_len | key | value |
+all integers are little endian, size covers the whole record including the header.
+commit/abort records carry no key or value.
+ */
+#[derive(Clone, PartialEq, Debug)]
+pub struct LogRecord {
+    lsn: Lsn,
+    txn_id: TxnId,
+    record_type: LogRecordType,
+    dir_page_id: PageId,
+    bucket_page_id: PageId,
+    key: Vec<u8>,
+    value: Vec<u8>,
+}
+
+impl LogRecord {
+    pub fn new<K: Pod, V: Pod>(
+        record_type: LogRecordType,
+        dir_page_id: PageId,
+        bucket_page_id: PageId,
+        key: &K,
+        value: &V,
+    ) -> Self {
+        LogRecord {
+            lsn: Lsn::default(),
+            txn_id: INVALID_TXN_ID,
+            record_type,
+            dir_page_id,
+            bucket_page_id,
+            key: bytes_of(key).to_vec(),
+            value: bytes_of(value).to_vec(),
+        }
+    }
+
+    pub fn new_txn_end(record_type: LogRecordType, txn_id: TxnId) -> Self {
+        debug_assert!(matches!(
+            record_type,
+            LogRecordType::Commit | LogRecordType::Abort
+        ));
+        LogRecord {
+            lsn: Lsn::default(),
+            txn_id,
+            record_type,
+            dir_page_id: PageId::default(),
+            bucket_page_id: PageId::default(),
+            key: Vec::new(),
+            value: Vec::new(),
+        }
+    }
+
+    pub fn get_lsn(&self) -> Lsn {
+        self.lsn
+    }
+
+    pub fn set_lsn(&mut self, lsn: Lsn) {
+        self.lsn = lsn;
+    }
+
+    pub fn get_txn_id(&self) -> TxnId {
+        self.txn_id
+    }
+
+    pub fn set_txn_id(&mut self, txn_id: TxnId) {
+        self.txn_id = txn_id;
+    }
+
+    pub fn get_type(&self) -> LogRecordType {
+        self.record_type
+    }
+
+    pub fn get_dir_page_id(&self) -> PageId {
+        self.dir_page_id
+    }
+
+    pub fn get_bucket_page_id(&self) -> PageId {
+        self.bucket_page_id
+    }
+
+    pub fn get_key<K: Pod>(&self) -> K {
+        assert_eq!(self.key.len(), size_of::<K>());
+        pod_read_unaligned(&self.key)
+    }
+
+    pub fn get_value<V: Pod>(&self) -> V {
+        assert_eq!(self.value.len(), size_of::<V>());
+        pod_read_unaligned(&self.value)
+    }
+
+    pub fn size(&self) -> usize {
+        LOG_HEADER_SIZE + self.key.len() + self.value.len()
+    }
+
+    pub fn encode(&self, buf: &mut Vec<u8>) {
+        buf.extend_from_slice(&(self.size() as u32).to_le_bytes());
+        buf.push(LOG_FORMAT_VERSION);
+        buf.push(self.record_type as u8);
+        buf.extend_from_slice(&self.lsn.0.to_le_bytes());
+        buf.extend_from_slice(&self.txn_id.0.to_le_bytes());
+        buf.extend_from_slice(&self.dir_page_id.0.to_le_bytes());
+        buf.extend_from_slice(&self.bucket_page_id.0.to_le_bytes());
+        buf.extend_from_slice(&(self.key.len() as u16).to_le_bytes());
+        buf.extend_from_slice(&(self.value.len() as u16).to_le_bytes());
+        buf.extend_from_slice(&self.key);
+        buf.extend_from_slice(&self.value);
+    }
+
+    // Returns the record and the number of bytes it occupied, or None if buf does not start
+    // with a complete record of a known version (e.g. a torn tail after a crash).
+    pub fn decode(buf: &[u8]) -> Option<(LogRecord, usize)> {
+        if buf.len() < LOG_HEADER_SIZE {
+            return None;
+        }
+        let read_u32 =
+            |offset: usize| u32::from_le_bytes(buf[offset..offset + 4].try_into().unwrap());
+        let read_u16 =
+            |offset: usize| u16::from_le_bytes(buf[offset..offset + 2].try_into().unwrap());
+        let size = read_u32(0) as usize;
+        if buf[4] != LOG_FORMAT_VERSION || size > buf.len() {
+            return None;
+        }
+        let record_type = LogRecordType::from_u8(buf[5])?;
+        let key_len = read_u16(22) as usize;
+        let value_len = read_u16(24) as usize;
+        if LOG_HEADER_SIZE + key_len + value_len != size {
+            return None;
+        }
+        let key_start = LOG_HEADER_SIZE;
+        let value_start = key_start + key_len;
+        let record = LogRecord {
+            lsn: Lsn(read_u32(6)),
+            txn_id: TxnId(read_u32(10)),
+            record_type,
+            dir_page_id: PageId(read_u32(14)),
+            bucket_page_id: PageId(read_u32(18)),
+            key: buf[key_start..value_start].to_vec(),
+            value: buf[value_start..size].to_vec(),
+        };
+        Some((record, size))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn encode_decode_test() {
+        let mut record =
+            LogRecord::new(LogRecordType::Delete, PageId(1), PageId(7), &42i32, &-3i64);
+        record.set_lsn(Lsn(9));
+        record.set_txn_id(TxnId(3));
+        let mut buf = Vec::new();
+        record.encode(&mut buf);
+        assert_eq!(buf.len(), record.size());
+        let (decoded, size) = LogRecord::decode(&buf).unwrap();
+        assert_eq!(size, buf.len());
+        assert_eq!(decoded, record);
+        assert_eq!(decoded.get_key::<i32>(), 42);
+        assert_eq!(decoded.get_value::<i64>(), -3);
+        assert!(LogRecord::decode(&buf[..buf.len() - 1]).is_none());
+
+        let commit = LogRecord::new_txn_end(LogRecordType::Commit, TxnId(3));
+        let mut buf = Vec::new();
+        commit.encode(&mut buf);
+        assert_eq!(buf.len(), LOG_HEADER_SIZE);
+        assert_eq!(LogRecord::decode(&buf).unwrap().0, commit);
+    }
+}
@@ -0,0 +1,351 @@
+use crate::buffer::replacer::Replacer;
+use crate::concurrency::transaction::{Transaction, INVALID_TXN_ID};
+use crate::concurrency::transaction_manager::TransactionManager;
+use crate::container::extendible_hash_table::{ExtendibleHashTable, HashTableError};
+use crate::recovery::log_manager::LogManager;
+use crate::recovery::log_record::LogRecordType;
+use crate::storage::disk::disk_manager::DiskManager;
+use crate::storage::pages::hash_table_bucket_page::Tool;
+use bytemuck::Pod;
+use std::collections::{HashMap, HashSet};
+use std::hash::{BuildHasher, Hash};
+
+pub struct LogRecovery<'a> {
+    log_manager: &'a LogManager,
+}
+
+impl<'a> LogRecovery<'a> {
+    pub fn new(log_manager: &'a LogManager) -> Self {
+        Self { log_manager }
+    }
+
+    // Recovers the table after a crash, in two passes. Buckets torn on disk are emptied first (see
+    // reset_torn_buckets), since their lsn can't be trusted. Splits aren't logged, but a logging
+    // table writes each one as it happens, so the directory on disk routes like the one in memory
+    // did; the entries a crash during such a write left behind in the old bucket are dropped (see
+    // drop_misrouted). Redo repeats history: every durable insert/remove of the table is replayed
+    // in lsn order into the buckets that don't hold it yet, going by the lsn each bucket was
+    // written with (see apply_log_record). Undo then takes back the changes of transactions that
+    // neither committed nor aborted, like an abort would, and closes them with an abort record.
+    // The table should log to the same log manager (see open_with_log_manager), so that the undo
+    // is logged and a crash during recovery is recovered from too. Finally the cached count is
+    // recomputed. Returns the number of records redo replayed, or the first error.
+    pub fn redo<R, D, K, V, H>(
+        &self,
+        table: &mut ExtendibleHashTable<'_, R, D, K, V, H>,
+    ) -> Result<usize, HashTableError>
+    where
+        R: Replacer,
+        D: DiskManager,
+        K: Hash + Default + Copy + PartialEq + Pod,
+        H: BuildHasher,
+        V: Default + Copy + PartialEq + Pod,
+        [(); Tool::<K, V>::KV_NUM]:,
+        [(); Tool::<K, V>::BYTE_NUM]:,
+        [(); Tool::<K, V>::BLANK_SIZE]:,
+    {
+        table.reset_torn_buckets()?;
+        table.drop_misrouted()?;
+        let records = self.log_manager.read_log_records();
+        let ended: HashSet<_> = records
+            .iter()
+            .filter(|record| {
+                matches!(
+                    record.get_type(),
+                    LogRecordType::Commit | LogRecordType::Abort
+                )
+            })
+            .map(|record| record.get_txn_id())
+            .collect();
+        let changes: Vec<_> = records
+            .iter()
+            .filter(|record| record.get_dir_page_id() == table.get_dir_page_id())
+            .collect();
+        let mut applied = 0;
+        for record in &changes {
+            if table.apply_log_record(record)? {
+                applied += 1;
+            }
+        }
+
+        let mut losers: HashMap<_, Transaction> = HashMap::new();
+        for record in changes {
+            let txn_id = record.get_txn_id();
+            if txn_id != INVALID_TXN_ID && !ended.contains(&txn_id) {
+                losers
+                    .entry(txn_id)
+                    .or_insert_with(|| Transaction::new(txn_id))
+                    .add_write_record(record.clone());
+            }
+        }
+        let txn_manager = TransactionManager::new(self.log_manager);
+        for txn in losers.values() {
+            txn_manager.abort(txn, table)?;
+        }
+        self.log_manager.flush();
+        table.recount()?;
+        Ok(applied)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::buffer::buffer_pool_manager::ParallelBufferPoolManager;
+    use crate::buffer::replacer::LRUReplacer;
+    use crate::storage::disk::disk_manager::{DiskManagerInstance, PAGE_SIZE};
+    use crate::storage::disk::faulty_disk_manager::FaultyDiskManager;
+    use crate::storage::disk::in_memory_disk_manager::InMemoryDiskManager;
+    use std::collections::hash_map::RandomState;
+    use std::sync::Arc;
+
+    #[test]
+    fn redo_test() {
+        let _ = std::fs::remove_file("test_redo.log");
+        let disk_manager = Arc::new(InMemoryDiskManager::new());
+        let log_manager = LogManager::new("test_redo");
+        let hasher = RandomState::new();
+        let header_page_id = {
+            let bpm = ParallelBufferPoolManager::<LRUReplacer, _>::new(5, 10, disk_manager.clone());
+            let mut eht = ExtendibleHashTable::<_, _, i32, i32, _>::new_with_log_manager(
+                &bpm,
+                hasher.clone(),
+                &log_manager,
+            );
+            for i in 0..50 {
+                eht.insert(&i, &(i * 2)).unwrap();
+            }
+            // the inserts reach the disk, the removes don't
+            log_manager.flush();
+            bpm.flush_all_pages().unwrap();
+            for i in (0..50).step_by(2) {
+                eht.remove(&i, &(i * 2)).unwrap();
+            }
+            log_manager.flush();
+            eht.get_header_page_id()
+            // crash: the buffer pool goes away without flush_all_pages
+        };
+
+        let bpm = ParallelBufferPoolManager::<LRUReplacer, _>::new(5, 10, disk_manager);
+        let mut eht = ExtendibleHashTable::<_, _, i32, i32, _>::open_with_log_manager(
+            &bpm,
+            hasher,
+            header_page_id,
+            &log_manager,
+        )
+        .unwrap();
+        assert_eq!(eht.len(), 50);
+        let recovery = LogRecovery::new(&log_manager);
+        // the inserts are older than the lsn the bucket was written with
+        assert_eq!(recovery.redo(&mut eht).unwrap(), 25);
+        for i in 0..50 {
+            if i % 2 == 0 {
+                assert_eq!(eht.get_value(&i).unwrap(), vec![]);
+            } else {
+                assert_eq!(eht.get_value(&i).unwrap(), vec![i * 2]);
+            }
+        }
+        assert_eq!(eht.len(), 25);
+        assert_eq!(recovery.redo(&mut eht).unwrap(), 0);
+        // recovery must not append to the log it is replaying
+        assert_eq!(log_manager.read_log_records().len(), 75);
+        std::fs::remove_file("test_redo.log").unwrap();
+    }
+
+    #[test]
+    fn redo_undoes_uncommitted_test() {
+        let _ = std::fs::remove_file("test_redo_txn.log");
+        let disk_manager = Arc::new(InMemoryDiskManager::new());
+        let log_manager = LogManager::new("test_redo_txn");
+        let hasher = RandomState::new();
+        let (header_page_id, running_id) = {
+            let bpm = ParallelBufferPoolManager::<LRUReplacer, _>::new(5, 10, disk_manager.clone());
+            let txn_manager = TransactionManager::new(&log_manager);
+            let mut eht = ExtendibleHashTable::<_, _, i32, i32, _>::new_with_log_manager(
+                &bpm,
+                hasher.clone(),
+                &log_manager,
+            );
+            let committed = txn_manager.begin();
+            let aborted = txn_manager.begin();
+            let running = txn_manager.begin();
+            eht.insert_with_txn(&1, &1, &committed).unwrap();
+            eht.insert_with_txn(&2, &2, &aborted).unwrap();
+            eht.insert_with_txn(&3, &3, &running).unwrap();
+            txn_manager.abort(&aborted, &mut eht).unwrap();
+            txn_manager.commit(&committed);
+            // the uncommitted insert reaches the disk
+            bpm.flush_all_pages().unwrap();
+            (eht.get_header_page_id(), running.get_txn_id())
+        };
+
+        let bpm = ParallelBufferPoolManager::<LRUReplacer, _>::new(5, 10, disk_manager);
+        let mut eht = ExtendibleHashTable::<_, _, i32, i32, _>::open_with_log_manager(
+            &bpm,
+            hasher,
+            header_page_id,
+            &log_manager,
+        )
+        .unwrap();
+        assert_eq!(eht.get_value(&3).unwrap(), vec![3]);
+        LogRecovery::new(&log_manager).redo(&mut eht).unwrap();
+        assert_eq!(eht.get_value(&1).unwrap(), vec![1]);
+        assert_eq!(eht.get_value(&2).unwrap(), vec![]);
+        assert_eq!(eht.get_value(&3).unwrap(), vec![]);
+        assert_eq!(eht.len(), 1);
+        // the undo is logged, so the next recovery doesn't undo it again
+        let records = log_manager.read_log_records();
+        let last = records.last().unwrap();
+        assert_eq!(last.get_type(), LogRecordType::Abort);
+        assert_eq!(last.get_txn_id(), running_id);
+        std::fs::remove_file("test_redo_txn.log").unwrap();
+    }
+
+    #[test]
+    fn redo_torn_bucket_test() {
+        let _ = std::fs::remove_file("test_redo_torn.log");
+        let disk_manager = Arc::new(DiskManagerInstance::new("test_redo_torn"));
+        let log_manager = LogManager::new("test_redo_torn");
+        let hasher = RandomState::new();
+        let (header_page_id, bucket_page_id) = {
+            let bpm = ParallelBufferPoolManager::<LRUReplacer, _>::new(1, 10, disk_manager.clone());
+            let mut eht = ExtendibleHashTable::<_, _, i32, i32, _>::new_with_log_manager(
+                &bpm,
+                hasher.clone(),
+                &log_manager,
+            );
+            for i in 0..50 {
+                eht.insert(&i, &(i * 2)).unwrap();
+            }
+            log_manager.flush();
+            bpm.flush_all_pages().unwrap();
+            let bucket_page_id = eht.get_value_located(&0).unwrap()[0].0;
+            (eht.get_header_page_id(), bucket_page_id)
+        };
+        // a crash halfway through rewriting the bucket leaves garbage in its first half, lsn
+        // included
+        disk_manager
+            .write_range(bucket_page_id, &[0xff; PAGE_SIZE], 0..PAGE_SIZE / 2)
+            .unwrap();
+        assert_eq!(
+            disk_manager.scan_for_torn_pages().unwrap(),
+            vec![bucket_page_id]
+        );
+
+        let bpm = ParallelBufferPoolManager::<LRUReplacer, _>::new(1, 10, disk_manager);
+        let mut eht = ExtendibleHashTable::<_, _, i32, i32, _>::open_with_log_manager(
+            &bpm,
+            hasher,
+            header_page_id,
+            &log_manager,
+        )
+        .unwrap();
+        assert_eq!(LogRecovery::new(&log_manager).redo(&mut eht).unwrap(), 50);
+        for i in 0..50 {
+            assert_eq!(eht.get_value(&i).unwrap(), vec![i * 2]);
+        }
+        assert_eq!(eht.len(), 50);
+        eht.verify().unwrap();
+        std::fs::remove_file("test_redo_torn.db").unwrap();
+        std::fs::remove_file("test_redo_torn.log").unwrap();
+    }
+
+    #[test]
+    fn redo_after_split_test() {
+        const NUM_KEYS: i32 = Tool::<i32, i32>::KV_NUM as i32 * 3;
+        let _ = std::fs::remove_file("test_redo_split.log");
+        let disk_manager = Arc::new(InMemoryDiskManager::new());
+        let log_manager = LogManager::new("test_redo_split");
+        let hasher = RandomState::new();
+        let header_page_id = {
+            let bpm = ParallelBufferPoolManager::<LRUReplacer, _>::new(1, 20, disk_manager.clone());
+            let mut eht = ExtendibleHashTable::<_, _, i32, i32, _>::new_with_log_manager(
+                &bpm,
+                hasher.clone(),
+                &log_manager,
+            );
+            for i in 0..10 {
+                eht.insert(&i, &i).unwrap();
+            }
+            log_manager.flush();
+            bpm.flush_all_pages().unwrap();
+            let bucket_page_id = eht.get_value_located(&0).unwrap()[0].0;
+            // enough to split the only bucket, after the last flush
+            for i in 10..NUM_KEYS {
+                eht.insert(&i, &i).unwrap();
+            }
+            assert!(eht.occupancy_histogram().unwrap().len() > 1);
+            log_manager.flush();
+            // the old bucket is written back, nothing else is
+            bpm.flush_page(bucket_page_id).unwrap();
+            eht.get_header_page_id()
+        };
+
+        let bpm = ParallelBufferPoolManager::<LRUReplacer, _>::new(1, 20, disk_manager);
+        let mut eht = ExtendibleHashTable::<_, _, i32, i32, _>::open_with_log_manager(
+            &bpm,
+            hasher,
+            header_page_id,
+            &log_manager,
+        )
+        .unwrap();
+        LogRecovery::new(&log_manager).redo(&mut eht).unwrap();
+        for i in 0..NUM_KEYS {
+            assert_eq!(eht.get_value(&i).unwrap(), vec![i]);
+        }
+        assert_eq!(eht.len(), NUM_KEYS as usize);
+        eht.verify().unwrap();
+        std::fs::remove_file("test_redo_split.log").unwrap();
+    }
+
+    #[test]
+    fn redo_torn_split_test() {
+        const NUM_KEYS: i32 = Tool::<i32, i32>::KV_NUM as i32;
+        let _ = std::fs::remove_file("test_redo_torn_split.log");
+        let disk_manager = Arc::new(FaultyDiskManager::new(InMemoryDiskManager::new()));
+        let log_manager = LogManager::new("test_redo_torn_split");
+        let hasher = RandomState::new();
+        let header_page_id = {
+            let bpm = ParallelBufferPoolManager::<LRUReplacer, _>::new(1, 20, disk_manager.clone());
+            let mut eht = ExtendibleHashTable::<_, _, i32, i32, _>::new_with_log_manager(
+                &bpm,
+                hasher.clone(),
+                &log_manager,
+            );
+            for i in 0..NUM_KEYS {
+                eht.insert(&i, &i).unwrap();
+            }
+            log_manager.flush();
+            bpm.flush_all_pages().unwrap();
+            // the split writes the new bucket and the directory, then crashes before the old
+            // bucket
+            disk_manager.fail_nth_write(3);
+            assert!(matches!(
+                eht.insert(&NUM_KEYS, &NUM_KEYS),
+                Err(HashTableError::Buffer(_))
+            ));
+            assert_eq!(eht.occupancy_histogram().unwrap().len(), 1);
+            eht.get_header_page_id()
+        };
+
+        let bpm = ParallelBufferPoolManager::<LRUReplacer, _>::new(1, 20, disk_manager);
+        let mut eht = ExtendibleHashTable::<_, _, i32, i32, _>::open_with_log_manager(
+            &bpm,
+            hasher,
+            header_page_id,
+            &log_manager,
+        )
+        .unwrap();
+        // the moved entries are in both buckets
+        let occupancy = eht.occupancy_histogram().unwrap();
+        assert_eq!(occupancy.len(), 2);
+        assert!(occupancy.iter().sum::<usize>() > NUM_KEYS as usize);
+        LogRecovery::new(&log_manager).redo(&mut eht).unwrap();
+        for i in 0..NUM_KEYS {
+            assert_eq!(eht.get_value(&i).unwrap(), vec![i]);
+        }
+        assert_eq!(eht.len(), NUM_KEYS as usize);
+        eht.verify().unwrap();
+        std::fs::remove_file("test_redo_torn_split.log").unwrap();
+    }
+}
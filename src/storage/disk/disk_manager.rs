@@ -1,49 +1,291 @@
 use crate::buffer::replacer::PageId;
+use crate::metrics::Metrics;
+use crate::storage::crc32::crc32;
 use lazy_static::lazy_static;
 use std::fs::File;
+use std::io;
 use std::io::Read;
+use std::ops::Range;
 use std::os::unix::fs::FileExt;
 use std::os::unix::fs::OpenOptionsExt;
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::{Arc, RwLock};
 
 extern crate libc;
 
 pub const PAGE_SIZE: usize = 4096;
 
+/*
+The first PAGE_SIZE bytes of a db file are a header. After it, pages come in groups of
+CHECKSUMS_PER_PAGE, each group led by a checksum page holding a CRC-32 of every page of the group
+as of its last write, 0 for pages never written. A page that doesn't match its checksum was torn,
+e.g. by a crash in the middle of writing it.
+Header layout, little-endian: magic u32, format version u32, page size u32, the rest is zero.
+ */
+const MAGIC: u32 = u32::from_le_bytes(*b"RBST");
+pub const FORMAT_VERSION: u32 = 2;
+const HEADER_SIZE: u64 = PAGE_SIZE as u64;
+const CHECKSUMS_PER_PAGE: u64 = (PAGE_SIZE / 4) as u64;
+
+fn checksum_page_offset(page_id: PageId) -> u64 {
+    let group = page_id.0 as u64 / CHECKSUMS_PER_PAGE;
+    HEADER_SIZE + group * (CHECKSUMS_PER_PAGE + 1) * PAGE_SIZE as u64
+}
+
+fn page_offset(page_id: PageId) -> u64 {
+    let slot = page_id.0 as u64 % CHECKSUMS_PER_PAGE;
+    checksum_page_offset(page_id) + (slot + 1) * PAGE_SIZE as u64
+}
+
+fn checksum_offset(page_id: PageId) -> u64 {
+    checksum_page_offset(page_id) + page_id.0 as u64 % CHECKSUMS_PER_PAGE * 4
+}
+
+// never 0, that is left for pages never written
+fn page_checksum(page: &[u8; PAGE_SIZE]) -> u32 {
+    crc32(page).max(1)
+}
+
 pub trait DiskManager {
-    fn read_page(&self, page_id: PageId, page: &mut [u8; PAGE_SIZE]);
-    fn write_page(&self, page_id: PageId, page: &[u8; PAGE_SIZE]);
+    fn read_page(&self, page_id: PageId, page: &mut [u8; PAGE_SIZE]) -> io::Result<()>;
+    fn write_page(&self, page_id: PageId, page: &[u8; PAGE_SIZE]) -> io::Result<()>;
+
+    // Writes only page[range], leaving the rest of the page on disk as it is. Disk managers
+    // that can't do better write the whole page.
+    fn write_range(
+        &self,
+        page_id: PageId,
+        page: &[u8; PAGE_SIZE],
+        range: Range<usize>,
+    ) -> io::Result<()> {
+        self.write_page(page_id, page)
+    }
+
+    // Writes a batch of pages, stopping at the first failure. Disk managers that can't do
+    // better write them one at a time.
+    fn write_pages(&self, pages: &[(PageId, &[u8; PAGE_SIZE])]) -> io::Result<()> {
+        for (page_id, page) in pages {
+            self.write_page(*page_id, page)?;
+        }
+        Ok(())
+    }
+
+    // Makes everything written so far durable. Disk managers that don't persist anything have
+    // nothing to do.
+    fn sync(&self) -> io::Result<()> {
+        Ok(())
+    }
+
+    // Copies everything written so far to a new file at path. The caller has to keep writes
+    // away while this runs.
+    fn copy_to(&self, path: &Path) -> io::Result<()> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "disk manager can't be copied",
+        ))
+    }
+
+    // Pages whose contents on disk don't match what was last written to them, e.g. because a
+    // crash cut a write short. Disk managers that keep no checksums can't tell.
+    fn scan_for_torn_pages(&self) -> io::Result<Vec<PageId>> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "disk manager keeps no page checksums",
+        ))
+    }
 }
 #[derive(Debug)]
 pub struct DiskManagerInstance {
     file: File,
+    // pages read and written, the header doesn't count
+    reads: AtomicUsize,
+    writes: AtomicUsize,
+    syncs: AtomicUsize,
 }
 
 impl DiskManager for DiskManagerInstance {
-    fn read_page(&self, page_id: PageId, page: &mut [u8; PAGE_SIZE]) {
-        self.file
-            .read_at(page, page_id.0 as u64 * PAGE_SIZE as u64)
-            .unwrap();
+    // Whatever lies past the end of the file reads as zeros, like a hole, so a page never
+    // written comes back zeroed rather than keeping what the buffer held before.
+    fn read_page(&self, page_id: PageId, page: &mut [u8; PAGE_SIZE]) -> io::Result<()> {
+        self.reads.fetch_add(1, Ordering::Relaxed);
+        let offset = page_offset(page_id);
+        let mut read = 0;
+        while read < PAGE_SIZE {
+            match self.file.read_at(&mut page[read..], offset + read as u64) {
+                Ok(0) => break,
+                Ok(n) => read += n,
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+                Err(err) => return Err(err),
+            }
+        }
+        page[read..].fill(0);
+        Ok(())
     }
 
-    fn write_page(&self, page_id: PageId, page: &[u8; PAGE_SIZE]) {
-        self.file
-            .write_at(page, page_id.0 as u64 * PAGE_SIZE as u64)
-            .unwrap();
+    // The checksum goes after the page, so a crash in between leaves the page reported torn
+    // rather than a torn page passing.
+    fn write_page(&self, page_id: PageId, page: &[u8; PAGE_SIZE]) -> io::Result<()> {
+        self.writes.fetch_add(1, Ordering::Relaxed);
+        self.file.write_all_at(page, page_offset(page_id))?;
+        self.write_checksum(page_id, page)
+    }
+
+    fn write_range(
+        &self,
+        page_id: PageId,
+        page: &[u8; PAGE_SIZE],
+        range: Range<usize>,
+    ) -> io::Result<()> {
+        self.writes.fetch_add(1, Ordering::Relaxed);
+        let offset = page_offset(page_id) + range.start as u64;
+        self.file.write_all_at(&page[range], offset)?;
+        self.write_checksum(page_id, page)
+    }
+
+    fn scan_for_torn_pages(&self) -> io::Result<Vec<PageId>> {
+        let len = self.file.metadata()?.len();
+        let mut torn = Vec::new();
+        let mut checksums = [0u8; PAGE_SIZE];
+        let mut page = [0u8; PAGE_SIZE];
+        let mut first = 0;
+        while checksum_page_offset(PageId(first)) < len {
+            checksums.fill(0);
+            self.file
+                .read_at(&mut checksums, checksum_page_offset(PageId(first)))?;
+            for (slot, checksum) in checksums.chunks_exact(4).enumerate() {
+                let checksum = u32::from_le_bytes(checksum.try_into().unwrap());
+                if checksum == 0 {
+                    continue;
+                }
+                let page_id = PageId(first + slot as u32);
+                let offset = page_offset(page_id);
+                if offset + PAGE_SIZE as u64 > len {
+                    torn.push(page_id);
+                    continue;
+                }
+                self.file.read_exact_at(&mut page, offset)?;
+                if page_checksum(&page) != checksum {
+                    torn.push(page_id);
+                }
+            }
+            first += CHECKSUMS_PER_PAGE as u32;
+        }
+        Ok(torn)
+    }
+
+    fn copy_to(&self, path: &Path) -> io::Result<()> {
+        let out = File::create(path)?;
+        let len = self.file.metadata()?.len();
+        let mut buf = [0u8; PAGE_SIZE];
+        let mut offset = 0;
+        while offset < len {
+            let n = self.file.read_at(&mut buf, offset)?;
+            if n == 0 {
+                break;
+            }
+            out.write_all_at(&buf[..n], offset)?;
+            offset += n as u64;
+        }
+        out.sync_all()
+    }
+
+    fn sync(&self) -> io::Result<()> {
+        self.syncs.fetch_add(1, Ordering::Relaxed);
+        self.file.sync_all()
+    }
+}
+
+impl Metrics for DiskManagerInstance {
+    fn snapshot(&self) -> Vec<(String, f64)> {
+        vec![
+            ("disk_reads".to_string(), self.reads.load(Ordering::Relaxed) as f64),
+            ("disk_writes".to_string(), self.writes.load(Ordering::Relaxed) as f64),
+            ("disk_syncs".to_string(), self.syncs.load(Ordering::Relaxed) as f64),
+        ]
     }
 }
 
 impl DiskManagerInstance {
     pub fn new(dbname: &str) -> Self {
+        Self::open(dbname).unwrap()
+    }
+
+    // Creates the file with a fresh header, or checks the header of an existing one. Fails with
+    // InvalidData if the file was not written by this format version and page size.
+    pub fn open(dbname: &str) -> io::Result<Self> {
         let file_name = format!("{}.db", dbname);
         let file = File::options()
             // .custom_flags(libc::O_DIRECT)
             .read(true)
             .write(true)
             .create(true)
-            .open(file_name)
-            .unwrap();
-        Self { file }
+            .truncate(false)
+            .open(file_name)?;
+        let disk_manager = Self {
+            file,
+            reads: AtomicUsize::new(0),
+            writes: AtomicUsize::new(0),
+            syncs: AtomicUsize::new(0),
+        };
+        if disk_manager.file.metadata()?.len() == 0 {
+            disk_manager.write_header(FORMAT_VERSION, PAGE_SIZE as u32)?;
+        } else {
+            disk_manager.check_header()?;
+        }
+        Ok(disk_manager)
+    }
+
+    // Opens an existing db without write permission, so every write fails. For tools that
+    // inspect a file they must not change.
+    pub fn open_readonly(dbname: &str) -> io::Result<Self> {
+        let file = File::options().read(true).open(format!("{}.db", dbname))?;
+        let disk_manager = Self {
+            file,
+            reads: AtomicUsize::new(0),
+            writes: AtomicUsize::new(0),
+            syncs: AtomicUsize::new(0),
+        };
+        disk_manager.check_header()?;
+        Ok(disk_manager)
+    }
+
+    fn write_checksum(&self, page_id: PageId, page: &[u8; PAGE_SIZE]) -> io::Result<()> {
+        self.file
+            .write_all_at(&page_checksum(page).to_le_bytes(), checksum_offset(page_id))
+    }
+
+    fn write_header(&self, version: u32, page_size: u32) -> io::Result<()> {
+        let mut header = [0u8; PAGE_SIZE];
+        header[0..4].copy_from_slice(&MAGIC.to_le_bytes());
+        header[4..8].copy_from_slice(&version.to_le_bytes());
+        header[8..12].copy_from_slice(&page_size.to_le_bytes());
+        self.file.write_all_at(&header, 0)
+    }
+
+    fn check_header(&self) -> io::Result<()> {
+        let mut header = [0u8; 12];
+        self.file.read_exact_at(&mut header, 0)?;
+        let field = |i: usize| u32::from_le_bytes(header[i..i + 4].try_into().unwrap());
+        let invalid = |msg: String| Err(io::Error::new(io::ErrorKind::InvalidData, msg));
+        if field(0) != MAGIC {
+            return invalid("not a db file".to_string());
+        }
+        if field(4) != FORMAT_VERSION {
+            return invalid(format!(
+                "format version {} is not supported, expected {}",
+                field(4),
+                FORMAT_VERSION
+            ));
+        }
+        if field(8) != PAGE_SIZE as u32 {
+            return invalid(format!(
+                "file was written with page size {}, expected {}",
+                field(8),
+                PAGE_SIZE
+            ));
+        }
+        Ok(())
     }
 }
 
@@ -61,11 +303,11 @@ mod test {
         let page10 = [10u8; PAGE_SIZE];
         let page5 = [5u8; PAGE_SIZE];
         let mut buf = [0u8; PAGE_SIZE];
-        disk_manager.write_page(PageId(10), &page10);
-        disk_manager.write_page(PageId(5), &page5);
-        disk_manager.read_page(PageId(10), &mut buf);
+        disk_manager.write_page(PageId(10), &page10).unwrap();
+        disk_manager.write_page(PageId(5), &page5).unwrap();
+        disk_manager.read_page(PageId(10), &mut buf).unwrap();
         assert_eq!(buf, page10);
-        disk_manager.read_page(PageId(5), &mut buf);
+        disk_manager.read_page(PageId(5), &mut buf).unwrap();
         assert_eq!(buf, page5);
         std::fs::remove_file("test.db").unwrap();
     }
@@ -78,7 +320,7 @@ mod test {
         for i in 0..num_pages {
             let disk_manager_clone = Arc::clone(&disk_manager);
             write_threads.push(thread::spawn(move || {
-                disk_manager_clone.write_page(PageId(i), &[i as u8; PAGE_SIZE]);
+                disk_manager_clone.write_page(PageId(i), &[i as u8; PAGE_SIZE]).unwrap();
             }));
         }
         for thread in write_threads {
@@ -100,7 +342,7 @@ mod test {
             let disk_manager_clone = Arc::clone(&disk_manager);
             let pages_clone = Arc::clone(&pages);
             write_threads.push(thread::spawn(move || {
-                disk_manager_clone.write_page(PageId(i as u32), &pages_clone.lock().unwrap()[i]);
+                disk_manager_clone.write_page(PageId(i as u32), &pages_clone.lock().unwrap()[i]).unwrap();
             }));
         }
         for thread in write_threads {
@@ -118,11 +360,11 @@ mod test {
         for i in 0..num_pages {
             pages.push(Arc::new(Mutex::new([i as u8; PAGE_SIZE])));
         }
-        for i in 0..num_pages {
+        for (i, page) in pages.iter().enumerate() {
             let disk_manager_clone = Arc::clone(&disk_manager);
-            let page_clone = Arc::clone(&pages[i]);
+            let page_clone = Arc::clone(page);
             write_threads.push(thread::spawn(move || {
-                disk_manager_clone.write_page(PageId(i as u32), &page_clone.lock().unwrap());
+                disk_manager_clone.write_page(PageId(i as u32), &page_clone.lock().unwrap()).unwrap();
             }));
         }
         for thread in write_threads {
@@ -153,7 +395,7 @@ mod test {
         let mut write_threads = Vec::new();
         for i in 0..num_pages {
             write_threads.push(thread::spawn(move || {
-                DISK_MANAGER.write_page(PageId(i as u32), &PAGES[i].lock().unwrap());
+                DISK_MANAGER.write_page(PageId(i as u32), &PAGES[i].lock().unwrap()).unwrap();
             }));
         }
         for thread in write_threads {
@@ -174,7 +416,7 @@ mod test {
             for (i, page) in pages.iter_mut().enumerate() {
                 let disk_manager_ref = &disk_manager;
                 s.spawn(move || {
-                    disk_manager_ref.write_page(PageId(i as u32), page);
+                    disk_manager_ref.write_page(PageId(i as u32), page).unwrap();
                 });
             }
         });
@@ -191,7 +433,7 @@ mod test {
             let disk_manager_clone = Arc::clone(&disk_manager);
             write_threads.push(thread::spawn(move || {
                 for j in 0..10 {
-                    disk_manager_clone.write_page(PageId(i), &[i as u8; PAGE_SIZE]);
+                    disk_manager_clone.write_page(PageId(i), &[i as u8; PAGE_SIZE]).unwrap();
                 }
             }));
         }
@@ -210,17 +452,68 @@ mod test {
         let start = Instant::now();
         for j in 0..10 {
             for i in 0..num_pages {
-                disk_manager.write_page(PageId(i), &[i as u8; PAGE_SIZE]);
+                disk_manager.write_page(PageId(i), &[i as u8; PAGE_SIZE]).unwrap();
             }
         }
 
         let end = Instant::now();
         println!("single thread write time: {:?}", end - start);
         let mut buf = [0u8; PAGE_SIZE];
-        disk_manager.read_page(PageId(0), &mut buf);
+        disk_manager.read_page(PageId(0), &mut buf).unwrap();
         for data in buf {
             println!("{}", data);
         }
         std::fs::remove_file("test_6.db").unwrap();
     }
+
+    #[test]
+    fn header_test() {
+        let _ = std::fs::remove_file("test_header.db");
+        {
+            let disk_manager = DiskManagerInstance::open("test_header").unwrap();
+            disk_manager.write_page(PageId(0), &[1u8; PAGE_SIZE]).unwrap();
+        }
+        // reopening checks the header and finds the page where it was left
+        let disk_manager = DiskManagerInstance::open("test_header").unwrap();
+        let mut buf = [0u8; PAGE_SIZE];
+        disk_manager.read_page(PageId(0), &mut buf).unwrap();
+        assert_eq!(buf, [1u8; PAGE_SIZE]);
+
+        disk_manager.write_header(FORMAT_VERSION, PAGE_SIZE as u32 * 2).unwrap();
+        let err = DiskManagerInstance::open("test_header").unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(err.to_string().contains("page size 8192"));
+
+        disk_manager.write_header(FORMAT_VERSION + 1, PAGE_SIZE as u32).unwrap();
+        let err = DiskManagerInstance::open("test_header").unwrap_err();
+        assert!(err.to_string().contains("format version"));
+        std::fs::remove_file("test_header.db").unwrap();
+    }
+
+    #[test]
+    fn scan_for_torn_pages_test() {
+        let _ = std::fs::remove_file("test_torn_pages.db");
+        let disk_manager = DiskManagerInstance::new("test_torn_pages");
+        // 1500 is in the second checksum group
+        for i in [0, 3, 1500] {
+            disk_manager.write_page(PageId(i), &[i as u8 + 1; PAGE_SIZE]).unwrap();
+        }
+        let mut page = [4u8; PAGE_SIZE];
+        page[..8].fill(9);
+        disk_manager.write_range(PageId(3), &page, 0..8).unwrap();
+        assert_eq!(disk_manager.scan_for_torn_pages().unwrap(), vec![]);
+        let mut buf = [0u8; PAGE_SIZE];
+        disk_manager.read_page(PageId(1500), &mut buf).unwrap();
+        assert_eq!(buf, [1500u32 as u8 + 1; PAGE_SIZE]);
+
+        // a crash halfway through rewriting page 3
+        disk_manager
+            .file
+            .write_all_at(&[7u8; PAGE_SIZE / 2], page_offset(PageId(3)))
+            .unwrap();
+        assert_eq!(disk_manager.scan_for_torn_pages().unwrap(), vec![PageId(3)]);
+        disk_manager.write_page(PageId(3), &[7u8; PAGE_SIZE]).unwrap();
+        assert_eq!(disk_manager.scan_for_torn_pages().unwrap(), vec![]);
+        std::fs::remove_file("test_torn_pages.db").unwrap();
+    }
 }
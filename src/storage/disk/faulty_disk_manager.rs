@@ -0,0 +1,131 @@
+use crate::buffer::replacer::PageId;
+use crate::storage::disk::disk_manager::{DiskManager, PAGE_SIZE};
+use std::io;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+// Wraps a disk manager and injects failures, for exercising error paths in tests. All knobs
+// can be flipped while the disk manager is shared between threads.
+#[derive(Debug, Default)]
+pub struct FaultyDiskManager<D: DiskManager> {
+    inner: D,
+    // counts down to the read/write that fails, 0 means off
+    fail_read_countdown: AtomicUsize,
+    fail_write_countdown: AtomicUsize,
+    // reads only fill the first half of the page and zero the rest
+    short_reads: AtomicBool,
+    // reads return the page with its first byte flipped
+    corrupt_reads: AtomicBool,
+}
+
+impl<D: DiskManager> FaultyDiskManager<D> {
+    pub fn new(inner: D) -> Self {
+        Self {
+            inner,
+            fail_read_countdown: AtomicUsize::new(0),
+            fail_write_countdown: AtomicUsize::new(0),
+            short_reads: AtomicBool::new(false),
+            corrupt_reads: AtomicBool::new(false),
+        }
+    }
+
+    pub fn inner(&self) -> &D {
+        &self.inner
+    }
+
+    // The nth read from now (1-based) fails, once.
+    pub fn fail_nth_read(&self, n: usize) {
+        self.fail_read_countdown.store(n, Ordering::SeqCst);
+    }
+
+    // The nth write from now (1-based) fails, once, without reaching the inner disk manager.
+    pub fn fail_nth_write(&self, n: usize) {
+        self.fail_write_countdown.store(n, Ordering::SeqCst);
+    }
+
+    pub fn set_short_reads(&self, short_reads: bool) {
+        self.short_reads.store(short_reads, Ordering::SeqCst);
+    }
+
+    pub fn set_corrupt_reads(&self, corrupt_reads: bool) {
+        self.corrupt_reads.store(corrupt_reads, Ordering::SeqCst);
+    }
+
+    fn should_fail(countdown: &AtomicUsize) -> bool {
+        countdown.fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| n.checked_sub(1)) == Ok(1)
+    }
+}
+
+impl<D: DiskManager> DiskManager for FaultyDiskManager<D> {
+    fn read_page(&self, page_id: PageId, page: &mut [u8; PAGE_SIZE]) -> io::Result<()> {
+        if Self::should_fail(&self.fail_read_countdown) {
+            return Err(io::Error::other(format!(
+                "injected read failure on {:?}",
+                page_id
+            )));
+        }
+        self.inner.read_page(page_id, page)?;
+        if self.short_reads.load(Ordering::SeqCst) {
+            page[PAGE_SIZE / 2..].fill(0);
+        }
+        if self.corrupt_reads.load(Ordering::SeqCst) {
+            page[0] ^= 0xff;
+        }
+        Ok(())
+    }
+
+    fn write_page(&self, page_id: PageId, page: &[u8; PAGE_SIZE]) -> io::Result<()> {
+        if Self::should_fail(&self.fail_write_countdown) {
+            return Err(io::Error::other(format!(
+                "injected write failure on {:?}",
+                page_id
+            )));
+        }
+        self.inner.write_page(page_id, page)
+    }
+
+    fn sync(&self) -> io::Result<()> {
+        self.inner.sync()
+    }
+
+    fn copy_to(&self, path: &Path) -> io::Result<()> {
+        self.inner.copy_to(path)
+    }
+
+    fn scan_for_torn_pages(&self) -> io::Result<Vec<PageId>> {
+        self.inner.scan_for_torn_pages()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::storage::disk::disk_manager::DiskManagerInstance;
+
+    #[test]
+    fn faulty_disk_manager_test() {
+        let disk_manager = FaultyDiskManager::new(DiskManagerInstance::new("test_faulty_disk"));
+        let page = [7u8; PAGE_SIZE];
+        let mut buf = [0u8; PAGE_SIZE];
+        disk_manager.fail_nth_write(2);
+        disk_manager.write_page(PageId(0), &page).unwrap();
+        assert!(disk_manager.write_page(PageId(1), &page).is_err());
+        disk_manager.write_page(PageId(1), &page).unwrap();
+
+        disk_manager.fail_nth_read(1);
+        assert!(disk_manager.read_page(PageId(0), &mut buf).is_err());
+        disk_manager.read_page(PageId(0), &mut buf).unwrap();
+        assert_eq!(buf, page);
+
+        disk_manager.set_short_reads(true);
+        disk_manager.read_page(PageId(1), &mut buf).unwrap();
+        assert_eq!(buf[PAGE_SIZE / 2 - 1], 7);
+        assert_eq!(buf[PAGE_SIZE / 2], 0);
+        disk_manager.set_short_reads(false);
+
+        disk_manager.set_corrupt_reads(true);
+        disk_manager.read_page(PageId(1), &mut buf).unwrap();
+        assert_eq!(buf[0], !7);
+        std::fs::remove_file("test_faulty_disk.db").unwrap();
+    }
+}
@@ -0,0 +1,148 @@
+use crate::buffer::replacer::PageId;
+use crate::storage::disk::disk_manager::{DiskManager, PAGE_SIZE};
+use std::io;
+use std::mem;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, OnceLock};
+use std::thread;
+use std::time::Duration;
+
+type Write = (PageId, Box<[u8; PAGE_SIZE]>);
+
+// io::Error isn't Clone, every committer of a failed batch gets its own copy
+type BatchResult = Arc<OnceLock<Result<(), (io::ErrorKind, String)>>>;
+
+// Batches the page writes of concurrent committers into one write_pages and one sync (group
+// commit). The first committer to arrive leads: it waits for the window so others can join,
+// then writes the batch while the next one fills up behind it.
+pub struct GroupCommitter<D: DiskManager> {
+    disk_manager: Arc<D>,
+    window: Duration,
+    state: Mutex<State>,
+    batch_done: Condvar,
+}
+
+struct State {
+    pending: Vec<Write>,
+    // where the pending writes will learn how they went
+    batch: BatchResult,
+    // whether some committer is collecting or writing a batch
+    leading: bool,
+}
+
+impl<D: DiskManager> GroupCommitter<D> {
+    pub fn new(disk_manager: Arc<D>, window: Duration) -> Self {
+        Self {
+            disk_manager,
+            window,
+            state: Mutex::new(State {
+                pending: Vec::new(),
+                batch: Arc::default(),
+                leading: false,
+            }),
+            batch_done: Condvar::new(),
+        }
+    }
+
+    // Blocks until the pages are written and synced, together with whatever else was committed
+    // around the same time. Fails if the batch they went out in failed.
+    pub fn commit(&self, writes: Vec<Write>) -> io::Result<()> {
+        let mut state = self.state.lock().unwrap();
+        state.pending.extend(writes);
+        let batch = state.batch.clone();
+        loop {
+            if let Some(result) = batch.get() {
+                return result
+                    .clone()
+                    .map_err(|(kind, msg)| io::Error::new(kind, msg));
+            }
+            // our batch hasn't gone out yet, or the leader would be busy with it
+            state = if state.leading {
+                self.batch_done.wait(state).unwrap()
+            } else {
+                self.lead(state)
+            };
+        }
+    }
+
+    fn lead<'a>(&'a self, mut state: MutexGuard<'a, State>) -> MutexGuard<'a, State> {
+        state.leading = true;
+        drop(state);
+        thread::sleep(self.window);
+        let mut state = self.state.lock().unwrap();
+        let writes = mem::take(&mut state.pending);
+        let batch = mem::take(&mut state.batch);
+        drop(state);
+
+        let pages: Vec<_> = writes
+            .iter()
+            .map(|(page_id, page)| (*page_id, &**page))
+            .collect();
+        let result = self
+            .disk_manager
+            .write_pages(&pages)
+            .and_then(|()| self.disk_manager.sync())
+            .map_err(|err| (err.kind(), err.to_string()));
+
+        let mut state = self.state.lock().unwrap();
+        let _ = batch.set(result);
+        state.leading = false;
+        self.batch_done.notify_all();
+        state
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::metrics::Metrics;
+    use crate::storage::disk::disk_manager::DiskManagerInstance;
+    use crate::storage::disk::faulty_disk_manager::FaultyDiskManager;
+    use crate::storage::disk::in_memory_disk_manager::InMemoryDiskManager;
+    use std::collections::HashMap;
+
+    #[test]
+    fn group_commit_test() {
+        let disk_manager = Arc::new(DiskManagerInstance::new("test_group_commit"));
+        let committer = Arc::new(GroupCommitter::new(
+            disk_manager.clone(),
+            Duration::from_millis(20),
+        ));
+        let handles: Vec<_> = (0..16u32)
+            .map(|i| {
+                let committer = committer.clone();
+                thread::spawn(move || {
+                    committer.commit(vec![(PageId(i), Box::new([i as u8; PAGE_SIZE]))])
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap().unwrap();
+        }
+        let metrics: HashMap<_, _> = disk_manager.snapshot().into_iter().collect();
+        assert_eq!(metrics["disk_writes"], 16.0);
+        assert!(
+            metrics["disk_syncs"] < 8.0,
+            "{} syncs",
+            metrics["disk_syncs"]
+        );
+        let mut buf = [0u8; PAGE_SIZE];
+        for i in 0..16u32 {
+            disk_manager.read_page(PageId(i), &mut buf).unwrap();
+            assert_eq!(buf, [i as u8; PAGE_SIZE]);
+        }
+        std::fs::remove_file("test_group_commit.db").unwrap();
+    }
+
+    #[test]
+    fn group_commit_error_test() {
+        let disk_manager = Arc::new(FaultyDiskManager::new(InMemoryDiskManager::new()));
+        let committer = GroupCommitter::new(disk_manager.clone(), Duration::ZERO);
+        disk_manager.fail_nth_write(1);
+        assert!(committer
+            .commit(vec![(PageId(0), Box::new([1; PAGE_SIZE]))])
+            .is_err());
+        committer
+            .commit(vec![(PageId(0), Box::new([1; PAGE_SIZE]))])
+            .unwrap();
+    }
+}
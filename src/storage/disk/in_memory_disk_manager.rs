@@ -0,0 +1,126 @@
+use crate::buffer::replacer::PageId;
+use crate::metrics::Metrics;
+use crate::storage::disk::disk_manager::{DiskManager, PAGE_SIZE};
+use std::collections::HashMap;
+use std::io;
+use std::ops::Range;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+// Keeps the pages in memory instead of a file, so tests leave nothing behind on disk, even
+// when they panic. Pages never written read as zeros, like the holes of a db file.
+#[derive(Debug, Default)]
+pub struct InMemoryDiskManager {
+    pages: Mutex<HashMap<PageId, Box<[u8; PAGE_SIZE]>>>,
+    reads: AtomicUsize,
+    writes: AtomicUsize,
+    syncs: AtomicUsize,
+}
+
+impl InMemoryDiskManager {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // pages written so far
+    pub fn num_pages(&self) -> usize {
+        self.pages.lock().unwrap().len()
+    }
+}
+
+impl DiskManager for InMemoryDiskManager {
+    fn read_page(&self, page_id: PageId, page: &mut [u8; PAGE_SIZE]) -> io::Result<()> {
+        self.reads.fetch_add(1, Ordering::Relaxed);
+        match self.pages.lock().unwrap().get(&page_id) {
+            Some(stored) => page.copy_from_slice(&**stored),
+            None => page.fill(0),
+        }
+        Ok(())
+    }
+
+    fn write_page(&self, page_id: PageId, page: &[u8; PAGE_SIZE]) -> io::Result<()> {
+        self.writes.fetch_add(1, Ordering::Relaxed);
+        self.pages.lock().unwrap().insert(page_id, Box::new(*page));
+        Ok(())
+    }
+
+    fn write_range(
+        &self,
+        page_id: PageId,
+        page: &[u8; PAGE_SIZE],
+        range: Range<usize>,
+    ) -> io::Result<()> {
+        self.writes.fetch_add(1, Ordering::Relaxed);
+        let mut pages = self.pages.lock().unwrap();
+        let stored = pages
+            .entry(page_id)
+            .or_insert_with(|| Box::new([0; PAGE_SIZE]));
+        stored[range.clone()].copy_from_slice(&page[range]);
+        Ok(())
+    }
+
+    fn sync(&self) -> io::Result<()> {
+        self.syncs.fetch_add(1, Ordering::Relaxed);
+        Ok(())
+    }
+
+    // Writes happen whole under the lock, so no page is ever torn.
+    fn scan_for_torn_pages(&self) -> io::Result<Vec<PageId>> {
+        Ok(Vec::new())
+    }
+}
+
+impl Metrics for InMemoryDiskManager {
+    fn snapshot(&self) -> Vec<(String, f64)> {
+        vec![
+            (
+                "disk_reads".to_string(),
+                self.reads.load(Ordering::Relaxed) as f64,
+            ),
+            (
+                "disk_writes".to_string(),
+                self.writes.load(Ordering::Relaxed) as f64,
+            ),
+            (
+                "disk_syncs".to_string(),
+                self.syncs.load(Ordering::Relaxed) as f64,
+            ),
+        ]
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn in_memory_disk_manager_test() {
+        let disk_manager = InMemoryDiskManager::new();
+        let mut buf = [7; PAGE_SIZE];
+        disk_manager.read_page(PageId(3), &mut buf).unwrap();
+        assert_eq!(buf, [0; PAGE_SIZE]);
+
+        disk_manager.write_page(PageId(3), &[1; PAGE_SIZE]).unwrap();
+        disk_manager
+            .write_range(PageId(3), &[2; PAGE_SIZE], 0..8)
+            .unwrap();
+        disk_manager.read_page(PageId(3), &mut buf).unwrap();
+        assert_eq!(buf[..8], [2; 8]);
+        assert!(buf[8..].iter().all(|&b| b == 1));
+        assert_eq!(disk_manager.num_pages(), 1);
+
+        // the rest of a page never written reads as zeros
+        disk_manager
+            .write_range(PageId(5), &[3; PAGE_SIZE], 8..16)
+            .unwrap();
+        disk_manager.read_page(PageId(5), &mut buf).unwrap();
+        assert!(buf[..8].iter().all(|&b| b == 0));
+        assert_eq!(buf[8..16], [3; 8]);
+        assert!(buf[16..].iter().all(|&b| b == 0));
+        assert_eq!(disk_manager.num_pages(), 2);
+
+        let metrics: HashMap<_, _> = disk_manager.snapshot().into_iter().collect();
+        assert_eq!(metrics["disk_reads"], 3.0);
+        assert_eq!(metrics["disk_writes"], 3.0);
+    }
+}
@@ -1 +1,5 @@
 pub mod disk_manager;
+pub mod faulty_disk_manager;
+pub mod group_committer;
+pub mod in_memory_disk_manager;
+pub mod recording_disk_manager;
@@ -0,0 +1,158 @@
+use crate::buffer::replacer::PageId;
+use crate::storage::disk::disk_manager::{DiskManager, PAGE_SIZE};
+use std::io;
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::Instant;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DiskOpKind {
+    Read,
+    Write,
+}
+
+#[derive(Debug, Clone)]
+pub struct DiskOp {
+    pub kind: DiskOpKind,
+    pub page_id: PageId,
+    pub at: Instant,
+    // the bytes read or written, if the recorder keeps them
+    pub data: Option<Box<[u8; PAGE_SIZE]>>,
+    // whether the inner disk manager succeeded
+    pub ok: bool,
+}
+
+// Wraps a disk manager and records every read and write in order, to reproduce and debug I/O
+// ordering issues. Keeping the page bytes costs a copy per call, so it is opt-in.
+#[derive(Debug)]
+pub struct RecordingDiskManager<D: DiskManager> {
+    inner: D,
+    keep_data: bool,
+    trace: Mutex<Vec<DiskOp>>,
+}
+
+impl<D: DiskManager> RecordingDiskManager<D> {
+    pub fn new(inner: D) -> Self {
+        Self {
+            inner,
+            keep_data: false,
+            trace: Mutex::new(Vec::new()),
+        }
+    }
+
+    pub fn with_data(inner: D) -> Self {
+        Self {
+            keep_data: true,
+            ..Self::new(inner)
+        }
+    }
+
+    pub fn inner(&self) -> &D {
+        &self.inner
+    }
+
+    // Everything recorded so far, leaving the trace empty.
+    pub fn take_trace(&self) -> Vec<DiskOp> {
+        std::mem::take(&mut *self.trace.lock().unwrap())
+    }
+
+    fn record(&self, kind: DiskOpKind, page_id: PageId, page: &[u8; PAGE_SIZE], ok: bool) {
+        let op = DiskOp {
+            kind,
+            page_id,
+            at: Instant::now(),
+            data: self.keep_data.then(|| Box::new(*page)),
+            ok,
+        };
+        self.trace.lock().unwrap().push(op);
+    }
+}
+
+impl<D: DiskManager> DiskManager for RecordingDiskManager<D> {
+    fn read_page(&self, page_id: PageId, page: &mut [u8; PAGE_SIZE]) -> io::Result<()> {
+        let result = self.inner.read_page(page_id, page);
+        self.record(DiskOpKind::Read, page_id, page, result.is_ok());
+        result
+    }
+
+    fn write_page(&self, page_id: PageId, page: &[u8; PAGE_SIZE]) -> io::Result<()> {
+        let result = self.inner.write_page(page_id, page);
+        self.record(DiskOpKind::Write, page_id, page, result.is_ok());
+        result
+    }
+
+    // sync, copy_to and scan_for_torn_pages aren't page operations, so they aren't recorded
+    fn sync(&self) -> io::Result<()> {
+        self.inner.sync()
+    }
+
+    fn copy_to(&self, path: &Path) -> io::Result<()> {
+        self.inner.copy_to(path)
+    }
+
+    fn scan_for_torn_pages(&self) -> io::Result<Vec<PageId>> {
+        self.inner.scan_for_torn_pages()
+    }
+}
+
+// Re-issues the successful writes of a trace recorded with data, in order. Returns how many
+// writes were replayed.
+pub fn replay(trace: &[DiskOp], disk_manager: &impl DiskManager) -> io::Result<usize> {
+    let mut replayed = 0;
+    for op in trace {
+        if let (DiskOpKind::Write, true, Some(data)) = (op.kind, op.ok, &op.data) {
+            disk_manager.write_page(op.page_id, data)?;
+            replayed += 1;
+        }
+    }
+    Ok(replayed)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::buffer::buffer_pool_manager::ParallelBufferPoolManager;
+    use crate::buffer::replacer::LRUReplacer;
+    use crate::storage::disk::disk_manager::DiskManagerInstance;
+    use std::sync::Arc;
+
+    #[test]
+    fn recording_disk_manager_test() {
+        let disk_manager = Arc::new(RecordingDiskManager::with_data(DiskManagerInstance::new(
+            "test_recording_disk",
+        )));
+        let bpm = ParallelBufferPoolManager::<LRUReplacer, _>::new(1, 1, disk_manager.clone());
+        let mut first = PageId(0);
+        bpm.new_page(&mut first).unwrap().write().unwrap().0[0] = 1;
+        bpm.unpin_page(first, true);
+        // evicts the first page
+        let mut second = PageId(0);
+        bpm.new_page(&mut second).unwrap().write().unwrap().0[0] = 2;
+        bpm.unpin_page(second, true);
+        bpm.fetch_page(first).unwrap();
+        bpm.unpin_page(first, false);
+
+        let trace = disk_manager.take_trace();
+        let ops: Vec<_> = trace.iter().map(|op| (op.kind, op.page_id)).collect();
+        assert_eq!(
+            ops,
+            vec![
+                (DiskOpKind::Write, first),
+                (DiskOpKind::Write, second),
+                (DiskOpKind::Read, first),
+            ]
+        );
+        assert!(trace.iter().all(|op| op.ok));
+        assert!(trace.windows(2).all(|ops| ops[0].at <= ops[1].at));
+        assert_eq!(trace[1].data.as_ref().unwrap()[0], 2);
+        assert!(disk_manager.take_trace().is_empty());
+
+        let copy = DiskManagerInstance::new("test_recording_disk_replay");
+        assert_eq!(replay(&trace, &copy).unwrap(), 2);
+        let mut buf = [0u8; PAGE_SIZE];
+        copy.read_page(second, &mut buf).unwrap();
+        assert_eq!(buf[0], 2);
+        std::fs::remove_file("test_recording_disk.db").unwrap();
+        std::fs::remove_file("test_recording_disk_replay.db").unwrap();
+    }
+}
@@ -1,2 +1,3 @@
+pub(crate) mod crc32;
 pub mod disk;
 pub mod pages;
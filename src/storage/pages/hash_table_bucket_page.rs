@@ -1,4 +1,8 @@
+use crate::buffer::replacer::PageId;
+use crate::recovery::log_record::Lsn;
+use crate::storage::crc32::crc32_update;
 use crate::storage::disk::disk_manager::PAGE_SIZE;
+use crate::storage::pages::page::InitPage;
 use bytemuck::{Pod, Zeroable};
 use std::marker::PhantomData;
 use std::mem::{size_of, transmute};
@@ -13,10 +17,24 @@ pub enum InertResult {
 
 impl<K, V> Tool<K, V> {
     pub(crate) const KV_NUM: usize = Self::BYTE_NUM * 8;
-    pub(crate) const BYTE_NUM: usize = PAGE_SIZE / (8 * (size_of::<K>() + size_of::<V>()) + 1);
+    pub(crate) const BYTE_NUM: usize =
+        (PAGE_SIZE - HEADER_SIZE) / (8 * (size_of::<K>() + size_of::<V>()) + 1);
     pub(crate) const BLANK_SIZE: usize =
-        PAGE_SIZE - (size_of::<K>() + size_of::<V>()) * Self::KV_NUM - Self::BYTE_NUM;
+        PAGE_SIZE - HEADER_SIZE - (size_of::<K>() + size_of::<V>()) * Self::KV_NUM - Self::BYTE_NUM;
 }
+// free_hint, page_type and lsn
+const HEADER_SIZE: usize = 2 + size_of::<u32>();
+
+// Written by init, so a bucket can be told from a zeroed page that never went through it.
+const BUCKET_PAGE_TYPE: u8 = 0xb0;
+
+// Bytes of blank holding the bucket's checksum, when there is room for it.
+const CHECKSUM_SIZE: usize = 4;
+
+fn raw_bytes<T>(value: &T) -> &[u8] {
+    unsafe { std::slice::from_raw_parts(value as *const T as *const u8, size_of::<T>()) }
+}
+
 /*
 8个键值对占的空间：8 *(key + value) + 2
 最多可以储存的键值对的个数：PAGE_SIZE / (8 * (key + value) + 2) * 8
@@ -32,6 +50,14 @@ where
     [(); Tool::<K, V>::BLANK_SIZE]:,
 {
     readable: [u8; Tool::<K, V>::BYTE_NUM],
+    // Every readable byte before this one is full (0xff). Only a lower bound, so a zeroed
+    // page is fine.
+    free_hint: u8,
+    // BUCKET_PAGE_TYPE once initialized, 0 before
+    page_type: u8,
+    // lsn of the latest logged change plus one, 0 if none was logged. Bytes, so the bucket has
+    // no padding whatever K and V are.
+    lsn: [u8; 4],
     kvs: [(K, V); Tool::<K, V>::KV_NUM],
     blank: [u8; Tool::<K, V>::BLANK_SIZE],
 }
@@ -56,6 +82,35 @@ where
 {
 }
 
+impl<K, V> Default for HashTableBucketPage<K, V>
+where
+    K: Default + Copy + PartialEq,
+    V: Default + Copy + PartialEq,
+    [(); Tool::<K, V>::KV_NUM]:,
+    [(); Tool::<K, V>::BYTE_NUM]:,
+    [(); Tool::<K, V>::BLANK_SIZE]:,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<K, V> InitPage for HashTableBucketPage<K, V>
+where
+    K: Default + Copy + PartialEq,
+    V: Default + Copy + PartialEq,
+    [(); Tool::<K, V>::KV_NUM]:,
+    [(); Tool::<K, V>::BYTE_NUM]:,
+    [(); Tool::<K, V>::BLANK_SIZE]:,
+{
+    // A zeroed page is already an empty bucket, and buckets don't record their page id. The
+    // type tag tells the bucket from a page nobody initialized.
+    fn init(&mut self, _page_id: PageId) {
+        self.page_type = BUCKET_PAGE_TYPE;
+        self.update_checksum();
+    }
+}
+
 impl<K, V> HashTableBucketPage<K, V>
 where
     K: Default + Copy + PartialEq,
@@ -64,58 +119,223 @@ where
     [(); Tool::<K, V>::BYTE_NUM]:,
     [(); Tool::<K, V>::BLANK_SIZE]:,
 {
+    // All slots are unreadable in a zeroed bucket, whatever is in kvs.
     pub fn new() -> Self {
-        Self {
-            readable: [0u8; Tool::<K, V>::BYTE_NUM],
-            kvs: [(K::default(), V::default()); Tool::<K, V>::KV_NUM],
-            blank: [0u8; Tool::<K, V>::BLANK_SIZE],
-        }
+        Zeroable::zeroed()
     }
 
-    fn is_readable(&self, index: usize) -> bool {
+    pub fn is_readable(&self, index: usize) -> bool {
         self.readable[index / 8] & (1 << (index % 8)) != 0
     }
 
+    // A heuristic that ignores the type tag: the padding past the checksum is still zero and
+    // every byte before free_hint is full. Any zeroed page passes.
+    pub fn looks_valid(&self) -> bool {
+        let free_hint = self.free_hint as usize;
+        free_hint <= Tool::<K, V>::BYTE_NUM
+            && self.readable[..free_hint].iter().all(|byte| *byte == 0xff)
+            && self.blank.iter().skip(CHECKSUM_SIZE).all(|byte| *byte == 0)
+    }
+
+    // Debug builds keep a CRC of the bitmap and the readable entries in the first bytes of
+    // blank, to catch writes that bypass the bucket's methods, e.g. through a bad cast. Buckets
+    // without room for it, release builds and zeroed pages (checksum 0) are never checked.
+    fn has_checksum() -> bool {
+        cfg!(debug_assertions) && Tool::<K, V>::BLANK_SIZE >= CHECKSUM_SIZE
+    }
+
+    // never 0, that means no checksum was stored
+    fn compute_checksum(&self) -> u32 {
+        let mut crc = crc32_update(!0, &self.readable);
+        crc = crc32_update(crc, &[self.free_hint]);
+        for i in Self::readable_slots(self.readable) {
+            crc = crc32_update(crc, raw_bytes(&self.kvs[i].0));
+            crc = crc32_update(crc, raw_bytes(&self.kvs[i].1));
+        }
+        (!crc).max(1)
+    }
+
+    fn stored_checksum(&self) -> u32 {
+        u32::from_le_bytes(self.blank[..CHECKSUM_SIZE].try_into().unwrap())
+    }
+
+    fn update_checksum(&mut self) {
+        if Self::has_checksum() {
+            let checksum = self.compute_checksum();
+            self.blank[..CHECKSUM_SIZE].copy_from_slice(&checksum.to_le_bytes());
+        }
+    }
+
+    // False for a page that never went through init, e.g. a zeroed one, whatever K and V are.
+    pub fn is_initialized(&self) -> bool {
+        self.page_type == BUCKET_PAGE_TYPE
+    }
+
+    // Always true in release builds.
+    pub fn checksum_ok(&self) -> bool {
+        !Self::has_checksum()
+            || self.stored_checksum() == 0
+            || self.stored_checksum() == self.compute_checksum()
+    }
+
+    pub fn key_at(&self, index: usize) -> K {
+        self.kvs[index].0
+    }
+
+    pub fn value_at(&self, index: usize) -> V {
+        self.kvs[index].1
+    }
+
+    // The latest logged change the bucket holds, written with it to disk. Recovery skips the
+    // records at or below it.
+    pub fn get_lsn(&self) -> Option<Lsn> {
+        u32::from_le_bytes(self.lsn).checked_sub(1).map(Lsn)
+    }
+
+    // Keeps the larger lsn.
+    pub fn set_lsn(&mut self, lsn: Lsn) {
+        if self.get_lsn() < Some(lsn) {
+            self.lsn = (lsn.0 + 1).to_le_bytes();
+        }
+    }
+
+    // Overwrites the value of an occupied slot in place.
+    pub fn set_value_at(&mut self, index: usize, value: &V) {
+        assert!(self.is_readable(index), "slot {} is empty", index);
+        self.kvs[index].1 = *value;
+        self.update_checksum();
+    }
+
+    pub fn num_readable(&self) -> usize {
+        self.readable
+            .iter()
+            .map(|byte| byte.count_ones() as usize)
+            .sum()
+    }
+
     pub fn get_value(&self, key: &K) -> Vec<V> {
+        self.get_value_with_slots(key)
+            .into_iter()
+            .map(|(_, value)| value)
+            .collect()
+    }
+
+    // (slot, value) of every entry with the key
+    pub fn get_value_with_slots(&self, key: &K) -> Vec<(usize, V)> {
         let mut result = Vec::new();
         for i in 0..Tool::<K, V>::KV_NUM {
             if self.is_readable(i) && self.kvs[i].0 == *key {
-                result.push(self.kvs[i].1);
+                result.push((i, self.kvs[i].1));
             }
         }
         result
     }
 
     pub fn insert(&mut self, key: &K, value: &V) -> InertResult {
-        let mut is_full = true;
-        let mut first_empty_index = 0;
-        for i in 0..Tool::<K, V>::KV_NUM {
-            if self.is_readable(i) {
-                if self.kvs[i].0 == *key && self.kvs[i].1 == *value {
+        // only occupied slots can hold a duplicate, skip empty bytes of the bitmap
+        for (byte_index, byte) in self.readable.iter().enumerate() {
+            if *byte == 0 {
+                continue;
+            }
+            for i in byte_index * 8..byte_index * 8 + 8 {
+                if self.is_readable(i) && self.kvs[i] == (*key, *value) {
                     return InertResult::Duplicate;
                 }
-            } else if is_full {
-                is_full = false;
-                first_empty_index = i;
             }
         }
-        if is_full {
+        let Some(first_empty_index) = self.first_empty_slot() else {
             return InertResult::Full;
-        }
+        };
+        self.free_hint = (first_empty_index / 8).min(u8::MAX as usize) as u8;
         self.kvs[first_empty_index] = (*key, *value);
         self.readable[first_empty_index / 8] |= 1 << (first_empty_index % 8);
+        self.update_checksum();
         InertResult::Success
     }
 
+    // Starts at free_hint rather than slot 0.
+    fn first_empty_slot(&self) -> Option<usize> {
+        let byte_index = (self.free_hint as usize..Tool::<K, V>::BYTE_NUM)
+            .find(|byte_index| self.readable[*byte_index] != u8::MAX)?;
+        Some(byte_index * 8 + self.readable[byte_index].trailing_ones() as usize)
+    }
+
     pub fn remove(&mut self, key: &K, value: &V) -> bool {
         for i in 0..Tool::<K, V>::KV_NUM {
             if self.is_readable(i) && self.kvs[i].0 == *key && self.kvs[i].1 == *value {
-                self.readable[i / 8] &= !(1 << (i % 8));
+                self.remove_at(i);
                 return true;
             }
         }
         false
     }
+
+    // Calls f on every entry, in slot order.
+    pub fn for_each(&self, mut f: impl FnMut(&K, &V)) {
+        for i in Self::readable_slots(self.readable) {
+            let (key, value) = &self.kvs[i];
+            f(key, value);
+        }
+    }
+
+    // Like for_each, but f may change values in place. Keys can't change, a different key may
+    // belong in a different bucket.
+    pub fn for_each_mut(&mut self, mut f: impl FnMut(&K, &mut V)) {
+        for i in Self::readable_slots(self.readable) {
+            let (key, value) = &mut self.kvs[i];
+            f(key, value);
+        }
+        self.update_checksum();
+    }
+
+    // Takes a copy of the bitmap so the slots can be written while iterating. Empty bytes are
+    // skipped, and bits past KV_NUM in the last byte are ignored.
+    fn readable_slots(readable: [u8; Tool::<K, V>::BYTE_NUM]) -> impl Iterator<Item = usize> {
+        readable
+            .into_iter()
+            .enumerate()
+            .filter(|(_, byte)| *byte != 0)
+            .flat_map(|(byte_index, byte)| {
+                (0..8)
+                    .filter(move |bit| byte & (1 << bit) != 0)
+                    .map(move |bit| byte_index * 8 + bit)
+            })
+            .filter(|i| *i < Tool::<K, V>::KV_NUM)
+    }
+
+    // Moves the entries to the lowest slots, keeping their order.
+    pub fn compact(&mut self) {
+        let mut len = 0;
+        for i in 0..Tool::<K, V>::KV_NUM {
+            if self.is_readable(i) {
+                self.kvs[len] = self.kvs[i];
+                len += 1;
+            }
+        }
+        self.readable = [0u8; Tool::<K, V>::BYTE_NUM];
+        for i in 0..len {
+            self.readable[i / 8] |= 1 << (i % 8);
+        }
+        self.free_hint = (len / 8).min(u8::MAX as usize) as u8;
+        self.update_checksum();
+    }
+
+    // Clears the slot without comparing its key and value. Returns false if it was empty.
+    pub fn remove_at(&mut self, index: usize) -> bool {
+        assert!(
+            index < Tool::<K, V>::KV_NUM,
+            "slot {} out of range, a bucket has {} slots",
+            index,
+            Tool::<K, V>::KV_NUM
+        );
+        let was_readable = self.is_readable(index);
+        self.readable[index / 8] &= !(1 << (index % 8));
+        if index / 8 < self.free_hint as usize {
+            self.free_hint = (index / 8) as u8;
+        }
+        self.update_checksum();
+        was_readable
+    }
 }
 
 #[cfg(test)]
@@ -123,6 +343,8 @@ mod test {
     use super::*;
     use crate::storage::pages::page::Page;
     use bytemuck::cast_mut;
+    use proptest::prelude::*;
+    use std::collections::HashMap;
     use std::mem::transmute;
 
     #[test]
@@ -141,4 +363,267 @@ mod test {
         let mut data = data.write().unwrap();
         let hash_table_bucket_page: &mut HashTableBucketPage<u64, u64> = cast_mut(&mut **data);
     }
+
+    type Bucket = HashTableBucketPage<u64, u64>;
+    const KV_NUM: usize = Tool::<u64, u64>::KV_NUM;
+
+    #[derive(Debug, Clone)]
+    enum Op {
+        Insert(u64, u64),
+        Remove(u64, u64),
+        Get(u64),
+    }
+
+    // A small key/value domain makes duplicates, misses and a full bucket likely.
+    fn op_strategy() -> impl Strategy<Value = Op> {
+        prop_oneof![
+            4 => (0..64u64, 0..8u64).prop_map(|(k, v)| Op::Insert(k, v)),
+            2 => (0..64u64, 0..8u64).prop_map(|(k, v)| Op::Remove(k, v)),
+            1 => (0..64u64).prop_map(Op::Get),
+        ]
+    }
+
+    // Applies ops to the bucket and to a reference map, asserting they agree after each step.
+    fn check_against_model(ops: &[Op]) {
+        let mut bucket = Bucket::new();
+        let mut model: HashMap<u64, Vec<u64>> = HashMap::new();
+        let mut len = 0;
+        for op in ops {
+            match *op {
+                Op::Insert(k, v) => {
+                    let values = model.entry(k).or_default();
+                    match bucket.insert(&k, &v) {
+                        InertResult::Success => {
+                            assert!(!values.contains(&v) && len < KV_NUM);
+                            values.push(v);
+                            len += 1;
+                        }
+                        InertResult::Duplicate => assert!(values.contains(&v)),
+                        InertResult::Full => assert!(!values.contains(&v) && len == KV_NUM),
+                    }
+                }
+                Op::Remove(k, v) => {
+                    let values = model.entry(k).or_default();
+                    let expected = values.iter().position(|x| *x == v);
+                    assert_eq!(bucket.remove(&k, &v), expected.is_some());
+                    if let Some(i) = expected {
+                        values.remove(i);
+                        len -= 1;
+                    }
+                }
+                Op::Get(k) => {
+                    let mut actual = bucket.get_value(&k);
+                    let mut expected = model.get(&k).cloned().unwrap_or_default();
+                    actual.sort();
+                    expected.sort();
+                    assert_eq!(actual, expected);
+                }
+            }
+            assert_eq!(bucket.num_readable(), len);
+        }
+    }
+
+    proptest! {
+        #[test]
+        fn bucket_matches_model(ops in prop::collection::vec(op_strategy(), 0..600)) {
+            check_against_model(&ops);
+        }
+    }
+
+    #[test]
+    fn bucket_fill_to_capacity() {
+        let ops: Vec<_> = (0..KV_NUM as u64 + 1)
+            .map(|i| Op::Insert(i, i))
+            .chain((0..KV_NUM as u64 + 1).map(Op::Get))
+            .collect();
+        check_against_model(&ops);
+    }
+
+    #[test]
+    fn bucket_remove_across_byte_boundaries() {
+        let mut ops: Vec<_> = (0..32u64).map(|i| Op::Insert(i, i)).collect();
+        for slot in [7u64, 8, 15, 16, 0, 31] {
+            ops.push(Op::Remove(slot, slot));
+            ops.push(Op::Get(slot));
+        }
+        // refill the freed slots, then fill up and overflow
+        ops.extend((0..KV_NUM as u64 + 8).map(|i| Op::Insert(i, i + 1)));
+        check_against_model(&ops);
+    }
+
+    #[test]
+    fn zeroed_page_is_empty_bucket() {
+        let page = Page::new();
+        let data = page.get_data();
+        let mut data = data.write().unwrap();
+        let cast: &mut Bucket = cast_mut(&mut **data);
+        let mut new = Bucket::new();
+        assert_eq!(cast.num_readable(), 0);
+        for i in 0..KV_NUM as u64 {
+            assert!(matches!(cast.insert(&i, &i), InertResult::Success));
+            assert!(matches!(new.insert(&i, &i), InertResult::Success));
+        }
+        assert!(matches!(cast.insert(&0, &1), InertResult::Full));
+        assert_eq!(bytemuck::bytes_of(cast), bytemuck::bytes_of(&new));
+    }
+
+    #[test]
+    fn get_value_with_slots_test() {
+        let mut bucket = Bucket::new();
+        for i in 0..10u64 {
+            bucket.insert(&(i % 3), &i);
+        }
+        let slots = bucket.get_value_with_slots(&1);
+        assert_eq!(slots, vec![(1, 1), (4, 4), (7, 7)]);
+        for (slot, value) in slots {
+            assert_eq!((bucket.key_at(slot), bucket.value_at(slot)), (1, value));
+        }
+    }
+
+    #[test]
+    fn remove_at_test() {
+        let mut bucket = Bucket::new();
+        for i in 0..20u64 {
+            bucket.insert(&i, &(i * 2));
+        }
+        let (slot, _) = bucket.get_value_with_slots(&13)[0];
+        assert!(bucket.remove_at(slot));
+        assert_eq!(bucket.num_readable(), 19);
+        assert!(bucket.get_value(&13).is_empty());
+        assert!(!bucket.remove_at(slot));
+        assert_eq!(bucket.num_readable(), 19);
+    }
+
+    #[test]
+    #[should_panic(expected = "out of range")]
+    fn remove_at_out_of_range_test() {
+        Bucket::new().remove_at(KV_NUM);
+    }
+
+    #[test]
+    fn compact_test() {
+        let mut bucket = Bucket::new();
+        for i in 0..40u64 {
+            bucket.insert(&(i % 7), &i);
+        }
+        for i in (0..40u64).step_by(2) {
+            assert!(bucket.remove(&(i % 7), &i));
+        }
+        let mut before: Vec<_> = (0..7).flat_map(|k| bucket.get_value(&k)).collect();
+        bucket.compact();
+        let mut after: Vec<_> = (0..7).flat_map(|k| bucket.get_value(&k)).collect();
+        before.sort();
+        after.sort();
+        assert_eq!(before, after);
+        assert_eq!(bucket.num_readable(), 20);
+        assert!((0..KV_NUM).all(|i| bucket.is_readable(i) == (i < 20)));
+        for i in 0..20 {
+            assert_eq!(bucket.value_at(i), 2 * i as u64 + 1);
+        }
+    }
+
+    #[test]
+    fn free_hint_test() {
+        let mut bucket = Bucket::new();
+        for i in 0..100u64 {
+            bucket.insert(&i, &i);
+        }
+        // the first 12 bytes are full and don't need to be looked at again
+        assert_eq!(bucket.free_hint, 12);
+        assert_eq!(bucket.first_empty_slot(), Some(100));
+
+        bucket.remove(&42, &42);
+        assert_eq!(bucket.free_hint, 5);
+        assert_eq!(bucket.first_empty_slot(), Some(42));
+        bucket.insert(&1000, &1000);
+        assert_eq!(bucket.get_value_with_slots(&1000), vec![(42, 1000)]);
+        assert_eq!(bucket.first_empty_slot(), Some(100));
+        assert!(matches!(
+            bucket.insert(&1000, &1000),
+            InertResult::Duplicate
+        ));
+    }
+
+    #[test]
+    fn for_each_test() {
+        let mut bucket = Bucket::new();
+        for i in 0..KV_NUM as u64 {
+            bucket.insert(&i, &(i + 1));
+        }
+        for i in (0..KV_NUM as u64).step_by(3) {
+            bucket.remove(&i, &(i + 1));
+        }
+        bucket.for_each_mut(|_, value| *value *= 2);
+        let mut visited = Vec::new();
+        bucket.for_each(|key, value| visited.push((*key, *value)));
+        assert_eq!(visited.len(), bucket.num_readable());
+        for i in 0..KV_NUM as u64 {
+            let expected = if i % 3 == 0 {
+                vec![]
+            } else {
+                vec![2 * (i + 1)]
+            };
+            assert_eq!(bucket.get_value(&i), expected);
+        }
+    }
+
+    #[test]
+    fn looks_valid_test() {
+        let mut bucket = Bucket::new();
+        assert!(bucket.looks_valid());
+        for i in 0..20u64 {
+            bucket.insert(&i, &i);
+        }
+        assert!(bucket.looks_valid());
+        bucket.blank[CHECKSUM_SIZE] = 1;
+        assert!(!bucket.looks_valid());
+        bucket.blank[CHECKSUM_SIZE] = 0;
+        bucket.free_hint = 3;
+        assert!(!bucket.looks_valid());
+    }
+
+    #[test]
+    fn checksum_test() {
+        let mut bucket = Bucket::new();
+        assert!(bucket.checksum_ok());
+        for i in 0..20u64 {
+            bucket.insert(&i, &i);
+        }
+        bucket.remove(&3, &3);
+        bucket.for_each_mut(|_, value| *value += 1);
+        assert!(bucket.checksum_ok());
+        // a write that goes around the bucket
+        bucket.kvs[5].1 = 1000;
+        assert_eq!(bucket.checksum_ok(), !cfg!(debug_assertions));
+    }
+
+    #[test]
+    fn is_initialized_test() {
+        let mut bucket = Bucket::new();
+        assert!(!bucket.is_initialized());
+        bucket.init(PageId(1));
+        assert!(bucket.is_initialized());
+        assert!(bucket.checksum_ok());
+        // the tag doesn't depend on the checksum, nor on K and V
+        let mut small = HashTableBucketPage::<i32, i32>::new();
+        assert!(!small.is_initialized());
+        small.insert(&1, &1);
+        assert!(!small.is_initialized());
+        small.init(PageId(2));
+        assert!(small.is_initialized());
+    }
+
+    #[test]
+    fn lsn_test() {
+        let mut bucket = Bucket::new();
+        assert_eq!(bucket.get_lsn(), None);
+        bucket.set_lsn(Lsn(0));
+        assert_eq!(bucket.get_lsn(), Some(Lsn(0)));
+        bucket.set_lsn(Lsn(7));
+        bucket.set_lsn(Lsn(3));
+        assert_eq!(bucket.get_lsn(), Some(Lsn(7)));
+        // the lsn isn't an entry
+        assert_eq!(bucket.num_readable(), 0);
+        assert!(bucket.checksum_ok());
+    }
 }
@@ -1,11 +1,16 @@
+use std::collections::HashMap;
 use std::mem::size_of;
 use bytemuck::{Pod, Zeroable};
 use crate::buffer::replacer::PageId;
 use crate::storage::disk::disk_manager::PAGE_SIZE;
+use crate::storage::pages::page::InitPage;
 
 const DIRECTORY_ARRAY_SIZE: usize = 512;
 
-const BLANK_SIZE: usize = PAGE_SIZE - size_of::<PageId>() - size_of::<u32>() - size_of::<u8>() * DIRECTORY_ARRAY_SIZE - size_of::<PageId>() * DIRECTORY_ARRAY_SIZE;
+// the global depth at which the directory is full
+pub const MAX_GLOBAL_DEPTH: u32 = DIRECTORY_ARRAY_SIZE.trailing_zeros();
+
+const BLANK_SIZE: usize = PAGE_SIZE - size_of::<PageId>() - size_of::<u32>() - size_of::<u8>() * DIRECTORY_ARRAY_SIZE - size_of::<PageId>() * DIRECTORY_ARRAY_SIZE - size_of::<u32>();
 
 #[derive(Debug, Clone, Copy)]
 pub struct HashTableDirectoryPage {
@@ -17,6 +22,8 @@ pub struct HashTableDirectoryPage {
     // 512 bytes
     page_ids: [PageId; DIRECTORY_ARRAY_SIZE],
     // 4 * 512 = 2048 bytes
+    seq: u32,
+    // 4 byte, odd while a split is rewriting the directory
     blank: [u8; BLANK_SIZE],
 }
 
@@ -24,6 +31,12 @@ unsafe impl Zeroable for HashTableDirectoryPage {}
 
 unsafe impl Pod for HashTableDirectoryPage {}
 
+// The rest of a zeroed page is a directory of depth 0 pointing at bucket page 0.
+impl InitPage for HashTableDirectoryPage {
+    fn init(&mut self, page_id: PageId) {
+        self.page_id = page_id;
+    }
+}
 
 impl HashTableDirectoryPage {
     pub fn get_page_id(&self) -> PageId {
@@ -31,6 +44,15 @@ impl HashTableDirectoryPage {
     }
 
 
+    pub fn get_seq(&self) -> u32 {
+        self.seq
+    }
+
+    // Called before and after every structural change.
+    pub fn increase_seq(&mut self) {
+        self.seq = self.seq.wrapping_add(1);
+    }
+
     pub fn get_global_depth(&self) -> u32 {
         self.global_depth
     }
@@ -59,13 +81,91 @@ impl HashTableDirectoryPage {
         self.page_ids[index] = page_id;
     }
 
+    // Points the slot at a bucket together with the bucket's local depth, so the two never
+    // disagree.
+    pub fn set_bucket(&mut self, index: usize, page_id: PageId, local_depth: u8) {
+        self.page_ids[index] = page_id;
+        self.local_depth[index] = local_depth;
+    }
+
+    // Exchanges the buckets, with their local depths, of two slots.
+    pub fn swap_bucket(&mut self, index_a: usize, index_b: usize) {
+        self.page_ids.swap(index_a, index_b);
+        self.local_depth.swap(index_a, index_b);
+    }
+
     pub fn increase_global_depth(&mut self) {
+        assert!(self.can_grow(), "directory is full");
         self.global_depth += 1;
     }
 
     pub fn increase_local_depth(&mut self, bucket_index: usize) {
         self.local_depth[bucket_index] += 1;
     }
+
+    // number of directory slots in use
+    pub fn size(&self) -> usize {
+        1 << self.global_depth
+    }
+
+    // whether there is room to double the directory
+    pub fn can_grow(&self) -> bool {
+        self.size() * 2 <= DIRECTORY_ARRAY_SIZE
+    }
+
+    // Doubles the directory. Slot i + size mirrors slot i, so every bucket keeps its local depth
+    // and is referenced twice as often.
+    pub fn grow(&mut self) {
+        let num_buckets_before = self.size();
+        self.increase_global_depth();
+        for i in 0..num_buckets_before {
+            self.page_ids[num_buckets_before + i] = self.page_ids[i];
+            self.local_depth[num_buckets_before + i] = self.local_depth[i];
+        }
+    }
+
+    // The directory can be halved once no bucket needs the top bit of the index.
+    pub fn can_shrink(&self) -> bool {
+        self.global_depth > 0
+            && self.local_depth[..self.size()]
+                .iter()
+                .all(|local_depth| (*local_depth as u32) < self.global_depth)
+    }
+
+    // Halves the directory. The lower half is kept as is and the upper half just becomes
+    // unaddressable until the next grow overwrites it.
+    pub fn shrink(&mut self) {
+        assert!(self.can_shrink(), "directory can't shrink");
+        self.global_depth -= 1;
+    }
+
+    // Panics unless every local depth is at most the global depth, every bucket is referenced
+    // by exactly 2^(global depth - local depth) slots, and all slots of a bucket agree on its
+    // local depth.
+    pub fn verify_integrity(&self) {
+        let mut buckets: HashMap<PageId, (u8, usize)> = HashMap::new();
+        for i in 0..self.size() {
+            let local_depth = self.local_depth[i];
+            assert!(
+                local_depth as u32 <= self.global_depth,
+                "local depth {} of slot {} exceeds global depth {}",
+                local_depth,
+                i,
+                self.global_depth
+            );
+            let (depth, count) = buckets.entry(self.page_ids[i]).or_insert((local_depth, 0));
+            assert_eq!(*depth, local_depth, "slots of bucket {:?} disagree on local depth", self.page_ids[i]);
+            *count += 1;
+        }
+        for (page_id, (local_depth, count)) in buckets {
+            assert_eq!(
+                count,
+                1 << (self.global_depth - local_depth as u32),
+                "bucket {:?} is referenced by the wrong number of slots",
+                page_id
+            );
+        }
+    }
 }
 
 #[cfg(test)]
@@ -121,4 +221,72 @@ mod test {
         //     assert_eq!(hash_table_directory_page_1.get_bucket_page_id(0), PageId(2));
         // }
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn grow_shrink_test() {
+        let mut dir: HashTableDirectoryPage = Zeroable::zeroed();
+        assert!(!dir.can_shrink());
+        dir.grow();
+        dir.set_bucket_page_id(1, PageId(1));
+        dir.set_local_depth(0, 1);
+        dir.set_local_depth(1, 1);
+        let lower_page_ids = dir.page_ids;
+        let lower_local_depth = dir.local_depth;
+
+        dir.grow();
+        assert_eq!(dir.size(), 4);
+        assert_eq!(dir.get_bucket_page_id(2), PageId(0));
+        assert_eq!(dir.get_bucket_page_id(3), PageId(1));
+        assert_eq!(dir.get_local_depth(3), 1);
+        dir.verify_integrity();
+        assert!(dir.can_shrink());
+
+        dir.shrink();
+        assert_eq!(dir.get_global_depth(), 1);
+        assert_eq!(dir.page_ids[..2], lower_page_ids[..2]);
+        assert_eq!(dir.local_depth[..2], lower_local_depth[..2]);
+        dir.verify_integrity();
+        // both buckets use bit 0
+        assert!(!dir.can_shrink());
+    }
+
+    #[test]
+    fn zeroed_page_init_test() {
+        let page = Page::new();
+        let data = page.get_data();
+        let mut data = data.write().unwrap();
+        let dir: &mut HashTableDirectoryPage = cast_mut(&mut data.0);
+        dir.init(PageId(3));
+        assert_eq!(dir.get_page_id(), PageId(3));
+        assert_eq!(dir.get_global_depth(), 0);
+        assert_eq!(dir.get_seq(), 0);
+        assert_eq!(dir.size(), 1);
+        assert_eq!(dir.get_local_depth(0), 0);
+        assert_eq!(dir.get_bucket_page_id(0), PageId(0));
+        dir.verify_integrity();
+    }
+
+    #[test]
+    fn set_swap_bucket_test() {
+        let mut dir: HashTableDirectoryPage = Zeroable::zeroed();
+        dir.grow();
+        dir.set_bucket(1, PageId(1), 1);
+        dir.set_bucket(0, PageId(0), 1);
+        dir.verify_integrity();
+        dir.grow();
+        // split bucket 1 into slots 1 and 3
+        dir.set_bucket(1, PageId(1), 2);
+        dir.set_bucket(3, PageId(3), 2);
+        dir.verify_integrity();
+
+        dir.swap_bucket(1, 3);
+        assert_eq!(dir.get_bucket_page_id(1), PageId(3));
+        assert_eq!(dir.get_local_depth(1), 2);
+        dir.verify_integrity();
+        // slot 2 shares bucket 0 with slot 0, so the depths travel with the page ids
+        dir.swap_bucket(2, 3);
+        assert_eq!((dir.get_bucket_page_id(2), dir.get_local_depth(2)), (PageId(1), 2));
+        assert_eq!((dir.get_bucket_page_id(3), dir.get_local_depth(3)), (PageId(0), 1));
+        dir.verify_integrity();
+    }
+}
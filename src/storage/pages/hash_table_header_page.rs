@@ -0,0 +1,77 @@
+use crate::buffer::replacer::PageId;
+use crate::storage::disk::disk_manager::PAGE_SIZE;
+use crate::storage::pages::page::InitPage;
+use bytemuck::{Pod, Zeroable};
+use std::mem::{offset_of, size_of};
+use std::ops::Range;
+
+// Bumped whenever the layout of the table's pages changes.
+pub const HEADER_VERSION: u32 = 3;
+
+// where the entry count sits in the page, for marking just it dirty
+pub const COUNT_RANGE: Range<usize> =
+    offset_of!(HashTableHeaderPage, count)..offset_of!(HashTableHeaderPage, count) + 8;
+
+const BLANK_SIZE: usize =
+    PAGE_SIZE - size_of::<PageId>() * 2 - size_of::<u32>() * 2 - size_of::<u64>();
+
+// The page a table is known by: where its directory is, plus what the table wants to know
+// about itself without scanning the buckets.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct HashTableHeaderPage {
+    page_id: PageId,
+    version: u32,
+    dir_page_id: PageId,
+    // 1 if a key may only hold one value. Nothing sets it yet, tables allow several values
+    // per key. A u32 so there is no padding before count.
+    unique_keys: u32,
+    // (key, value) pairs in the table, changed under the latch of the bucket that changed
+    count: u64,
+    blank: [u8; BLANK_SIZE],
+}
+
+unsafe impl Zeroable for HashTableHeaderPage {}
+
+unsafe impl Pod for HashTableHeaderPage {}
+
+impl InitPage for HashTableHeaderPage {
+    fn init(&mut self, page_id: PageId) {
+        self.page_id = page_id;
+        self.version = HEADER_VERSION;
+    }
+}
+
+impl HashTableHeaderPage {
+    pub fn get_page_id(&self) -> PageId {
+        self.page_id
+    }
+
+    pub fn get_version(&self) -> u32 {
+        self.version
+    }
+
+    pub fn get_dir_page_id(&self) -> PageId {
+        self.dir_page_id
+    }
+
+    pub fn set_dir_page_id(&mut self, dir_page_id: PageId) {
+        self.dir_page_id = dir_page_id;
+    }
+
+    pub fn is_unique(&self) -> bool {
+        self.unique_keys != 0
+    }
+
+    pub fn get_count(&self) -> u64 {
+        self.count
+    }
+
+    // delta is negative for removals
+    pub fn add_count(&mut self, delta: i64) {
+        self.count = self
+            .count
+            .checked_add_signed(delta)
+            .expect("entry count went negative");
+    }
+}
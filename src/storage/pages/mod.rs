@@ -1,3 +1,4 @@
 pub mod page;
 pub mod hash_table_directory_page;
 pub mod hash_table_bucket_page;
+pub mod hash_table_header_page;
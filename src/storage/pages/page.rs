@@ -1,20 +1,33 @@
 use std::fmt::{Debug, Formatter};
+use std::ops::Range;
 use std::ops::{Deref, DerefMut};
 use std::sync::{Arc, Mutex, RwLock};
+use crate::buffer::frame_arena::{FrameArena, FrameData};
 use crate::buffer::replacer::PageId;
+use crate::recovery::log_record::Lsn;
 use crate::storage::disk::disk_manager::PAGE_SIZE;
 
-pub type Data = Arc<RwLock<Align4096>>;
+pub type Data = FrameData;
+
+// Sets up the header of a freshly allocated page of a given type. Pages handed out by new_page
+// have just been through reset_data, so init only needs to write the fields that aren't zero.
+pub trait InitPage {
+    fn init(&mut self, page_id: PageId);
+}
 
 #[derive(Clone)]
 pub struct Page {
-    data: Arc<RwLock<Align4096>>,
+    data: Data,
     page_id: Option<PageId>,
     is_dirty: bool,
+    // the part of a dirty page that changed, None if it may be anywhere
+    dirty_range: Option<Range<usize>>,
     pin_count: usize,
+    // latest log record that changed the page since it was read, if any
+    lsn: Option<Lsn>,
 }
 
-#[repr(align(8))]
+#[repr(align(4096))]
 pub struct Align4096(pub(crate) [u8; PAGE_SIZE]);
 
 impl Deref for Align4096 {
@@ -31,13 +44,26 @@ impl DerefMut for Align4096 {
     }
 }
 
+impl Default for Page {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Page {
+    // A page with a buffer of its own.
     pub fn new() -> Self {
+        Self::in_arena(&FrameArena::new(1), 0)
+    }
+
+    pub fn in_arena(arena: &Arc<FrameArena>, index: usize) -> Self {
         Page {
-            data: Arc::new(RwLock::new(Align4096([0u8; PAGE_SIZE]))),
+            data: arena.frame(index),
             page_id: None,
             is_dirty: false,
+            dirty_range: None,
             pin_count: 0,
+            lsn: None,
         }
     }
 
@@ -45,7 +71,7 @@ impl Page {
         self.page_id
     }
 
-    pub fn get_data(&self) -> Arc<RwLock<Align4096>> {
+    pub fn get_data(&self) -> Data {
         self.data.clone()
     }
 
@@ -57,12 +83,41 @@ impl Page {
         self.pin_count
     }
 
+    // The frame now holds another page, which starts without an lsn.
     pub fn set_page_id(&mut self, page_id: PageId) {
         self.page_id = Some(page_id);
+        self.lsn = None;
+    }
+
+    pub fn get_lsn(&self) -> Option<Lsn> {
+        self.lsn
     }
 
+    // Keeps the larger lsn.
+    pub fn set_lsn(&mut self, lsn: Lsn) {
+        self.lsn = self.lsn.max(Some(lsn));
+    }
+
+    // Dirty means the whole page may have changed.
     pub fn set_is_dirty(&mut self, is_dirty: bool) {
         self.is_dirty = is_dirty;
+        self.dirty_range = None;
+    }
+
+    // Marks bytes lo..hi as changed, widening the dirty range of a page that is already dirty
+    // in part. A page that is dirty as a whole stays that way.
+    pub fn mark_dirty_range(&mut self, lo: usize, hi: usize) {
+        assert!(lo <= hi && hi <= PAGE_SIZE, "bad dirty range {}..{}", lo, hi);
+        self.dirty_range = match (self.is_dirty, self.dirty_range.take()) {
+            (false, _) => Some(lo..hi),
+            (true, Some(range)) => Some(range.start.min(lo)..range.end.max(hi)),
+            (true, None) => None,
+        };
+        self.is_dirty = true;
+    }
+
+    pub fn get_dirty_range(&self) -> Option<Range<usize>> {
+        self.dirty_range.clone()
     }
 
     pub fn set_pin_count(&mut self, pin_count: usize) {
@@ -77,7 +132,17 @@ impl Page {
         self.pin_count -= 1;
     }
 
+    // Back to holding no page, for a frame going on the free list. The buffer is left as is.
+    pub fn reset(&mut self) {
+        self.page_id = None;
+        self.is_dirty = false;
+        self.dirty_range = None;
+        self.pin_count = 0;
+        self.lsn = None;
+    }
+
+    // Zeroes the buffer in place, it stays in the same spot of the arena.
     pub fn reset_data(&mut self) {
-        self.data = Arc::new(RwLock::new(Align4096([0u8; PAGE_SIZE])));
+        self.data.write().unwrap().fill(0);
     }
 }